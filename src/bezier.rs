@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::fs;
+
+use crate::math::{Point3D, Vector3D};
+use crate::mesh::Mesh;
+
+// bounds on the number of segments a patch is cut into along each direction
+const MIN_SEGMENTS: usize = 1;
+const MAX_SEGMENTS: usize = 64;
+
+fn bernstein(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+fn bernstein_derivative(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [-3.0 * s * s, 3.0 * s * s - 6.0 * t * s, 6.0 * t * s - 3.0 * t * t, 3.0 * t * t]
+}
+
+// bicubic patch, the 16 control points are stored row by row with u varying fastest
+#[derive(Copy, Clone, Debug)]
+pub struct BezierPatch {
+    pub points: [Point3D; 16],
+}
+
+impl BezierPatch {
+    pub fn new(points: [Point3D; 16]) -> BezierPatch {
+        BezierPatch { points }
+    }
+
+    fn control(&self, u: usize, v: usize) -> Vector3D {
+        self.points[v * 4 + u] - Point3D::default()
+    }
+
+    fn combine(&self, weights_u: [f64; 4], weights_v: [f64; 4]) -> Vector3D {
+        let mut sum = Vector3D::default();
+        for (v, weight_v) in weights_v.iter().enumerate() {
+            for (u, weight_u) in weights_u.iter().enumerate() {
+                sum += self.control(u, v) * (weight_u * weight_v);
+            }
+        }
+        sum
+    }
+
+    pub fn evaluate(&self, u: f64, v: f64) -> Point3D {
+        Point3D::default() + self.combine(bernstein(u), bernstein(v))
+    }
+
+    pub fn normal(&self, u: f64, v: f64) -> Vector3D {
+        let tangent_u = self.combine(bernstein_derivative(u), bernstein(v));
+        let tangent_v = self.combine(bernstein(u), bernstein_derivative(v));
+        let normal = tangent_u.cross(tangent_v);
+        if normal.length() > 1e-12 {
+            return normal.normalize();
+        }
+        // collapsed edges such as the tip of the teapot lid, the normal is taken slightly inside the patch
+        let nudge = |t: f64| t + (0.5 - t) * 1e-3;
+        let tangent_u = self.combine(bernstein_derivative(nudge(u)), bernstein(nudge(v)));
+        let tangent_v = self.combine(bernstein(nudge(u)), bernstein_derivative(nudge(v)));
+        tangent_u.cross(tangent_v).normalize()
+    }
+
+    // segments along u and v so the flat triangles stay within `tolerance` of the surface,
+    // from the bound on the error of a cubic against its chords: 3/4 of the largest second difference over n²
+    fn segments(&self, tolerance: f64) -> (usize, usize) {
+        let mut second_u: f64 = 0.0;
+        let mut second_v: f64 = 0.0;
+        for row in 0..4 {
+            for i in 0..2 {
+                let along_u = self.control(i, row) - self.control(i + 1, row) * 2.0 + self.control(i + 2, row);
+                let along_v = self.control(row, i) - self.control(row, i + 1) * 2.0 + self.control(row, i + 2);
+                second_u = second_u.max(along_u.length());
+                second_v = second_v.max(along_v.length());
+            }
+        }
+        let count = |second: f64| {
+            ((0.75 * second / tolerance).sqrt().ceil() as usize).clamp(MIN_SEGMENTS, MAX_SEGMENTS)
+        };
+        (count(second_u), count(second_v))
+    }
+}
+
+// patch file made of a patch count followed by, for each patch, its degrees "3 3" and 16 "x y z" lines
+pub fn read_bpt(path: &str) -> Result<Vec<BezierPatch>, Box<dyn Error>> {
+    parse_bpt(&fs::read_to_string(path)?)
+}
+
+pub fn parse_bpt(text: &str) -> Result<Vec<BezierPatch>, Box<dyn Error>> {
+    let mut tokens = text.split_whitespace();
+    let mut next = || -> Result<f64, Box<dyn Error>> {
+        Ok(tokens.next().ok_or("Unexpected end of BPT file")?.parse::<f64>()?)
+    };
+    let count = next()? as usize;
+    let mut patches = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (degree_u, degree_v) = (next()?, next()?);
+        if degree_u != 3.0 || degree_v != 3.0 {
+            return Err(format!("Only bicubic patches are supported, found degrees {} {}", degree_u, degree_v).into());
+        }
+        let mut points = [Point3D::default(); 16];
+        for point in points.iter_mut() {
+            *point = Point3D::new(next()?, next()?, next()?);
+        }
+        patches.push(BezierPatch::new(points));
+    }
+    Ok(patches)
+}
+
+// cuts every patch into a grid of triangles with the exact surface normals at the vertices
+// patch files do not agree on which side is outside, so the normals are turned away from the center of the
+// set, `flip_normals` turns them back for open sheets where that guess is wrong
+pub fn tessellate(patches: &[BezierPatch], tolerance: f64, flip_normals: bool, offset: Vector3D, color: Vector3D) -> Mesh {
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut triangles = Vec::new();
+    for patch in patches {
+        let (segments_u, segments_v) = patch.segments(tolerance);
+        let first = vertices.len();
+        for j in 0..=segments_v {
+            for i in 0..=segments_u {
+                let (u, v) = (i as f64 / segments_u as f64, j as f64 / segments_v as f64);
+                vertices.push(patch.evaluate(u, v) + offset);
+                normals.push(patch.normal(u, v));
+            }
+        }
+        let index = |i: usize, j: usize| first + j * (segments_u + 1) + i;
+        for j in 0..segments_v {
+            for i in 0..segments_u {
+                triangles.push([index(i, j), index(i + 1, j), index(i + 1, j + 1)]);
+                triangles.push([index(i, j), index(i + 1, j + 1), index(i, j + 1)]);
+            }
+        }
+    }
+    let center = vertices.iter().fold(Vector3D::default(), |sum, vertex| sum + (*vertex - Point3D::default()))
+        / vertices.len().max(1) as f64;
+    let outward: f64 = vertices
+        .iter()
+        .zip(&normals)
+        .map(|(vertex, normal)| (*vertex - (Point3D::default() + center)).dot(normal))
+        .sum();
+    if (outward < 0.0) != flip_normals {
+        for normal in normals.iter_mut() {
+            *normal *= -1.0;
+        }
+        for triangle in triangles.iter_mut() {
+            triangle.swap(1, 2);
+        }
+    }
+    // collapsed edges produce triangles without area, they can never be hit
+    triangles.retain(|[a, b, c]| (vertices[*b] - vertices[*a]).cross(vertices[*c] - vertices[*a]).length() > 1e-12);
+    Mesh::new(vertices, Some(normals), None, triangles, color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+    use crate::raytracer::Ray;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    // a 3 by 3 patch over the XZ plane, with its inner control points raised to `bulge`
+    fn patch(bulge: f64) -> BezierPatch {
+        let mut points = [Point3D::default(); 16];
+        for (index, point) in points.iter_mut().enumerate() {
+            let (i, j) = (index % 4, index / 4);
+            let inner = (1..3).contains(&i) && (1..3).contains(&j);
+            *point = Point3D::new(i as f64, if inner { bulge } else { 0.0 }, j as f64);
+        }
+        BezierPatch::new(points)
+    }
+
+    fn bpt_text(patch: &BezierPatch) -> String {
+        let mut text = String::from("1\n3 3\n");
+        for point in patch.points {
+            text += &format!("{} {} {}\n", point.x, point.y, point.z);
+        }
+        text
+    }
+
+    #[test]
+    fn reads_patch_files() {
+        let patches = parse_bpt(&bpt_text(&patch(1.0))).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].points[5], Point3D::new(1.0, 1.0, 1.0));
+        assert!(parse_bpt("1\n3 3\n0 0 0\n").is_err());
+        assert!(parse_bpt("1\n2 2\n").is_err());
+        assert!(parse_bpt("one").is_err());
+    }
+
+    #[test]
+    fn corners_and_normals() {
+        let flat = patch(0.0);
+        assert_eq!(flat.evaluate(0.0, 0.0), Point3D::new(0.0, 0.0, 0.0));
+        assert_eq!(flat.evaluate(1.0, 1.0), Point3D::new(3.0, 0.0, 3.0));
+        let normal = flat.normal(0.5, 0.5);
+        assert!((normal.y.abs() - 1.0).abs() < 1e-9);
+        // the inner control points weigh 3/4 along each direction in the middle
+        assert!((patch(1.0).evaluate(0.5, 0.5).y - 0.5625).abs() < 1e-9);
+    }
+
+    #[test]
+    fn curved_patches_get_more_segments() {
+        assert_eq!(patch(0.0).segments(0.01), (MIN_SEGMENTS, MIN_SEGMENTS));
+        let (coarse, _) = patch(1.0).segments(0.1);
+        let (fine, _) = patch(1.0).segments(0.001);
+        assert!(coarse > MIN_SEGMENTS && fine > coarse && fine <= MAX_SEGMENTS);
+    }
+
+    #[test]
+    fn tessellated_patch_faces_away_from_its_center() {
+        let mesh = tessellate(&[patch(1.0)], 0.001, false, Vector3D::default(), WHITE);
+        let ray = Ray::new(Point3D::new(1.5, 5.0, 1.5), Vector3D::new(0.0, -1.0, 0.0));
+        let hit = mesh.hits(ray).unwrap();
+        assert!((hit.y - 0.5625).abs() < 1e-3, "{:?}", hit);
+        assert!(mesh.surface_normal(&hit).y > 0.0);
+        let flipped = tessellate(&[patch(1.0)], 0.001, true, Vector3D::default(), WHITE);
+        assert!(flipped.surface_normal(&hit).y < 0.0);
+    }
+}
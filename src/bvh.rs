@@ -0,0 +1,254 @@
+use crate::math::{Matrix4, Point3D, Vector3D};
+use crate::raytracer::Ray;
+
+const LEAF_SIZE: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Point3D,
+    pub max: Point3D,
+}
+
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb {
+            min: Point3D::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point3D::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+}
+
+impl Aabb {
+    pub fn from_points(points: &[Point3D]) -> Aabb {
+        let mut bounds = Aabb::default();
+        for point in points {
+            bounds.grow(point);
+        }
+        bounds
+    }
+    pub fn infinite() -> Aabb {
+        Aabb {
+            min: Point3D::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            max: Point3D::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        }
+    }
+    pub fn is_finite(&self) -> bool {
+        [self.min.x, self.min.y, self.min.z, self.max.x, self.max.y, self.max.z]
+            .iter()
+            .all(|value| value.is_finite())
+    }
+    // box around the eight transformed corners
+    pub fn transformed(&self, matrix: &Matrix4) -> Aabb {
+        if !self.is_finite() {
+            return Aabb::infinite();
+        }
+        let corners: Vec<Point3D> = (0..8)
+            .map(|corner| {
+                matrix.transform_point(&Point3D::new(
+                    if corner & 1 == 0 { self.min.x } else { self.max.x },
+                    if corner & 2 == 0 { self.min.y } else { self.max.y },
+                    if corner & 4 == 0 { self.min.z } else { self.max.z },
+                ))
+            })
+            .collect();
+        Aabb::from_points(&corners)
+    }
+    pub fn grow(&mut self, point: &Point3D) {
+        self.min = Point3D::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+        self.max = Point3D::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+    }
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut bounds = *self;
+        bounds.grow(&other.min);
+        bounds.grow(&other.max);
+        bounds
+    }
+    pub fn centroid(&self) -> Point3D {
+        (self.min + self.max) * 0.5
+    }
+    pub fn extent(&self) -> Vector3D {
+        self.max - self.min
+    }
+    pub fn contains(&self, point: &Point3D, epsilon: f64) -> bool {
+        point.x >= self.min.x - epsilon && point.x <= self.max.x + epsilon
+            && point.y >= self.min.y - epsilon && point.y <= self.max.y + epsilon
+            && point.z >= self.min.z - epsilon && point.z <= self.max.z + epsilon
+    }
+    // slab test, returns the entry and exit distances along the ray
+    pub fn hit_range(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut t_min = f64::NEG_INFINITY;
+        let mut t_max = f64::INFINITY;
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for (origin, direction, min, max) in axes {
+            let inv = 1.0 / direction;
+            let mut t0 = (min - origin) * inv;
+            let mut t1 = (max - origin) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+    pub fn hit(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        let (enter, exit) = self.hit_range(ray)?;
+        if exit < 0.0 || enter > t_max {
+            return None;
+        }
+        Some(enter.max(0.0))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    // leaves index into `Bvh::indices`, inner nodes store their right child in `start`
+    start: usize,
+    count: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let centroids: Vec<Point3D> = bounds.iter().map(|b| b.centroid()).collect();
+            bvh.build_node(bounds, &centroids, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], centroids: &[Point3D], start: usize, end: usize) -> usize {
+        let mut node_bounds = Aabb::default();
+        let mut centroid_bounds = Aabb::default();
+        for &index in &self.indices[start..end] {
+            node_bounds = node_bounds.union(&bounds[index]);
+            centroid_bounds.grow(&centroids[index]);
+        }
+        let node_index = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: node_bounds, start, count: end - start });
+        if end - start <= LEAF_SIZE {
+            return node_index;
+        }
+        let extent = centroid_bounds.extent();
+        let axis_value = |p: &Point3D| -> f64 {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                p.x
+            } else if extent.y >= extent.z {
+                p.y
+            } else {
+                p.z
+            }
+        };
+        let mid = (start + end) / 2;
+        self.indices[start..end].select_nth_unstable_by(mid - start, |a, b| {
+            axis_value(&centroids[*a]).total_cmp(&axis_value(&centroids[*b]))
+        });
+        self.build_node(bounds, centroids, start, mid);
+        let right = self.build_node(bounds, centroids, mid, end);
+        self.nodes[node_index].start = right;
+        self.nodes[node_index].count = 0;
+        node_index
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map(|node| node.bounds).unwrap_or_default()
+    }
+
+    // finds the closest primitive hit, `hit` returns the distance along the ray for one primitive
+    pub fn intersect<F>(&self, ray: &Ray, mut hit: F) -> Option<(usize, f64)>
+    where
+        F: FnMut(usize) -> Option<f64>,
+    {
+        let mut closest: Option<(usize, f64)> = None;
+        let mut stack: Vec<usize> = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node_index) = stack.pop() {
+            let node = self.nodes[node_index];
+            let t_max = closest.map_or(f64::INFINITY, |(_, t)| t);
+            if node.bounds.hit(ray, t_max).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(node_index + 1);
+                continue;
+            }
+            for &index in &self.indices[node.start..node.start + node.count] {
+                if let Some(t) = hit(index) {
+                    if t < closest.map_or(f64::INFINITY, |(_, best)| best) {
+                        closest = Some((index, t));
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    // visits every primitive whose bounds the ray line crosses, behind the origin included
+    pub fn visit_ray<F>(&self, ray: &Ray, mut visit: F)
+    where
+        F: FnMut(usize),
+    {
+        let mut stack: Vec<usize> = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node_index) = stack.pop() {
+            let node = self.nodes[node_index];
+            if node.bounds.hit_range(ray).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(node_index + 1);
+                continue;
+            }
+            for &index in &self.indices[node.start..node.start + node.count] {
+                visit(index);
+            }
+        }
+    }
+
+    pub fn visit_point<F>(&self, point: &Point3D, epsilon: f64, mut visit: F)
+    where
+        F: FnMut(usize),
+    {
+        let mut stack: Vec<usize> = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node_index) = stack.pop() {
+            let node = self.nodes[node_index];
+            if !node.bounds.contains(point, epsilon) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(node_index + 1);
+                continue;
+            }
+            for &index in &self.indices[node.start..node.start + node.count] {
+                visit(index);
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use serde::Deserialize;
+
+use crate::bvh::Aabb;
+use crate::math::{Point3D, Vector3D};
+use crate::object::{Interval, Material, Object};
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-6;
+// distance the probe rays start away from a hit point when looking for the child that owns it
+const PROBE_OFFSET: f64 = 1e-3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<dyn Object>,
+    pub right: Box<dyn Object>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn Object>, right: Box<dyn Object>) -> Csg {
+        Csg { operation, left, right }
+    }
+
+    // child whose surface the point lies on, and whether its normal has to be flipped
+    fn owner(&self, hit_point: &Point3D) -> (&dyn Object, bool) {
+        let distance_to_surface = |child: &dyn Object| -> f64 {
+            let normal = child.surface_normal(hit_point);
+            let probe = Ray::new(*hit_point - normal * PROBE_OFFSET, normal);
+            child
+                .intervals(probe)
+                .iter()
+                .flat_map(|interval| [interval.enter, interval.exit])
+                .map(|t| (t - PROBE_OFFSET).abs())
+                .fold(f64::INFINITY, f64::min)
+        };
+        let left = self.left.as_ref();
+        let right = self.right.as_ref();
+        if distance_to_surface(left) <= distance_to_surface(right) {
+            (left, false)
+        } else {
+            (right, self.operation == CsgOperation::Difference)
+        }
+    }
+}
+
+impl Object for Csg {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let t = self
+            .intervals(ray)
+            .into_iter()
+            .flat_map(|interval| [interval.enter, interval.exit])
+            .find(|t| *t > EPSILON)?;
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        // sweep over the boundaries of both children, entries before exits at equal distances
+        let mut events: Vec<(f64, bool, bool)> = Vec::new();
+        for (is_left, child) in [(true, &self.left), (false, &self.right)] {
+            for interval in child.intervals(ray) {
+                events.push((interval.enter, is_left, true));
+                events.push((interval.exit, is_left, false));
+            }
+        }
+        events.sort_by(|a, b| a.0.total_cmp(&b.0).then(b.2.cmp(&a.2)));
+        let mut depth_left = 0;
+        let mut depth_right = 0;
+        let mut enter = f64::NEG_INFINITY;
+        let mut result: Vec<Interval> = Vec::new();
+        for (t, is_left, is_enter) in events {
+            let was_inside = self.operation.contains(depth_left > 0, depth_right > 0);
+            let depth = if is_left { &mut depth_left } else { &mut depth_right };
+            if is_enter {
+                *depth += 1;
+            } else {
+                *depth -= 1;
+            }
+            let is_inside = self.operation.contains(depth_left > 0, depth_right > 0);
+            if !was_inside && is_inside {
+                enter = t;
+            } else if was_inside && !is_inside {
+                result.push(Interval::new(enter, t));
+            }
+        }
+        result
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let (child, flip) = self.owner(hit_point);
+        let normal = child.surface_normal(hit_point);
+        if flip {
+            normal * -1.0
+        } else {
+            normal
+        }
+    }
+    fn get_center(&self) -> Point3D {
+        self.left.get_center()
+    }
+    fn bounds(&self) -> Aabb {
+        match self.operation {
+            CsgOperation::Union => self.left.bounds().union(&self.right.bounds()),
+            CsgOperation::Intersection | CsgOperation::Difference => self.left.bounds(),
+        }
+    }
+    fn get_color(&self) -> Vector3D {
+        self.left.get_color()
+    }
+    fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D {
+        self.owner(hit_point).0.get_albedo(hit_point, light_dir)
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        self.owner(hit_point).0.get_uv(hit_point)
+    }
+    fn shading(&self, hit_point: &Point3D, light_dir: &Vector3D, view_dir: &Vector3D) -> Option<(f64, f64)> {
+        self.owner(hit_point).0.shading(hit_point, light_dir, view_dir)
+    }
+    fn get_material(&self, hit_point: &Point3D) -> Material {
+        self.owner(hit_point).0.get_material(hit_point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Sphere;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn sphere(x: f64, radius: f64) -> Box<dyn Object> {
+        Box::new(Sphere::new(Point3D::new(x, 0.0, 0.0), radius, WHITE))
+    }
+
+    // spans along the x axis, the ray starting at x = -10
+    fn spans(csg: &Csg) -> Vec<(f64, f64)> {
+        let ray = Ray::new(Point3D::new(-10.0, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        csg.intervals(ray)
+            .into_iter()
+            .map(|interval| (interval.enter - 10.0, interval.exit - 10.0))
+            .collect()
+    }
+
+    fn assert_spans(found: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(found.len(), expected.len(), "{:?}", found);
+        for (found, expected) in found.iter().zip(expected) {
+            assert!((found.0 - expected.0).abs() < 1e-9 && (found.1 - expected.1).abs() < 1e-9, "{:?}", found);
+        }
+    }
+
+    #[test]
+    fn union_merges_overlaps() {
+        let overlapping = Csg::new(CsgOperation::Union, sphere(0.0, 1.0), sphere(1.5, 1.0));
+        assert_spans(spans(&overlapping), &[(-1.0, 2.5)]);
+        let apart = Csg::new(CsgOperation::Union, sphere(0.0, 1.0), sphere(3.0, 1.0));
+        assert_spans(spans(&apart), &[(-1.0, 1.0), (2.0, 4.0)]);
+        // the entry of the second sphere sorts before the exit of the first where they touch
+        let touching = Csg::new(CsgOperation::Union, sphere(0.0, 1.0), sphere(2.0, 1.0));
+        assert_spans(spans(&touching), &[(-1.0, 3.0)]);
+        let nested = Csg::new(CsgOperation::Union, sphere(0.0, 2.0), sphere(0.5, 1.0));
+        assert_spans(spans(&nested), &[(-2.0, 2.0)]);
+    }
+
+    #[test]
+    fn intersection_keeps_the_overlap() {
+        let lens = Csg::new(CsgOperation::Intersection, sphere(0.0, 1.0), sphere(1.5, 1.0));
+        assert_spans(spans(&lens), &[(0.5, 1.0)]);
+        let apart = Csg::new(CsgOperation::Intersection, sphere(0.0, 1.0), sphere(3.0, 1.0));
+        assert_spans(spans(&apart), &[]);
+    }
+
+    #[test]
+    fn difference_cuts_the_right_child_out() {
+        let bitten = Csg::new(CsgOperation::Difference, sphere(0.0, 1.0), sphere(1.5, 1.0));
+        assert_spans(spans(&bitten), &[(-1.0, 0.5)]);
+        let hollow = Csg::new(CsgOperation::Difference, sphere(0.0, 2.0), sphere(0.0, 1.0));
+        assert_spans(spans(&hollow), &[(-2.0, -1.0), (1.0, 2.0)]);
+        let swallowed = Csg::new(CsgOperation::Difference, sphere(0.0, 1.0), sphere(0.0, 2.0));
+        assert_spans(spans(&swallowed), &[]);
+    }
+
+    #[test]
+    fn nested_operations() {
+        // a hollow shell with a ball back inside its cavity
+        let shell = Csg::new(CsgOperation::Difference, sphere(0.0, 3.0), sphere(0.0, 2.0));
+        let filled = Csg::new(CsgOperation::Union, Box::new(shell), sphere(0.0, 1.0));
+        assert_spans(spans(&filled), &[(-3.0, -2.0), (-1.0, 1.0), (2.0, 3.0)]);
+    }
+
+    #[test]
+    fn difference_flips_the_normals_of_the_cut() {
+        let bitten = Csg::new(CsgOperation::Difference, sphere(0.0, 1.0), sphere(1.5, 1.0));
+        let ray = Ray::new(Point3D::new(-10.0, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        let front = bitten.hits(ray).unwrap();
+        let normal = bitten.surface_normal(&front);
+        assert!((normal.x + 1.0).abs() < 1e-9);
+        // the exit at x = 0.5 lies on the right sphere, whose normal now points into the cavity
+        let back = Point3D::new(0.5, 0.0, 0.0);
+        let normal = bitten.surface_normal(&back);
+        assert!((normal.x - 1.0).abs() < 1e-9, "{:?}", normal);
+    }
+}
@@ -0,0 +1,458 @@
+use std::error::Error;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::math::{self, Point3D, Vector3D};
+use crate::object::{Interval, Material, Object};
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-9;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveBasis {
+    // smooth, does not pass through its control points, n points give n - 3 segments
+    Bspline,
+    // passes through every control point
+    CatmullRom,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurveShape {
+    // flat strip always turned toward the ray
+    Ribbon,
+    Tube,
+}
+
+// control points with the width of the strand at each of them
+#[derive(Clone, Debug)]
+pub struct Strand {
+    pub points: Vec<Point3D>,
+    pub widths: Vec<f64>,
+}
+
+impl Strand {
+    pub fn new(points: Vec<Point3D>, widths: Vec<f64>) -> Strand {
+        Strand { points, widths }
+    }
+
+    // basis weights of the four control points around a segment
+    fn weights(basis: CurveBasis, t: f64) -> [f64; 4] {
+        let (t2, t3) = (t * t, t * t * t);
+        match basis {
+            CurveBasis::Bspline => [
+                (1.0 - 3.0 * t + 3.0 * t2 - t3) / 6.0,
+                (4.0 - 6.0 * t2 + 3.0 * t3) / 6.0,
+                (1.0 + 3.0 * t + 3.0 * t2 - 3.0 * t3) / 6.0,
+                t3 / 6.0,
+            ],
+            CurveBasis::CatmullRom => [
+                (-t + 2.0 * t2 - t3) / 2.0,
+                (2.0 - 5.0 * t2 + 3.0 * t3) / 2.0,
+                (t + 4.0 * t2 - 3.0 * t3) / 2.0,
+                (-t2 + t3) / 2.0,
+            ],
+        }
+    }
+
+    // groups of four control point indices, one per cubic segment
+    fn spans(&self, basis: CurveBasis) -> Vec<[usize; 4]> {
+        let count = self.points.len();
+        match basis {
+            CurveBasis::Bspline => (0..count.saturating_sub(3)).map(|i| [i, i + 1, i + 2, i + 3]).collect(),
+            // the end points are repeated so the curve reaches them
+            CurveBasis::CatmullRom => (0..count.saturating_sub(1))
+                .map(|i| [i.saturating_sub(1), i, i + 1, (i + 2).min(count - 1)])
+                .collect(),
+        }
+    }
+
+    // position and radius along the strand, sampled `subdivisions` times per segment
+    fn sample(&self, basis: CurveBasis, subdivisions: usize) -> Vec<(Point3D, f64)> {
+        let mut samples = Vec::new();
+        for (span_index, span) in self.spans(basis).iter().enumerate() {
+            let first = if span_index == 0 { 0 } else { 1 };
+            for step in first..=subdivisions {
+                let weights = Self::weights(basis, step as f64 / subdivisions as f64);
+                let mut position = Vector3D::default();
+                let mut width = 0.0;
+                for (weight, index) in weights.iter().zip(span) {
+                    position += (self.points[*index] - Point3D::default()) * *weight;
+                    width += self.widths[*index] * weight;
+                }
+                samples.push((Point3D::default() + position, 0.5 * width.max(0.0)));
+            }
+        }
+        samples
+    }
+}
+
+// one straight piece of a strand, `start_v` and `end_v` go from 0 at the root to 1 at the tip
+#[derive(Copy, Clone, Debug)]
+struct CurveSegment {
+    start: Point3D,
+    end: Point3D,
+    start_radius: f64,
+    end_radius: f64,
+    start_v: f64,
+    end_v: f64,
+}
+
+impl CurveSegment {
+    fn axis(&self) -> Vector3D {
+        self.end - self.start
+    }
+
+    fn bounds(&self) -> Aabb {
+        let radius = self.start_radius.max(self.end_radius);
+        let extent = Vector3D::new(radius, radius, radius);
+        Aabb::from_points(&[self.start - extent, self.start + extent, self.end - extent, self.end + extent])
+    }
+
+    // position along the segment closest to a point, clamped to its ends
+    fn project(&self, point: &Point3D) -> f64 {
+        let axis = self.axis();
+        let length2 = axis.dot(&axis);
+        if length2 < EPSILON {
+            return 0.0;
+        }
+        ((*point - self.start).dot(&axis) / length2).clamp(0.0, 1.0)
+    }
+
+    fn radius_at(&self, s: f64) -> f64 {
+        self.start_radius + (self.end_radius - self.start_radius) * s
+    }
+
+    // position along the segment closest to the ray line
+    fn closest_to_ray(&self, ray: &Ray) -> f64 {
+        let axis = self.axis();
+        let w = ray.origin - self.start;
+        let (a, b, c) = (ray.direction.dot(&ray.direction), ray.direction.dot(&axis), axis.dot(&axis));
+        let (d, e) = (ray.direction.dot(&w), axis.dot(&w));
+        let denominator = a * c - b * b;
+        if denominator.abs() < EPSILON {
+            return 0.0;
+        }
+        ((a * e - b * d) / denominator).clamp(0.0, 1.0)
+    }
+
+    // entry and exit distances, a ribbon is crossed at a single distance
+    fn intersect(&self, ray: &Ray, shape: CurveShape) -> Option<(f64, f64)> {
+        let s = self.closest_to_ray(ray);
+        let center = self.start + self.axis() * s;
+        let radius = self.radius_at(s);
+        match shape {
+            CurveShape::Ribbon => {
+                let t = (center - ray.origin).dot(&ray.direction) / ray.direction.dot(&ray.direction);
+                let offset = ray.origin + ray.direction * t - center;
+                if offset.dot(&offset) > radius * radius {
+                    return None;
+                }
+                Some((t, t))
+            }
+            // swept sphere, the sphere under the closest point of the axis stands for the tube there
+            CurveShape::Tube => {
+                let oc = ray.origin - center;
+                let roots = math::solve_quadratic(
+                    ray.direction.dot(&ray.direction),
+                    2.0 * oc.dot(&ray.direction),
+                    oc.dot(&oc) - radius * radius,
+                );
+                match roots.as_slice() {
+                    [enter, exit] => Some((*enter, *exit)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+// strands drawn with the Kajiya-Kay hair model, `color` at the roots fading to `tip_color`
+pub struct Curves {
+    pub shape: CurveShape,
+    pub color: Vector3D,
+    pub tip_color: Option<Vector3D>,
+    pub specular: f64,
+    pub shininess: f64,
+    segments: Vec<CurveSegment>,
+    bvh: Bvh,
+}
+
+impl Curves {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        strands: &[Strand],
+        basis: CurveBasis,
+        shape: CurveShape,
+        subdivisions: usize,
+        color: Vector3D,
+        tip_color: Option<Vector3D>,
+        specular: f64,
+        shininess: f64,
+    ) -> Curves {
+        let mut segments = Vec::new();
+        for strand in strands {
+            let samples = strand.sample(basis, subdivisions.max(1));
+            let count = samples.len().saturating_sub(1).max(1) as f64;
+            for (index, pair) in samples.windows(2).enumerate() {
+                segments.push(CurveSegment {
+                    start: pair[0].0,
+                    end: pair[1].0,
+                    start_radius: pair[0].1,
+                    end_radius: pair[1].1,
+                    start_v: index as f64 / count,
+                    end_v: (index + 1) as f64 / count,
+                });
+            }
+        }
+        let bounds: Vec<Aabb> = segments.iter().map(CurveSegment::bounds).collect();
+        let bvh = Bvh::build(&bounds);
+        Curves { shape, color, tip_color, specular, shininess, segments, bvh }
+    }
+
+    // one strand per line made of "x y z width" groups, lines starting with # are comments
+    pub fn read_strands(path: &str) -> Result<Vec<Strand>, Box<dyn Error>> {
+        Self::parse_strands(&fs::read_to_string(path)?)
+    }
+
+    fn parse_strands(text: &str) -> Result<Vec<Strand>, Box<dyn Error>> {
+        let mut strands = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|error| format!("Strand line {}: {}", number + 1, error))?;
+            if values.len() % 4 != 0 {
+                return Err(format!("Strand line {} is not made of x y z width groups", number + 1).into());
+            }
+            let points = values.chunks_exact(4).map(|point| Point3D::new(point[0], point[1], point[2])).collect();
+            let widths = values.chunks_exact(4).map(|point| point[3]).collect();
+            strands.push(Strand::new(points, widths));
+        }
+        Ok(strands)
+    }
+
+    // how far a point is from the surface of one segment, with the position along it
+    fn segment_distance(&self, index: usize, point: &Point3D) -> (f64, f64) {
+        let segment = &self.segments[index];
+        let s = segment.project(point);
+        let distance = (*point - (segment.start + segment.axis() * s)).length() - segment.radius_at(s);
+        let distance = match self.shape {
+            CurveShape::Ribbon => distance.max(0.0),
+            CurveShape::Tube => distance.abs(),
+        };
+        (distance, s)
+    }
+
+    // the segment a hit point lies on and the position along it, or the nearest segment for a
+    // point outside every segment box, None only when there are no segments
+    fn locate(&self, point: &Point3D) -> Option<(usize, f64)> {
+        let mut best: Option<(usize, f64)> = None;
+        let mut best_distance = f64::INFINITY;
+        self.bvh.visit_point(point, 1e-6, |index| {
+            let (distance, s) = self.segment_distance(index, point);
+            if distance < best_distance {
+                best_distance = distance;
+                best = Some((index, s));
+            }
+        });
+        best.or_else(|| {
+            (0..self.segments.len())
+                .map(|index| (index, self.segment_distance(index, point)))
+                .min_by(|a, b| a.1 .0.total_cmp(&b.1 .0))
+                .map(|(index, (_, s))| (index, s))
+        })
+    }
+
+    fn tangent(&self, point: &Point3D) -> Vector3D {
+        match self.locate(point) {
+            Some((index, _)) => self.segments[index].axis().normalize(),
+            None => Vector3D::new(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+impl Object for Curves {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let (_, t) = self.bvh.intersect(&ray, |index| {
+            let (enter, exit) = self.segments[index].intersect(&ray, self.shape)?;
+            [enter, exit].into_iter().find(|t| *t > EPSILON)
+        })?;
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let mut spans: Vec<(f64, f64)> = Vec::new();
+        self.bvh.visit_ray(&ray, |index| {
+            if let Some(span) = self.segments[index].intersect(&ray, self.shape) {
+                spans.push(span);
+            }
+        });
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut intervals: Vec<Interval> = Vec::new();
+        for (enter, exit) in spans {
+            match intervals.last_mut() {
+                Some(last) if enter <= last.exit => last.exit = last.exit.max(exit),
+                _ => intervals.push(Interval::new(enter, exit)),
+            }
+        }
+        intervals
+    }
+    // away from the axis for tubes, across the strip for ribbons, whose side depends on the ray
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let Some((index, s)) = self.locate(hit_point) else {
+            return Vector3D::new(0.0, 1.0, 0.0);
+        };
+        let segment = &self.segments[index];
+        let tangent = segment.axis().normalize();
+        let offset = *hit_point - (segment.start + segment.axis() * s);
+        let offset = offset - tangent * offset.dot(&tangent);
+        if offset.length() < EPSILON {
+            return tangent.orthonormal_basis().0;
+        }
+        match self.shape {
+            CurveShape::Tube => offset.normalize(),
+            CurveShape::Ribbon => tangent.cross(offset).normalize(),
+        }
+    }
+    fn get_center(&self) -> Point3D {
+        self.bounds().centroid()
+    }
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        match self.tip_color {
+            Some(tip_color) => {
+                let (_, v) = self.get_uv(hit_point);
+                self.color * (1.0 - v) + tip_color * v
+            }
+            None => self.color,
+        }
+    }
+    // u across the strand is unknown, v runs from the root to the tip
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        match self.locate(hit_point) {
+            Some((index, s)) => {
+                let segment = &self.segments[index];
+                (0.5, segment.start_v + (segment.end_v - segment.start_v) * s)
+            }
+            None => (0.0, 0.0),
+        }
+    }
+    // Kajiya-Kay, lit along the strand instead of the normal
+    fn shading(&self, hit_point: &Point3D, light_dir: &Vector3D, view_dir: &Vector3D) -> Option<(f64, f64)> {
+        let tangent = self.tangent(hit_point);
+        let cos_light = tangent.dot(light_dir);
+        let cos_view = tangent.dot(view_dir);
+        let sin_light = (1.0 - cos_light * cos_light).max(0.0).sqrt();
+        let sin_view = (1.0 - cos_view * cos_view).max(0.0).sqrt();
+        let highlight = (sin_light * sin_view - cos_light * cos_view).max(0.0);
+        Some((sin_light, highlight.powf(self.shininess)))
+    }
+    // white highlights scaled by `specular`, their shape comes from `shading`
+    fn get_material(&self, _hit_point: &Point3D) -> Material {
+        Material { specular: Vector3D::new(255.0, 255.0, 255.0) * self.specular, ..Material::default() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROWN: Vector3D = Vector3D { x: 120.0, y: 80.0, z: 40.0 };
+
+    // a straight vertical strand of radius 0.1 from y = 0 to y = 2
+    fn straight(shape: CurveShape, tip_color: Option<Vector3D>) -> Curves {
+        let points = (0..3).map(|i| Point3D::new(0.0, i as f64, 0.0)).collect();
+        let strand = Strand::new(points, vec![0.2; 3]);
+        Curves::new(&[strand], CurveBasis::CatmullRom, shape, 4, BROWN, tip_color, 0.5, 20.0)
+    }
+
+    #[test]
+    fn reads_strand_lines() {
+        let strands = Curves::parse_strands("# comment\n0 0 0 0.1 0 1 0 0.05\n\n1 0 0 0.2 1 1 0 0.1 1 2 0 0\n").unwrap();
+        assert_eq!(strands.len(), 2);
+        assert_eq!(strands[0].points, vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(0.0, 1.0, 0.0)]);
+        assert_eq!(strands[1].widths, vec![0.2, 0.1, 0.0]);
+        assert!(Curves::parse_strands("0 0 0\n").is_err());
+        assert!(Curves::parse_strands("0 0 0 x\n").is_err());
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_its_points() {
+        let points = vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(1.0, 1.0, 0.0), Point3D::new(2.0, 0.0, 0.0)];
+        let samples = Strand::new(points.clone(), vec![0.1, 0.2, 0.3]).sample(CurveBasis::CatmullRom, 4);
+        assert_eq!(samples.len(), 9);
+        for (sample, point) in [&samples[0], &samples[4], &samples[8]].iter().zip(&points) {
+            assert!((sample.0 - *point).length() < 1e-9);
+        }
+        assert!((samples[8].1 - 0.15).abs() < 1e-9);
+        // a B-spline needs four points for its first segment
+        assert!(Strand::new(points, vec![0.1; 3]).sample(CurveBasis::Bspline, 4).is_empty());
+    }
+
+    #[test]
+    fn tubes_are_hit_on_their_side() {
+        let curves = straight(CurveShape::Tube, None);
+        let ray = Ray::new(Point3D::new(-5.0, 1.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        let hit = curves.hits(ray).unwrap();
+        assert!((hit.x + 0.1).abs() < 1e-9);
+        assert!((curves.surface_normal(&hit).x + 1.0).abs() < 1e-9);
+        let intervals = curves.intervals(ray);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].exit - 5.1).abs() < 1e-9);
+        assert!(curves.hits(Ray::new(Point3D::new(-5.0, 3.0, 0.0), Vector3D::new(1.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn ribbons_face_the_ray() {
+        let curves = straight(CurveShape::Ribbon, None);
+        let hit = curves.hits(Ray::new(Point3D::new(0.05, 1.0, -5.0), Vector3D::new(0.0, 0.0, 1.0))).unwrap();
+        assert!(hit.z.abs() < 1e-9);
+        assert!(curves.surface_normal(&hit).z.abs() > 1.0 - 1e-9);
+        assert!(curves.hits(Ray::new(Point3D::new(0.2, 1.0, -5.0), Vector3D::new(0.0, 0.0, 1.0))).is_none());
+    }
+
+    #[test]
+    fn color_fades_to_the_tip() {
+        let white = Vector3D::new(255.0, 255.0, 255.0);
+        let curves = straight(CurveShape::Tube, Some(white));
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        assert!((curves.get_albedo(&Point3D::new(0.1, 0.0, 0.0), &up) - BROWN).length() < 1e-9);
+        assert!((curves.get_uv(&Point3D::new(0.1, 1.0, 0.0)).1 - 0.5).abs() < 1e-9);
+        assert!((curves.get_albedo(&Point3D::new(0.1, 2.0, 0.0), &up) - white).length() < 1e-9);
+    }
+
+    #[test]
+    fn stray_points_use_the_nearest_segment() {
+        let curves = straight(CurveShape::Tube, Some(Vector3D::new(255.0, 255.0, 255.0)));
+        // far outside every segment box, level with the tip
+        let stray = Point3D::new(5.0, 2.0, 0.0);
+        assert!((curves.surface_normal(&stray).x - 1.0).abs() < 1e-9);
+        assert!((curves.get_uv(&stray).1 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn highlights_follow_the_strand() {
+        let curves = straight(CurveShape::Tube, None);
+        let point = Point3D::new(0.1, 1.0, 0.0);
+        // light and eye mirrored across the strand light it fully, a light along it not at all
+        let light = Vector3D::new(1.0, 1.0, 0.0).normalize();
+        let view = Vector3D::new(1.0, -1.0, 0.0).normalize();
+        let (diffuse, highlight) = curves.shading(&point, &light, &view).unwrap();
+        assert!((diffuse - 0.5f64.sqrt()).abs() < 1e-9 && (highlight - 1.0).abs() < 1e-9);
+        let (diffuse, _) = curves.shading(&point, &Vector3D::new(0.0, 1.0, 0.0), &view).unwrap();
+        assert!(diffuse.abs() < 1e-9);
+    }
+}
@@ -0,0 +1,265 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::codecs::hdr::HdrDecoder;
+
+use crate::light::{Light, LightSample};
+use crate::math::{self, Point3D, Vector3D};
+
+fn luminance(color: &Vector3D) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// index of the interval of a cumulative table holding `value`
+fn find_interval(cdf: &[f64], value: f64) -> usize {
+    let upper = cdf.partition_point(|entry| *entry <= value);
+    upper.saturating_sub(1).min(cdf.len() - 2)
+}
+
+// equirectangular image around the scene, +Y up, the center of the image looking down -Z
+// `rotation` turns it around Y in degrees, texels hold linear radiance where 1 is white
+pub struct Environment {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Vector3D>,
+    pub rotation: f64,
+    pub intensity: f64,
+    pub samples: usize,
+    // false keeps the map as a backdrop only
+    pub lighting: bool,
+    // cumulative luminance of each row, then of the rows themselves, for importance sampling
+    row_cdfs: Vec<Vec<f64>>,
+    marginal_cdf: Vec<f64>,
+    row_weights: Vec<f64>,
+    total_weight: f64,
+}
+
+impl Environment {
+    pub fn new(width: usize, height: usize, texels: Vec<Vector3D>, rotation: f64, intensity: f64, samples: usize) -> Environment {
+        let mut row_cdfs = Vec::with_capacity(height);
+        let mut row_weights = Vec::with_capacity(height);
+        for row in 0..height {
+            // texels near the poles cover less of the sphere
+            let sin_theta = ((row as f64 + 0.5) / height as f64 * PI).sin();
+            let mut cdf = Vec::with_capacity(width + 1);
+            let mut sum = 0.0;
+            cdf.push(0.0);
+            for column in 0..width {
+                sum += luminance(&texels[row * width + column]).max(0.0) * sin_theta;
+                cdf.push(sum);
+            }
+            row_cdfs.push(cdf);
+            row_weights.push(sum);
+        }
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        let mut total_weight = 0.0;
+        marginal_cdf.push(0.0);
+        for weight in &row_weights {
+            total_weight += weight;
+            marginal_cdf.push(total_weight);
+        }
+        Environment {
+            width,
+            height,
+            texels,
+            rotation,
+            intensity,
+            samples,
+            lighting: true,
+            row_cdfs,
+            marginal_cdf,
+            row_weights,
+            total_weight,
+        }
+    }
+
+    // Radiance .hdr files, or any image the image crate reads taken as 0-255 colors
+    pub fn load(path: &str, rotation: f64, intensity: f64, samples: usize) -> Result<Environment, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let (width, height, texels) = match extension.as_deref() {
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let metadata = decoder.metadata();
+                let texels = decoder
+                    .read_image_hdr()?
+                    .into_iter()
+                    .map(|pixel| Vector3D::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64))
+                    .collect();
+                (metadata.width as usize, metadata.height as usize, texels)
+            }
+            Some("exr") => return Err("OpenEXR environments are not supported, convert the file to .hdr".into()),
+            _ => {
+                let image = image::open(path)?.to_rgb8();
+                let texels = image
+                    .pixels()
+                    .map(|pixel| Vector3D::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64) / 255.0)
+                    .collect();
+                (image.width() as usize, image.height() as usize, texels)
+            }
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("{} is an empty environment map", path).into());
+        }
+        Ok(Environment::new(width, height, texels, rotation, intensity, samples))
+    }
+
+    // image coordinates in [0, 1] of a world direction
+    fn direction_to_uv(&self, direction: &Vector3D) -> (f64, f64) {
+        let direction = direction.normalize();
+        let (sin, cos) = (-self.rotation).to_radians().sin_cos();
+        let (x, z) = (direction.x * cos + direction.z * sin, -direction.x * sin + direction.z * cos);
+        let phi = x.atan2(-z);
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        (0.5 + phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vector3D {
+        let (phi, theta) = ((u - 0.5) * 2.0 * PI, v * PI);
+        let (x, y, z) = (theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        Vector3D::new(x * cos + z * sin, y, -x * sin + z * cos)
+    }
+
+    fn texel(&self, u: f64, v: f64) -> (usize, usize) {
+        let column = ((u * self.width as f64) as usize).min(self.width - 1);
+        let row = ((v * self.height as f64) as usize).min(self.height - 1);
+        (column, row)
+    }
+
+    // linear radiance coming from a direction, intensity included
+    pub fn radiance(&self, direction: &Vector3D) -> Vector3D {
+        let (u, v) = self.direction_to_uv(direction);
+        let (column, row) = self.texel(u, v);
+        self.texels[row * self.width + column] * self.intensity
+    }
+
+    // what a ray leaving the scene shows, clipped to 0-255
+    pub fn background(&self, direction: &Vector3D) -> Vector3D {
+        let radiance = self.radiance(direction);
+        Vector3D::new(radiance.x.clamp(0.0, 1.0), radiance.y.clamp(0.0, 1.0), radiance.z.clamp(0.0, 1.0)) * 255.0
+    }
+
+    // direction drawn in proportion to the luminance of the map, with its density over solid angle
+    fn sample_direction(&self, first: f64, second: f64) -> Option<(Vector3D, f64)> {
+        if self.total_weight <= 0.0 {
+            return None;
+        }
+        let row = find_interval(&self.marginal_cdf, first * self.total_weight);
+        let row_weight = self.row_weights[row];
+        if row_weight <= 0.0 {
+            return None;
+        }
+        let cdf = &self.row_cdfs[row];
+        let target = second * row_weight;
+        let column = find_interval(cdf, target);
+        let cell = cdf[column + 1] - cdf[column];
+        let offset = if cell > 0.0 { (target - cdf[column]) / cell } else { 0.5 };
+        let u = (column as f64 + offset) / self.width as f64;
+        let v = (row as f64 + 0.5) / self.height as f64;
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        // density over the image is weight / mean weight, each texel spans 2π² sin θ / (w h) steradians
+        let pdf_image = cell / self.total_weight * (self.width * self.height) as f64;
+        let pdf = pdf_image / (2.0 * PI * PI * sin_theta);
+        Some((self.uv_to_direction(u, v), pdf))
+    }
+}
+
+// lights the scene from every direction, each sample weighted by 1 / (pdf * count * π) so that a uniform
+// white map of radiance 1 gives a white surface its full albedo
+impl Light for Environment {
+    fn sample(&self, point: &Point3D) -> LightSample {
+        self.samples(point).into_iter().next().unwrap_or(LightSample {
+            direction: Vector3D::new(0.0, 1.0, 0.0),
+            distance: f64::INFINITY,
+            color: Vector3D::new(0.0, 0.0, 0.0),
+            radiance: 0.0,
+        })
+    }
+    fn samples(&self, point: &Point3D) -> Vec<LightSample> {
+        let side = (self.samples.max(1) as f64).sqrt().ceil() as usize;
+        let count = (side * side) as f64;
+        let mut samples = Vec::with_capacity(side * side);
+        for j in 0..side {
+            for i in 0..side {
+                let index = (j * side + i) as u64;
+                let first = (j as f64 + math::hash_unit(math::point_seed(point, 2 * index))) / side as f64;
+                let second = (i as f64 + math::hash_unit(math::point_seed(point, 2 * index + 1))) / side as f64;
+                let Some((direction, pdf)) = self.sample_direction(first, second) else {
+                    continue;
+                };
+                let radiance = self.radiance(&direction);
+                let brightness = luminance(&radiance);
+                if brightness <= 0.0 || pdf <= 0.0 {
+                    continue;
+                }
+                samples.push(LightSample {
+                    direction,
+                    distance: f64::INFINITY,
+                    // hue of the texel, its brightness goes into the radiance
+                    color: radiance / brightness * 255.0,
+                    radiance: brightness / (pdf * count * PI),
+                });
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a dark map with one bright texel on the horizon
+    fn spot_map(rotation: f64) -> Environment {
+        let (width, height) = (8, 4);
+        let mut texels = vec![Vector3D::new(0.01, 0.01, 0.01); width * height];
+        texels[width + 4] = Vector3D::new(100.0, 100.0, 100.0);
+        Environment::new(width, height, texels, rotation, 1.0, 16)
+    }
+
+    #[test]
+    fn directions_map_to_the_image_and_back() {
+        for rotation in [0.0, 90.0, 215.0] {
+            let environment = spot_map(rotation);
+            for (u, v) in [(0.5, 0.5), (0.1, 0.3), (0.8, 0.9)] {
+                let (back_u, back_v) = environment.direction_to_uv(&environment.uv_to_direction(u, v));
+                assert!((back_u - u).abs() < 1e-9 && (back_v - v).abs() < 1e-9);
+            }
+        }
+        // the center of the image looks down -Z and the top up
+        let environment = spot_map(0.0);
+        assert!((environment.uv_to_direction(0.5, 0.5) - Vector3D::new(0.0, 0.0, -1.0)).length() < 1e-9);
+        assert!(environment.uv_to_direction(0.5, 0.0).y > 1.0 - 1e-9);
+    }
+
+    #[test]
+    fn samples_head_for_the_bright_texel() {
+        let environment = spot_map(0.0);
+        let samples = environment.samples(&Point3D::default());
+        assert_eq!(samples.len(), 16);
+        let toward_spot = samples.iter().filter(|sample| environment.radiance(&sample.direction).x > 1.0).count();
+        assert!(toward_spot >= 14);
+    }
+
+    #[test]
+    fn a_uniform_white_map_gives_full_albedo() {
+        let environment = Environment::new(16, 8, vec![Vector3D::new(1.0, 1.0, 1.0); 16 * 8], 0.0, 1.0, 1024);
+        let normal = Vector3D::new(0.0, 1.0, 0.0);
+        // the cosine weighted sum over the upper half is the light a white diffuse surface reflects
+        let total: f64 = environment
+            .samples(&Point3D::default())
+            .iter()
+            .map(|sample| sample.radiance * normal.dot(&sample.direction).max(0.0))
+            .sum();
+        assert!((total - 1.0).abs() < 0.05);
+    }
+}
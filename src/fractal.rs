@@ -0,0 +1,251 @@
+use serde::Deserialize;
+
+use crate::bvh::Aabb;
+use crate::math::{Point3D, Vector3D};
+use crate::object::{Interval, Object};
+use crate::raytracer::Ray;
+use crate::sdf;
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FractalKind {
+    Mandelbulb { power: f64 },
+    MengerSponge,
+    // constant of the iteration z² + c, as (w, x, y, z)
+    Julia { c: [f64; 4] },
+}
+
+fn quaternion_product(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+fn quaternion_length(q: [f64; 4]) -> f64 {
+    (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt()
+}
+
+// fractal of unit size around `center`, grown by `scale`
+// the orbit trap is a value in [0, 1] taken along the iterations, blending `color` into `trap_color`
+pub struct Fractal {
+    pub kind: FractalKind,
+    pub center: Point3D,
+    pub scale: f64,
+    pub iterations: usize,
+    pub escape_radius: f64,
+    pub color: Vector3D,
+    pub trap_color: Option<Vector3D>,
+}
+
+impl Fractal {
+    pub fn new(
+        kind: FractalKind,
+        center: Point3D,
+        scale: f64,
+        iterations: usize,
+        escape_radius: f64,
+        color: Vector3D,
+        trap_color: Option<Vector3D>,
+    ) -> Fractal {
+        Fractal { kind, center, scale, iterations, escape_radius, color, trap_color }
+    }
+
+    fn distance(&self, point: &Point3D) -> f64 {
+        self.estimate(point).0
+    }
+
+    fn trap(&self, point: &Point3D) -> f64 {
+        self.estimate(point).1
+    }
+
+    // distance estimate and orbit trap at a world point
+    fn estimate(&self, point: &Point3D) -> (f64, f64) {
+        let p = (*point - self.center) / self.scale;
+        let (distance, trap) = match self.kind {
+            FractalKind::Mandelbulb { power } => self.mandelbulb(p, power),
+            FractalKind::MengerSponge => self.menger_sponge(p),
+            FractalKind::Julia { c } => self.julia(p, c),
+        };
+        (distance * self.scale, trap.clamp(0.0, 1.0))
+    }
+
+    // the trap is the closest the orbit comes to one of the coordinate planes
+    fn mandelbulb(&self, p: Vector3D, power: f64) -> (f64, f64) {
+        let mut z = p;
+        let mut derivative = 1.0;
+        let mut radius = z.length();
+        let mut trap = f64::INFINITY;
+        for _ in 0..self.iterations {
+            if radius > self.escape_radius || radius == 0.0 {
+                break;
+            }
+            let theta = (z.z / radius).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            derivative = radius.powf(power - 1.0) * power * derivative + 1.0;
+            z = Vector3D::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * radius.powf(power) + p;
+            radius = z.length();
+            trap = trap.min(z.x.abs().min(z.y.abs()).min(z.z.abs()));
+        }
+        if radius == 0.0 {
+            return (0.0, 0.0);
+        }
+        (0.5 * radius.ln() * radius / derivative, trap)
+    }
+
+    // unit box with crosses carved at every level, the trap is how deep the carving that bounds the point goes
+    fn menger_sponge(&self, p: Vector3D) -> (f64, f64) {
+        let outside = Vector3D::new(p.x.abs() - 1.0, p.y.abs() - 1.0, p.z.abs() - 1.0);
+        let mut distance = Vector3D::new(outside.x.max(0.0), outside.y.max(0.0), outside.z.max(0.0)).length()
+            + outside.x.max(outside.y.max(outside.z)).min(0.0);
+        let mut trap = 0.0;
+        let mut scale = 1.0;
+        for level in 0..self.iterations {
+            let fold = |value: f64| (value * scale).rem_euclid(2.0) - 1.0;
+            let a = Vector3D::new(fold(p.x), fold(p.y), fold(p.z));
+            scale *= 3.0;
+            let r = Vector3D::new(
+                (1.0 - 3.0 * a.x.abs()).abs(),
+                (1.0 - 3.0 * a.y.abs()).abs(),
+                (1.0 - 3.0 * a.z.abs()).abs(),
+            );
+            let cross = (r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x)) - 1.0) / scale;
+            if cross > distance {
+                distance = cross;
+                trap = (level + 1) as f64 / self.iterations as f64;
+            }
+        }
+        (distance, trap)
+    }
+
+    fn julia(&self, p: Vector3D, c: [f64; 4]) -> (f64, f64) {
+        let mut z = [p.x, p.y, p.z, 0.0];
+        let mut derivative = [1.0, 0.0, 0.0, 0.0];
+        let mut trap = f64::INFINITY;
+        for _ in 0..self.iterations {
+            derivative = quaternion_product(z, derivative).map(|value| value * 2.0);
+            z = quaternion_product(z, z);
+            for (value, offset) in z.iter_mut().zip(c) {
+                *value += offset;
+            }
+            let radius = quaternion_length(z);
+            trap = trap.min(radius * radius);
+            if radius > self.escape_radius {
+                break;
+            }
+        }
+        let radius = quaternion_length(z);
+        let derivative = quaternion_length(derivative);
+        if radius == 0.0 || derivative == 0.0 {
+            return (0.0, trap);
+        }
+        (0.5 * radius * radius.ln() / derivative, trap)
+    }
+
+    // radius of a sphere the set never leaves, in the unit space of the fractal
+    fn extent(&self) -> f64 {
+        match self.kind {
+            FractalKind::Mandelbulb { .. } => 2.0,
+            FractalKind::MengerSponge => 3.0_f64.sqrt(),
+            FractalKind::Julia { c } => (1.0 + (1.0 + 4.0 * quaternion_length(c)).sqrt()) / 2.0,
+        }
+    }
+}
+
+impl Object for Fractal {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let (start, end) = sdf::march_range(&self.bounds(), &ray)?;
+        let t = sdf::march(|point| self.distance(point), &ray, start, end, 1.0)?;
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        match sdf::march_range(&self.bounds(), &ray) {
+            Some((start, end)) => sdf::march_intervals(|point| self.distance(point), &ray, start, end, 1.0),
+            None => Vec::new(),
+        }
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        sdf::gradient_normal(|point| self.distance(point), hit_point)
+    }
+    fn get_center(&self) -> Point3D {
+        self.center
+    }
+    fn bounds(&self) -> Aabb {
+        let radius = self.extent() * self.scale;
+        let extent = Vector3D::new(radius, radius, radius);
+        Aabb::from_points(&[self.center - extent, self.center + extent])
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        match self.trap_color {
+            Some(trap_color) => {
+                let trap = self.trap(hit_point);
+                self.color * (1.0 - trap) + trap_color * trap
+            }
+            None => self.color,
+        }
+    }
+    // the orbit trap is exposed as u so textures can use it too
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        (self.trap(hit_point), 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn fractal(kind: FractalKind, scale: f64, iterations: usize) -> Fractal {
+        Fractal::new(kind, Point3D::default(), scale, iterations, 2.0, WHITE, None)
+    }
+
+    fn down_z(x: f64, y: f64) -> Ray {
+        Ray::new(Point3D::new(x, y, 5.0), Vector3D::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn sponge_without_iterations_is_a_box() {
+        let cube = fractal(FractalKind::MengerSponge, 2.0, 0);
+        let hit = cube.hits(down_z(0.0, 0.0)).unwrap();
+        assert!((hit.z - 2.0).abs() < 1e-3, "{:?}", hit);
+        let normal = cube.surface_normal(&hit);
+        assert!((normal.z - 1.0).abs() < 1e-3);
+        let bounds = cube.bounds();
+        assert!((bounds.max.x - 2.0 * 3f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sponge_is_carved_through_the_middle() {
+        let sponge = fractal(FractalKind::MengerSponge, 1.0, 3);
+        // straight through the central tunnel
+        assert!(sponge.hits(down_z(0.0, 0.0)).is_none());
+        let hit = sponge.hits(down_z(0.8, 0.8)).unwrap();
+        assert!((hit.z - 1.0).abs() < 1e-3, "{:?}", hit);
+        // the faces of the first tunnel sit a third of the way in, deeper carvings give a higher trap
+        assert!(sponge.trap(&Point3D::new(0.0, 1.0 / 3.0, 0.5)) > 0.0);
+        assert_eq!(sponge.trap(&Point3D::new(0.8, 0.8, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn escaping_sets_stay_inside_their_bounds() {
+        let kinds = [FractalKind::Mandelbulb { power: 8.0 }, FractalKind::Julia { c: [-0.2, 0.6, 0.2, 0.2] }];
+        for kind in kinds {
+            let set = fractal(kind, 1.0, 12);
+            let radius = set.extent();
+            // a point beyond the bounding sphere is estimated outside
+            assert!(set.distance(&Point3D::new(radius * 1.1, 0.0, 0.0)) > 0.0);
+            if let Some(hit) = set.hits(down_z(0.0, 0.0)) {
+                assert!((hit - Point3D::default()).length() <= radius + 1e-9);
+                assert!(set.distance(&hit).abs() < 1e-3);
+            }
+        }
+        // the bulb covers the origin
+        assert!(fractal(FractalKind::Mandelbulb { power: 8.0 }, 1.0, 12).hits(down_z(0.0, 0.0)).is_some());
+    }
+}
@@ -0,0 +1,287 @@
+use std::error::Error;
+
+use crate::bvh::Aabb;
+use crate::math::{Point3D, Vector3D};
+use crate::mesh;
+use crate::object::{Interval, Object};
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-6;
+
+// grid of heights over the XZ plane, `corner` is the lowest corner and `size` the world extent,
+// size.y being the height of a white pixel
+pub struct Heightfield {
+    pub corner: Point3D,
+    pub size: Vector3D,
+    pub color: Vector3D,
+    columns: usize,
+    rows: usize,
+    // heights above corner.y, row by row along Z
+    heights: Vec<f64>,
+    normals: Vec<Vector3D>,
+    // lowest and highest height of every cell, used to skip cells the ray passes over
+    cell_ranges: Vec<(f64, f64)>,
+    highest: f64,
+}
+
+impl Heightfield {
+    pub fn new(heights: Vec<f64>, columns: usize, rows: usize, corner: Point3D, size: Vector3D, color: Vector3D) -> Heightfield {
+        let highest = heights.iter().cloned().fold(0.0, f64::max);
+        let mut heightfield = Heightfield {
+            corner,
+            size,
+            color,
+            columns,
+            rows,
+            heights,
+            normals: Vec::new(),
+            cell_ranges: Vec::new(),
+            highest,
+        };
+        heightfield.normals = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| heightfield.vertex_normal(column, row))
+            .collect();
+        heightfield.cell_ranges = (0..rows - 1)
+            .flat_map(|row| (0..columns - 1).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let corners = [
+                    heightfield.height(column, row),
+                    heightfield.height(column + 1, row),
+                    heightfield.height(column, row + 1),
+                    heightfield.height(column + 1, row + 1),
+                ];
+                (
+                    corners.iter().cloned().fold(f64::INFINITY, f64::min),
+                    corners.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                )
+            })
+            .collect();
+        heightfield
+    }
+
+    // reads any grayscale image the image crate can decode, PNG and PGM included
+    pub fn load(path: &str, corner: Point3D, size: Vector3D, color: Vector3D) -> Result<Heightfield, Box<dyn Error>> {
+        let image = image::open(path)?.into_luma16();
+        let (columns, rows) = (image.width() as usize, image.height() as usize);
+        if columns < 2 || rows < 2 {
+            return Err(format!("heightfield {} needs at least 2x2 pixels", path).into());
+        }
+        let heights = image.pixels().map(|pixel| pixel[0] as f64 / u16::MAX as f64 * size.y).collect();
+        Ok(Heightfield::new(heights, columns, rows, corner, size, color))
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (self.size.x / (self.columns - 1) as f64, self.size.z / (self.rows - 1) as f64)
+    }
+
+    fn height(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.columns + column]
+    }
+
+    fn vertex(&self, column: usize, row: usize) -> Point3D {
+        let (cell_x, cell_z) = self.cell_size();
+        Point3D::new(
+            self.corner.x + column as f64 * cell_x,
+            self.corner.y + self.height(column, row),
+            self.corner.z + row as f64 * cell_z,
+        )
+    }
+
+    // central differences, one sided on the borders
+    fn vertex_normal(&self, column: usize, row: usize) -> Vector3D {
+        let (cell_x, cell_z) = self.cell_size();
+        let (left, right) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+        let (back, front) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+        let slope_x = (self.height(right, row) - self.height(left, row)) / ((right - left) as f64 * cell_x);
+        let slope_z = (self.height(column, front) - self.height(column, back)) / ((front - back) as f64 * cell_z);
+        Vector3D::new(-slope_x, 1.0, -slope_z).normalize()
+    }
+
+    // cell containing a point and the position inside it, both clamped to the grid
+    fn locate(&self, point: &Point3D) -> (usize, usize, f64, f64) {
+        let (cell_x, cell_z) = self.cell_size();
+        let grid_x = ((point.x - self.corner.x) / cell_x).clamp(0.0, (self.columns - 1) as f64);
+        let grid_z = ((point.z - self.corner.z) / cell_z).clamp(0.0, (self.rows - 1) as f64);
+        let column = (grid_x as usize).min(self.columns - 2);
+        let row = (grid_z as usize).min(self.rows - 2);
+        (column, row, grid_x - column as f64, grid_z - row as f64)
+    }
+
+    // surface crossings between `t_min` and `t_max`, walking the cells under the ray with a 2D DDA
+    // each crossing tells whether the ray goes below the surface there
+    fn crossings(&self, ray: &Ray, t_min: f64, t_max: f64, first_only: bool) -> Vec<(f64, bool)> {
+        let (cell_x, cell_z) = self.cell_size();
+        let (mut column, mut row, _, _) = self.locate(&(ray.origin + ray.direction * t_min));
+        let axis = |origin: f64, direction: f64, corner: f64, cell: f64, index: usize| -> (f64, f64) {
+            if direction == 0.0 {
+                return (f64::INFINITY, f64::INFINITY);
+            }
+            let boundary = corner + (index + usize::from(direction > 0.0)) as f64 * cell;
+            ((boundary - origin) / direction, cell / direction.abs())
+        };
+        let (mut next_x, delta_x) = axis(ray.origin.x, ray.direction.x, self.corner.x, cell_x, column);
+        let (mut next_z, delta_z) = axis(ray.origin.z, ray.direction.z, self.corner.z, cell_z, row);
+        let mut t_cell = t_min;
+        let mut crossings: Vec<(f64, bool)> = Vec::new();
+        loop {
+            let t_leave = next_x.min(next_z).min(t_max);
+            let y_enter = ray.origin.y + ray.direction.y * t_cell - self.corner.y;
+            let y_leave = ray.origin.y + ray.direction.y * t_leave - self.corner.y;
+            let (lowest, highest) = self.cell_ranges[row * (self.columns - 1) + column];
+            if y_enter.min(y_leave) <= highest + EPSILON && y_enter.max(y_leave) >= lowest - EPSILON {
+                let corners = [
+                    self.vertex(column, row),
+                    self.vertex(column + 1, row),
+                    self.vertex(column + 1, row + 1),
+                    self.vertex(column, row + 1),
+                ];
+                let mut cell_crossings: Vec<(f64, bool)> = [[0, 2, 1], [0, 3, 2]]
+                    .iter()
+                    .filter_map(|[a, b, c]| {
+                        let (t, _, _) = mesh::intersect_triangle(&corners[*a], &corners[*b], &corners[*c], ray)?;
+                        let normal = (corners[*b] - corners[*a]).cross(corners[*c] - corners[*a]);
+                        Some((t, ray.direction.dot(&normal) < 0.0))
+                    })
+                    .filter(|(t, _)| *t >= t_min && *t <= t_max && *t >= t_cell - EPSILON && *t <= t_leave + EPSILON)
+                    .collect();
+                cell_crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+                for crossing in cell_crossings {
+                    // hits on the edge shared by two triangles or two cells are reported twice
+                    if crossings.last().is_none_or(|last| (crossing.0 - last.0).abs() > EPSILON) {
+                        crossings.push(crossing);
+                    }
+                }
+                if first_only && !crossings.is_empty() {
+                    break;
+                }
+            }
+            if t_leave >= t_max {
+                break;
+            }
+            if next_x < next_z {
+                if (ray.direction.x > 0.0 && column + 2 >= self.columns) || (ray.direction.x < 0.0 && column == 0) {
+                    break;
+                }
+                column = if ray.direction.x > 0.0 { column + 1 } else { column - 1 };
+                t_cell = next_x;
+                next_x += delta_x;
+            } else {
+                if (ray.direction.z > 0.0 && row + 2 >= self.rows) || (ray.direction.z < 0.0 && row == 0) {
+                    break;
+                }
+                row = if ray.direction.z > 0.0 { row + 1 } else { row - 1 };
+                t_cell = next_z;
+                next_z += delta_z;
+            }
+        }
+        crossings
+    }
+}
+
+impl Object for Heightfield {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let (enter, exit) = self.bounds().hit_range(&ray)?;
+        if exit < EPSILON {
+            return None;
+        }
+        let (t, _) = *self.crossings(&ray, enter.max(EPSILON), exit, true).first()?;
+        Some(ray.origin + ray.direction * t)
+    }
+    // the solid is everything between corner.y and the surface
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let Some((box_enter, box_exit)) = self.bounds().hit_range(&ray) else {
+            return Vec::new();
+        };
+        let crossings = self.crossings(&ray, box_enter, box_exit, false);
+        let mut intervals = Vec::new();
+        let mut enter = match crossings.first() {
+            Some((_, false)) => Some(box_enter),
+            _ => None,
+        };
+        for (t, going_below) in crossings {
+            match (enter, going_below) {
+                (None, true) => enter = Some(t),
+                (Some(start), false) => {
+                    intervals.push(Interval::new(start, t));
+                    enter = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = enter {
+            intervals.push(Interval::new(start, box_exit));
+        }
+        intervals
+    }
+    // vertex normals blended across the cell
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let (column, row, fx, fz) = self.locate(hit_point);
+        let normal = |column: usize, row: usize| self.normals[row * self.columns + column];
+        (normal(column, row) * ((1.0 - fx) * (1.0 - fz))
+            + normal(column + 1, row) * (fx * (1.0 - fz))
+            + normal(column, row + 1) * ((1.0 - fx) * fz)
+            + normal(column + 1, row + 1) * (fx * fz))
+            .normalize()
+    }
+    fn get_center(&self) -> Point3D {
+        self.bounds().centroid()
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&[
+            self.corner,
+            Point3D::new(self.corner.x + self.size.x, self.corner.y + self.highest, self.corner.z + self.size.z),
+        ])
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        (
+            ((hit_point.x - self.corner.x) / self.size.x).clamp(0.0, 1.0),
+            ((hit_point.z - self.corner.z) / self.size.z).clamp(0.0, 1.0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    // a ridge of height 4 across the middle of a 4 by 1 field
+    fn ridge() -> Heightfield {
+        let heights = vec![0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0];
+        Heightfield::new(heights, 5, 2, Point3D::default(), Vector3D::new(4.0, 4.0, 1.0), WHITE)
+    }
+
+    #[test]
+    fn rays_from_above_land_on_the_surface() {
+        let field = ridge();
+        let hit = field.hits(Ray::new(Point3D::new(2.0, 10.0, 0.5), Vector3D::new(0.0, -1.0, 0.0))).unwrap();
+        assert!((hit.y - 4.0).abs() < 1e-9);
+        // halfway up the slope
+        let hit = field.hits(Ray::new(Point3D::new(1.5, 10.0, 0.5), Vector3D::new(0.0, -1.0, 0.0))).unwrap();
+        assert!((hit.y - 2.0).abs() < 1e-9);
+        let normal = field.surface_normal(&Point3D::new(0.0, 0.0, 0.5));
+        assert!((normal.y - 1.0).abs() < 1e-9);
+        assert!(field.hits(Ray::new(Point3D::new(5.0, 10.0, 0.5), Vector3D::new(0.0, -1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn low_rays_are_stopped_by_the_ridge() {
+        let field = ridge();
+        let ray = Ray::new(Point3D::new(-1.0, 1.0, 0.5), Vector3D::new(1.0, 0.0, 0.0));
+        let hit = field.hits(ray).unwrap();
+        assert!((hit.x - 1.25).abs() < 1e-9, "{:?}", hit);
+        let intervals = field.intervals(ray);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].enter - 2.25).abs() < 1e-9 && (intervals[0].exit - 3.75).abs() < 1e-9);
+        // over the top of it
+        assert!(field.hits(Ray::new(Point3D::new(-1.0, 5.0, 0.5), Vector3D::new(1.0, 0.0, 0.0))).is_none());
+    }
+}
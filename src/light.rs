@@ -1,11 +1,106 @@
 use serde::{Deserialize, Serialize};
 
-use crate::math::{Point3D, Vector3D};
+use crate::math::{self, Point3D, Vector3D};
+use crate::object::Object;
+
+// how a light is seen from a shaded point
+#[derive(Copy, Clone, Debug)]
+pub struct LightSample {
+    // normalized, from the point toward the light
+    pub direction: Vector3D,
+    // infinite for lights without a position, shadow rays stop there
+    pub distance: f64,
+    pub color: Vector3D,
+    // intensity left once it reaches the point
+    pub radiance: f64,
+}
 
 pub trait Light {
-    fn get_direction(&self) -> Vector3D;
-    fn get_color(&self) -> Vector3D;
-    fn get_intensity(&self) -> f64;
+    fn sample(&self, point: &Point3D) -> LightSample;
+    // every sample shading needs from the light, each carrying its share of the radiance
+    fn samples(&self, point: &Point3D) -> Vec<LightSample> {
+        vec![self.sample(point)]
+    }
+    // whether the light shines on the object at all
+    fn illuminates(&self, _object: &dyn Object) -> bool {
+        true
+    }
+}
+
+// objects a light is restricted to, by name, `include` lists the only ones it lights when given
+// and `exclude` the ones it leaves out
+#[derive(Clone, Debug, Default)]
+pub struct LightLinking {
+    pub include: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+impl LightLinking {
+    pub fn new(include: Option<Vec<String>>, exclude: Vec<String>) -> LightLinking {
+        LightLinking { include, exclude }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+    // unnamed objects can only be lit by lights without an include list
+    pub fn links(&self, name: Option<&str>) -> bool {
+        let listed = |names: &[String]| name.is_some_and(|name| names.iter().any(|entry| entry == name));
+        self.include.as_deref().is_none_or(listed) && !listed(&self.exclude)
+    }
+}
+
+// a light that only shines on the objects its linking allows
+pub struct Linked {
+    pub light: Box<dyn Light>,
+    pub linking: LightLinking,
+}
+
+impl Linked {
+    pub fn new(light: Box<dyn Light>, linking: LightLinking) -> Linked {
+        Linked { light, linking }
+    }
+}
+
+impl Light for Linked {
+    fn sample(&self, point: &Point3D) -> LightSample {
+        self.light.sample(point)
+    }
+    fn samples(&self, point: &Point3D) -> Vec<LightSample> {
+        self.light.samples(point)
+    }
+    fn illuminates(&self, object: &dyn Object) -> bool {
+        self.linking.links(object.name()) && self.light.illuminates(object)
+    }
+}
+
+// intensity is divided by constant + linear * d + quadratic * d²
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Default for Attenuation {
+    fn default() -> Attenuation {
+        Attenuation { constant: 1.0, linear: 0.0, quadratic: 0.0 }
+    }
+}
+
+impl Attenuation {
+    pub fn new(constant: f64, linear: f64, quadratic: f64) -> Attenuation {
+        Attenuation { constant, linear, quadratic }
+    }
+    pub fn inverse_square() -> Attenuation {
+        Attenuation::new(0.0, 0.0, 1.0)
+    }
+    pub fn factor(&self, distance: f64) -> f64 {
+        let divisor = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        if divisor <= 0.0 {
+            return 1.0;
+        }
+        1.0 / divisor
+    }
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
@@ -28,14 +123,13 @@ impl DirectionalLight {
 }
 
 impl Light for DirectionalLight {
-    fn get_direction(&self) -> Vector3D {
-        self.direction
-    }
-    fn get_color(&self) -> Vector3D {
-        self.color
-    }
-    fn get_intensity(&self) -> f64 {
-        self.intensity
+    fn sample(&self, _point: &Point3D) -> LightSample {
+        LightSample {
+            direction: self.direction.normalize(),
+            distance: f64::INFINITY,
+            color: self.color,
+            radiance: self.intensity,
+        }
     }
 }
 
@@ -44,26 +138,178 @@ pub struct PointLight {
     pub position: Point3D,
     pub color: Vector3D,
     pub intensity: f64,
+    pub attenuation: Attenuation,
 }
 
 impl PointLight {
-    pub fn new(position: Point3D, color: Vector3D, intensity: f64) -> PointLight {
-        PointLight { position, color, intensity }
+    pub fn new(position: Point3D, color: Vector3D, intensity: f64, attenuation: Attenuation) -> PointLight {
+        PointLight { position, color, intensity, attenuation }
     }
 }
 
 impl Light for PointLight {
-    fn get_direction(&self) -> Vector3D {
-        (self.position - Point3D::new(0.0, 0.0, 0.0)).normalize()
+    fn sample(&self, point: &Point3D) -> LightSample {
+        let offset = self.position - *point;
+        let distance = offset.length();
+        LightSample {
+            direction: offset.normalize(),
+            distance,
+            color: self.color,
+            radiance: self.intensity * self.attenuation.factor(distance),
+        }
     }
-    fn get_color(&self) -> Vector3D {
-        self.color
+}
+
+// point light shining inside a cone around `direction`, full inside `inner_angle` and fading out
+// smoothly up to `outer_angle`, both half angles in degrees
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct SpotLight {
+    pub position: Point3D,
+    pub direction: Vector3D,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub color: Vector3D,
+    pub intensity: f64,
+    pub attenuation: Attenuation,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point3D,
+        direction: Vector3D,
+        inner_angle: f64,
+        outer_angle: f64,
+        color: Vector3D,
+        intensity: f64,
+        attenuation: Attenuation,
+    ) -> SpotLight {
+        SpotLight { position, direction, inner_angle, outer_angle, color, intensity, attenuation }
+    }
+    // 1 inside the inner cone, 0 outside the outer one, a half angle of 180 lights every direction
+    fn cone(&self, direction_to_point: &Vector3D) -> f64 {
+        let cos_angle = self.direction.normalize().dot(direction_to_point);
+        let outer_angle = self.outer_angle.clamp(0.0, 180.0);
+        let cos_inner = self.inner_angle.clamp(0.0, outer_angle).to_radians().cos();
+        let cos_outer = outer_angle.to_radians().cos();
+        if cos_inner - cos_outer <= 0.0 {
+            return if cos_angle >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let x = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: &Point3D) -> LightSample {
+        let offset = self.position - *point;
+        let distance = offset.length();
+        let direction = offset.normalize();
+        LightSample {
+            direction,
+            distance,
+            color: self.color,
+            radiance: self.intensity * self.attenuation.factor(distance) * self.cone(&(direction * -1.0)),
+        }
+    }
+}
+
+// emitting surface around `position`, a rectangle is given by its two full edges,
+// it shines toward u x v and a disk toward its normal, a sphere shines all around
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(tag = "shape", rename_all = "lowercase")]
+pub enum AreaShape {
+    Rectangle { u: Vector3D, v: Vector3D },
+    Disk { normal: Vector3D, radius: f64 },
+    Sphere { radius: f64 },
+}
+
+// light spread over a surface, sampled by a jittered grid of `samples` points rounded up to a square,
+// which gives soft shadows whose penumbra grows with the size of the light
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct AreaLight {
+    pub position: Point3D,
+    pub shape: AreaShape,
+    pub color: Vector3D,
+    pub intensity: f64,
+    pub attenuation: Attenuation,
+    pub samples: usize,
+}
+
+impl AreaLight {
+    pub fn new(
+        position: Point3D,
+        shape: AreaShape,
+        color: Vector3D,
+        intensity: f64,
+        attenuation: Attenuation,
+        samples: usize,
+    ) -> AreaLight {
+        AreaLight { position, shape, color, intensity, attenuation, samples }
     }
-    fn get_intensity(&self) -> f64 {
-        self.intensity
+    // point of the light for the coordinates (u, v) of the unit square, seen from `point`
+    fn surface_point(&self, point: &Point3D, u: f64, v: f64) -> Point3D {
+        match self.shape {
+            AreaShape::Rectangle { u: edge_u, v: edge_v } => self.position + edge_u * (u - 0.5) + edge_v * (v - 0.5),
+            AreaShape::Disk { normal, radius } => {
+                let (tangent, bitangent) = normal.normalize().orthonormal_basis();
+                let (x, y) = math::concentric_disk(u, v);
+                self.position + (tangent * x + bitangent * y) * radius
+            }
+            // a sphere looks like a disk facing the point
+            AreaShape::Sphere { radius } => {
+                let (tangent, bitangent) = (*point - self.position).normalize().orthonormal_basis();
+                let (x, y) = math::concentric_disk(u, v);
+                self.position + (tangent * x + bitangent * y) * radius
+            }
+        }
+    }
+    // side a flat light shines toward, none for a sphere
+    fn facing(&self) -> Option<Vector3D> {
+        match self.shape {
+            AreaShape::Rectangle { u, v } => Some(u.cross(v).normalize()),
+            AreaShape::Disk { normal, .. } => Some(normal.normalize()),
+            AreaShape::Sphere { .. } => None,
+        }
+    }
+    fn sample_at(&self, point: &Point3D, light_point: Point3D, share: f64) -> LightSample {
+        let offset = light_point - *point;
+        let distance = offset.length();
+        let direction = offset.normalize();
+        // a flat light dims as it is seen edge on and is dark from behind
+        let cosine = self.facing().map_or(1.0, |normal| normal.dot(&(direction * -1.0)).max(0.0));
+        LightSample {
+            direction,
+            distance,
+            color: self.color,
+            radiance: self.intensity * self.attenuation.factor(distance) * share * cosine,
+        }
     }
 }
 
+impl Light for AreaLight {
+    fn sample(&self, point: &Point3D) -> LightSample {
+        self.sample_at(point, self.surface_point(point, 0.5, 0.5), 1.0)
+    }
+    fn samples(&self, point: &Point3D) -> Vec<LightSample> {
+        let side = (self.samples.max(1) as f64).sqrt().ceil() as usize;
+        let share = 1.0 / (side * side) as f64;
+        let mut samples = Vec::with_capacity(side * side);
+        for j in 0..side {
+            for i in 0..side {
+                let index = (j * side + i) as u64;
+                let u = (i as f64 + math::hash_unit(math::point_seed(point, 2 * index))) / side as f64;
+                let v = (j as f64 + math::hash_unit(math::point_seed(point, 2 * index + 1))) / side as f64;
+                let sample = self.sample_at(point, self.surface_point(point, u, v), share);
+                if sample.radiance > 0.0 {
+                    samples.push(sample);
+                }
+            }
+        }
+        samples
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub struct AmbientLight {
     pub color: Vector3D,
     pub intensity: f64,
@@ -75,16 +321,124 @@ impl AmbientLight {
     }
 }
 
+// comes from nowhere in particular, so it never casts shadows
 impl Light for AmbientLight {
-    fn get_direction(&self) -> Vector3D {
-        Vector3D::new(0.0, 0.0, 1.0)
+    fn sample(&self, _point: &Point3D) -> LightSample {
+        LightSample {
+            direction: Vector3D::new(0.0, 0.0, 1.0),
+            distance: 0.0,
+            color: self.color,
+            radiance: self.intensity,
+        }
+    }
+}
+
+// scene wide balance between the kinds of light, the ambient term lifts the parts no light reaches
+// `emitter_samples` is the number of light and of reflection samples taken toward emissive objects
+#[derive(Copy, Clone, Debug)]
+pub struct Lighting {
+    pub ambient: AmbientLight,
+    pub diffuse: f64,
+    pub specular: f64,
+    pub emitter_samples: usize,
+}
+
+impl Default for Lighting {
+    fn default() -> Lighting {
+        Lighting {
+            ambient: AmbientLight::new(Vector3D::new(255.0, 255.0, 255.0), 0.0),
+            diffuse: 1.0,
+            specular: 1.0,
+            emitter_samples: 16,
+        }
+    }
+}
+
+impl Lighting {
+    pub fn new(ambient: AmbientLight, diffuse: f64, specular: f64, emitter_samples: usize) -> Lighting {
+        Lighting { ambient, diffuse, specular, emitter_samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    #[test]
+    fn point_lights_are_seen_from_the_shaded_point() {
+        let light = PointLight::new(Point3D::new(0.0, 4.0, 0.0), WHITE, 8.0, Attenuation::inverse_square());
+        let sample = light.sample(&Point3D::new(3.0, 0.0, 0.0));
+        assert!((sample.direction - Vector3D::new(-0.6, 0.8, 0.0)).length() < 1e-9);
+        assert!((sample.distance - 5.0).abs() < 1e-9);
+        assert!((sample.radiance - 8.0 / 25.0).abs() < 1e-9);
+        // without attenuation the distance changes nothing
+        let light = PointLight::new(Point3D::new(0.0, 4.0, 0.0), WHITE, 8.0, Attenuation::default());
+        assert_eq!(light.sample(&Point3D::new(100.0, 0.0, 0.0)).radiance, 8.0);
+    }
+
+    #[test]
+    fn attenuation_divides_by_its_terms() {
+        let attenuation = Attenuation::new(1.0, 0.5, 0.25);
+        assert_eq!(attenuation.factor(0.0), 1.0);
+        assert_eq!(attenuation.factor(2.0), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn directional_lights_have_no_distance() {
+        let sample = DirectionalLight::new(Vector3D::new(0.0, 2.0, 0.0), WHITE, 1.0).sample(&Point3D::new(5.0, 5.0, 5.0));
+        assert_eq!(sample.direction.y, 1.0);
+        assert!(sample.distance.is_infinite());
+    }
+
+    #[test]
+    fn spot_lights_fade_between_their_cones() {
+        let spot = |inner_angle, outer_angle| {
+            let down = Vector3D::new(0.0, -1.0, 0.0);
+            SpotLight::new(Point3D::new(0.0, 1.0, 0.0), down, inner_angle, outer_angle, WHITE, 1.0, Attenuation::default())
+        };
+        let at = |degrees: f64| Point3D::new(degrees.to_radians().tan(), 0.0, 0.0);
+        let light = spot(10.0, 30.0);
+        assert_eq!(light.sample(&at(5.0)).radiance, 1.0);
+        let between = light.sample(&at(20.0)).radiance;
+        assert!(between > 0.0 && between < 1.0);
+        assert_eq!(light.sample(&at(40.0)).radiance, 0.0);
+        // an inner cone wider than the outer one leaves a hard edge
+        assert_eq!(spot(45.0, 30.0).sample(&at(29.0)).radiance, 1.0);
+        // past 180 the cone does not wrap around into a narrower one
+        assert_eq!(spot(200.0, 200.0).sample(&Point3D::new(0.0, 2.0, 0.0)).radiance, 1.0);
     }
 
-    fn get_color(&self) -> Vector3D {
-        self.color
+    #[test]
+    fn area_lights_share_their_intensity_over_a_square_grid() {
+        let shape = AreaShape::Rectangle { u: Vector3D::new(2.0, 0.0, 0.0), v: Vector3D::new(0.0, 0.0, 2.0) };
+        let light = AreaLight::new(Point3D::new(0.0, 4.0, 0.0), shape, WHITE, 3.0, Attenuation::default(), 5);
+        let samples = light.samples(&Point3D::default());
+        // five samples are rounded up to a 3 x 3 grid
+        assert_eq!(samples.len(), 9);
+        for sample in &samples {
+            let light_point = Point3D::default() + sample.direction * sample.distance;
+            assert!(light_point.x.abs() <= 1.0 && light_point.z.abs() <= 1.0 && (light_point.y - 4.0).abs() < 1e-9);
+            // each sample carries its share of the intensity times the cosine at the light
+            assert!((sample.radiance - 3.0 / 9.0 * 4.0 / sample.distance).abs() < 1e-9);
+        }
+        // the rectangle faces down, above it nothing is lit
+        assert!(light.samples(&Point3D::new(0.0, 8.0, 0.0)).is_empty());
+        assert_eq!(light.sample(&Point3D::new(0.0, 8.0, 0.0)).radiance, 0.0);
     }
 
-    fn get_intensity(&self) -> f64 {
-        self.intensity
+    #[test]
+    fn linking_includes_and_excludes_by_name() {
+        let names = |list: &[&str]| list.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        let everything = LightLinking::default();
+        assert!(everything.is_empty() && everything.links(None) && everything.links(Some("floor")));
+        let only = LightLinking::new(Some(names(&["hero", "floor"])), Vec::new());
+        assert!(only.links(Some("hero")) && !only.links(Some("wall")) && !only.links(None));
+        // exclusion wins over inclusion
+        let both = LightLinking::new(Some(names(&["hero", "floor"])), names(&["floor"]));
+        assert!(both.links(Some("hero")) && !both.links(Some("floor")));
+        let except = LightLinking::new(None, names(&["wall"]));
+        assert!(except.links(None) && !except.links(Some("wall")));
     }
 }
@@ -1,7 +1,21 @@
+mod bezier;
+mod bvh;
+mod csg;
+mod curve;
+mod environment;
+mod fractal;
+mod heightfield;
 mod light;
 mod math;
+mod mesh;
+mod metaball;
 mod object;
+mod ply;
+mod pointcloud;
 mod raytracer;
+mod sdf;
+mod sky;
+mod voxel;
 mod parser;
 use image::{ImageBuffer, Rgb};
 use math::{Point3D, Vector3D};
@@ -12,8 +26,7 @@ use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
 use std::thread::sleep_ms;
 
-use light::{DirectionalLight, Light, PointLight, AmbientLight};
-use object::{Cylinder, Object, Plane, Sphere};
+use object::Plane;
 use parser::{Parser};
 
 use crate::raytracer::{Camera, Rectangle3D, Scene};
@@ -63,17 +76,24 @@ fn average_rgb_pixels(
 }
 
 fn main() {
+    // every object is built once here and handed to the scene
+    let parser: Parser = match Parser::new() {
+        Ok(parser) => parser,
+        Err(error) => {
+            eprintln!("Invalid scene: {}", error);
+            std::process::exit(84);
+        }
+    };
     File::create("data.ppm").expect("cannot create file");
-    let _parser: Parser = Parser::new();
-    let mut cam: Camera = Parser::get_camera_data();
-    let mut objects: Vec<Box<dyn Object>> = Parser::get_objects_data();
-    let width_height: (u32, u32) = Parser::get_height_width_data();
+    let mut cam: Camera = parser.camera;
+    let width_height: (u32, u32) = parser.width_height;
     let width: u32 = width_height.0;
     let height: u32 = width_height.1;
     cam.aspect_ratio = width as f64 / height as f64;
-    let lights: Vec<Box<dyn Light>> = Parser::get_lights_data();
     let plane: Plane = Plane::default();
-    let mut scene: Scene = Scene::new(cam, objects, lights, plane, width, height);
+    let mut scene: Scene = Scene::new(cam, parser.objects, parser.lights, plane, width, height);
+    scene.set_lighting(parser.lighting);
+    scene.set_environment(parser.environment);
     println!("P3\n{}\n{}\n{}", width_height.0, width_height.1, 255);
     let mut data_file: File = OpenOptions::new()
         .append(true)
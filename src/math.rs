@@ -42,6 +42,10 @@ impl Vector3D {
     pub fn scale(&self, s: f64) -> Vector3D {
         Vector3D { x: self.x * s, y: self.y * s, z: self.z * s }
     }
+    // no positive component, for colors that carry no light
+    pub fn is_black(&self) -> bool {
+        self.x <= 0.0 && self.y <= 0.0 && self.z <= 0.0
+    }
     pub fn cross(self, other: Self) -> Vector3D {
         Vector3D {
             x: self.y * other.z - self.z * other.y,
@@ -49,6 +53,18 @@ impl Vector3D {
             z: self.x * other.y - self.y * other.x,
         }
     }
+    // two unit vectors perpendicular to self and to each other
+    pub fn orthonormal_basis(&self) -> (Vector3D, Vector3D) {
+        let normal = self.normalize();
+        let helper = if normal.x.abs() > 0.9 {
+            Vector3D::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3D::new(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross(normal).normalize();
+        let bitangent = normal.cross(tangent);
+        (tangent, bitangent)
+    }
 }
 impl Add<Vector3D> for Vector3D {
     type Output = Vector3D;
@@ -300,4 +316,453 @@ impl Div<Point3D> for Point3D {
             z: self.z / other.z,
         }
     }
-}
\ No newline at end of file
+}
+// value in [0, 1) that only depends on the seed, so renders are the same from run to run
+pub fn hash_unit(seed: u64) -> f64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// seed for `hash_unit` mixing a point with a counter
+pub fn point_seed(point: &Point3D, index: u64) -> u64 {
+    point.x.to_bits() ^ point.y.to_bits().rotate_left(21) ^ point.z.to_bits().rotate_left(42) ^ index.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+// maps the unit square onto the unit disk keeping strata compact, (Shirley and Chiu)
+pub fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (radius, angle) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b))
+    };
+    (radius * angle.cos(), radius * angle.sin())
+}
+
+// real roots of a x^2 + b x + c, in ascending order
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+    // avoids the cancellation of -b + sqrt(discriminant) when b is large
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let mut roots = if q == 0.0 { vec![0.0, 0.0] } else { vec![q / a, c / q] };
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots
+}
+
+// real roots of x^3 + a x^2 + b x + c
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let q3 = q * q * q;
+    let mut roots = if r * r < q3 {
+        let theta = (r / q3.sqrt()).clamp(-1.0, 1.0).acos();
+        let factor = -2.0 * q.sqrt();
+        vec![
+            factor * (theta / 3.0).cos() - a / 3.0,
+            factor * ((theta + 2.0 * std::f64::consts::PI) / 3.0).cos() - a / 3.0,
+            factor * ((theta - 2.0 * std::f64::consts::PI) / 3.0).cos() - a / 3.0,
+        ]
+    } else {
+        let big_a = -r.signum() * (r.abs() + (r * r - q3).sqrt()).cbrt();
+        let big_b = if big_a == 0.0 { 0.0 } else { q / big_a };
+        vec![big_a + big_b - a / 3.0]
+    };
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots
+}
+
+// real roots of c4 x^4 + c3 x^3 + c2 x^2 + c1 x + c0, in ascending order
+pub fn solve_quartic(c4: f64, c3: f64, c2: f64, c1: f64, c0: f64) -> Vec<f64> {
+    if c4.abs() < 1e-12 {
+        if c3.abs() < 1e-12 {
+            return solve_quadratic(c2, c1, c0);
+        }
+        return solve_cubic(c2 / c3, c1 / c3, c0 / c3);
+    }
+    let (a, b, c, d) = (c3 / c4, c2 / c4, c1 / c4, c0 / c4);
+    // depressed quartic y^4 + p y^2 + q y + r with x = y - a / 4
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+    // Ferrari: the largest root of the resolvent cubic splits the quartic in two quadratics;
+    // it is positive whenever q is not zero, so a root lost to rounding means q is negligible
+    let m = if q.abs() < 1e-12 {
+        0.0
+    } else {
+        solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max)
+    };
+    let mut depressed: Vec<f64> = Vec::new();
+    if m > 0.0 {
+        let s = (2.0 * m).sqrt();
+        depressed.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+        depressed.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+    } else {
+        // biquadratic y^4 + p y^2 + r
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                depressed.push(z.sqrt());
+                depressed.push(-z.sqrt());
+            }
+        }
+    }
+    let evaluate = |x: f64| (((x + a) * x + b) * x + c) * x + d;
+    let derivative = |x: f64| ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+    let mut roots: Vec<f64> = depressed
+        .into_iter()
+        .map(|y| {
+            // a few Newton steps recover the precision lost in the closed form
+            let mut x = y - a / 4.0;
+            for _ in 0..3 {
+                let slope = derivative(x);
+                if slope.abs() < 1e-12 {
+                    break;
+                }
+                x -= evaluate(x) / slope;
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots.dedup_by(|x, y| (*x - *y).abs() < 1e-9);
+    roots
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Matrix4 {
+        Matrix4::identity()
+    }
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+    pub fn identity() -> Matrix4 {
+        Matrix4::scaling(Vector3D::new(1.0, 1.0, 1.0))
+    }
+    pub fn translation(offset: Vector3D) -> Matrix4 {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+    pub fn scaling(factors: Vector3D) -> Matrix4 {
+        Matrix4::new([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+    // rotates around X, then Y, then Z, angles in degrees
+    pub fn rotation(angles: Vector3D) -> Matrix4 {
+        let (sin_x, cos_x) = angles.x.to_radians().sin_cos();
+        let (sin_y, cos_y) = angles.y.to_radians().sin_cos();
+        let (sin_z, cos_z) = angles.z.to_radians().sin_cos();
+        let rotation_x = Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, cos_x, -sin_x, 0.0],
+            [0.0, sin_x, cos_x, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let rotation_y = Matrix4::new([
+            [cos_y, 0.0, sin_y, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [-sin_y, 0.0, cos_y, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let rotation_z = Matrix4::new([
+            [cos_z, -sin_z, 0.0, 0.0],
+            [sin_z, cos_z, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        rotation_z * rotation_y * rotation_x
+    }
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = [[0.0; 4]; 4];
+        for (row, values) in result.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = self.m[column][row];
+            }
+        }
+        Matrix4::new(result)
+    }
+    // Gauss-Jordan elimination with partial pivoting, None for singular matrices
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inverse = Matrix4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|x, y| a[*x][column].abs().total_cmp(&a[*y][column].abs()))?;
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let factor = 1.0 / a[column][column];
+            for k in 0..4 {
+                a[column][k] *= factor;
+                inverse[column][k] *= factor;
+            }
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let scale = a[row][column];
+                for k in 0..4 {
+                    a[row][k] -= scale * a[column][k];
+                    inverse[row][k] -= scale * inverse[column][k];
+                }
+            }
+        }
+        Some(Matrix4::new(inverse))
+    }
+    pub fn transform_point(&self, point: &Point3D) -> Point3D {
+        let m = &self.m;
+        let x = m[0][0] * point.x + m[0][1] * point.y + m[0][2] * point.z + m[0][3];
+        let y = m[1][0] * point.x + m[1][1] * point.y + m[1][2] * point.z + m[1][3];
+        let z = m[2][0] * point.x + m[2][1] * point.y + m[2][2] * point.z + m[2][3];
+        let w = m[3][0] * point.x + m[3][1] * point.y + m[3][2] * point.z + m[3][3];
+        if w != 0.0 && w != 1.0 {
+            return Point3D::new(x / w, y / w, z / w);
+        }
+        Point3D::new(x, y, z)
+    }
+    pub fn transform_vector(&self, vector: &Vector3D) -> Vector3D {
+        let m = &self.m;
+        Vector3D::new(
+            m[0][0] * vector.x + m[0][1] * vector.y + m[0][2] * vector.z,
+            m[1][0] * vector.x + m[1][1] * vector.y + m[1][2] * vector.z,
+            m[2][0] * vector.x + m[2][1] * vector.y + m[2][2] * vector.z,
+        )
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut result = [[0.0; 4]; 4];
+        for (row, values) in result.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[row][k] * other.m[k][column]).sum();
+            }
+        }
+        Matrix4::new(result)
+    }
+}
+
+// object to world matrix together with its inverse
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Transform {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix4) -> Option<Transform> {
+        Some(Transform { matrix, inverse: matrix.inverse()? })
+    }
+    pub fn point_to_world(&self, point: &Point3D) -> Point3D {
+        self.matrix.transform_point(point)
+    }
+    pub fn point_to_object(&self, point: &Point3D) -> Point3D {
+        self.inverse.transform_point(point)
+    }
+    pub fn vector_to_object(&self, vector: &Vector3D) -> Vector3D {
+        self.inverse.transform_vector(vector)
+    }
+    // normals use the inverse transpose so they stay perpendicular under non uniform scale
+    pub fn normal_to_world(&self, normal: &Vector3D) -> Vector3D {
+        self.inverse.transpose().transform_vector(normal).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every expected root is found once within `tolerance` and nothing else is returned
+    fn assert_roots(found: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(found.len(), expected.len(), "found {:?}, expected {:?}", found, expected);
+        for (found, expected) in found.iter().zip(expected) {
+            assert!((found - expected).abs() < tolerance, "found {:?}, expected {:?}", found, expected);
+        }
+    }
+
+    // coefficients of the monic polynomial with the given roots, highest degree first
+    fn expand(roots: &[f64]) -> Vec<f64> {
+        let mut coefficients = vec![1.0];
+        for root in roots {
+            let mut next = vec![0.0; coefficients.len() + 1];
+            for (index, coefficient) in coefficients.iter().enumerate() {
+                next[index] += coefficient;
+                next[index + 1] -= coefficient * root;
+            }
+            coefficients = next;
+        }
+        coefficients
+    }
+
+    fn quartic(roots: [f64; 4], scale: f64) -> Vec<f64> {
+        let c = expand(&roots);
+        solve_quartic(c[0] * scale, c[1] * scale, c[2] * scale, c[3] * scale, c[4] * scale)
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_roots(&solve_quadratic(1.0, -3.0, 2.0), &[1.0, 2.0], 1e-12);
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_empty());
+        assert_roots(&solve_quadratic(0.0, 2.0, -4.0), &[2.0], 1e-12);
+        assert!(solve_quadratic(0.0, 0.0, 1.0).is_empty());
+        // large b, where the textbook formula cancels the small root away
+        assert_roots(&solve_quadratic(1.0, -1e8, 1.0), &[1e-8, 1e8], 1e-12);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        let c = expand(&[-2.0, 1.0, 3.0]);
+        assert_roots(&solve_cubic(c[1], c[2], c[3]), &[-2.0, 1.0, 3.0], 1e-9);
+        // x³ - 1 has a single real root
+        assert_roots(&solve_cubic(0.0, 0.0, -1.0), &[1.0], 1e-12);
+    }
+
+    #[test]
+    fn quartic_distinct_roots() {
+        assert_roots(&quartic([1.0, 2.0, 3.0, 4.0], 1.0), &[1.0, 2.0, 3.0, 4.0], 1e-9);
+        assert_roots(&quartic([-5.0, -0.5, 0.25, 7.0], 3.0), &[-5.0, -0.5, 0.25, 7.0], 1e-9);
+        // x⁴ + 1 has no real root
+        assert!(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0).is_empty());
+        // (x² - 4)(x² + 1)
+        assert_roots(&solve_quartic(1.0, 0.0, -3.0, 0.0, -4.0), &[-2.0, 2.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_repeated_roots() {
+        assert_roots(&quartic([1.0, 1.0, 3.0, -2.0], 1.0), &[-2.0, 1.0, 3.0], 1e-6);
+        assert_roots(&quartic([-1.0, -1.0, 2.0, 2.0], 1.0), &[-1.0, 2.0], 1e-6);
+        assert_roots(&quartic([2.0, 2.0, 2.0, 2.0], 1.0), &[2.0], 1e-3);
+        // a biquadratic with a double pair
+        assert_roots(&quartic([-1.5, -1.5, 1.5, 1.5], 1.0), &[-1.5, 1.5], 1e-6);
+    }
+
+    #[test]
+    fn quartic_falls_back_to_lower_degrees() {
+        // a vanishing leading coefficient leaves the cubic (x - 1)(x - 2)(x - 3)
+        assert_roots(&solve_quartic(1e-14, 1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0], 1e-9);
+        assert_roots(&solve_quartic(0.0, 0.0, 1.0, -3.0, 2.0), &[1.0, 2.0], 1e-12);
+        // a small but real leading coefficient keeps all four roots
+        assert_roots(&quartic([1.0, 2.0, 3.0, 4.0], 1e-6), &[1.0, 2.0, 3.0, 4.0], 1e-6);
+    }
+
+    #[test]
+    fn quartic_large_coefficients() {
+        let roots = [-1200.0, -800.0, 800.0, 1200.0];
+        let found = quartic(roots, 1.0);
+        assert_roots(&found, &roots, 1e-6);
+        // a ray down the tube of a torus with R = 1000 and r = 1, two real roots next to a
+        // complex pair of size R, whose depressed form is biquadratic up to rounding
+        let h = 2001f64.sqrt();
+        let real = expand(&[h - 1.0, h + 1.0]);
+        let complex = [1.0, -2.0 * h, h * h + 4e6];
+        let mut c = [0.0; 5];
+        for (i, x) in real.iter().enumerate() {
+            for (j, y) in complex.iter().enumerate() {
+                c[i + j] += x * y;
+            }
+        }
+        assert_roots(&solve_quartic(c[0], c[1], c[2], c[3], c[4]), &[h - 1.0, h + 1.0], 1e-9);
+    }
+
+    fn assert_matrix(found: &Matrix4, expected: &Matrix4) {
+        for row in 0..4 {
+            for column in 0..4 {
+                assert!((found.m[row][column] - expected.m[row][column]).abs() < 1e-9, "{:?}", found);
+            }
+        }
+    }
+
+    fn assert_vector(found: &Vector3D, expected: &Vector3D) {
+        assert!((*found - *expected).length() < 1e-9, "found {:?}, expected {:?}", found, expected);
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrix = Matrix4::translation(Vector3D::new(1.0, -2.0, 3.0))
+            * Matrix4::rotation(Vector3D::new(30.0, 45.0, 60.0))
+            * Matrix4::scaling(Vector3D::new(2.0, 0.5, 3.0));
+        let inverse = matrix.inverse().unwrap();
+        assert_matrix(&(matrix * inverse), &Matrix4::identity());
+        assert_matrix(&(inverse * matrix), &Matrix4::identity());
+        let point = Point3D::new(0.3, -1.2, 4.0);
+        let moved = inverse.transform_point(&matrix.transform_point(&point));
+        assert!((moved - point).length() < 1e-9);
+        // a zero on the diagonal needs a row swap
+        let swap = Matrix4::new([
+            [0.0, 1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_matrix(&swap.inverse().unwrap(), &swap);
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert!(Matrix4::scaling(Vector3D::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Transform::new(Matrix4::new([[1.0; 4]; 4])).is_none());
+    }
+
+    #[test]
+    fn rotations_are_in_degrees_x_then_y_then_z() {
+        let quarter_z = Matrix4::rotation(Vector3D::new(0.0, 0.0, 90.0));
+        assert_vector(&quarter_z.transform_vector(&Vector3D::new(1.0, 0.0, 0.0)), &Vector3D::new(0.0, 1.0, 0.0));
+        // x first takes y to z, then y takes z to x
+        let both = Matrix4::rotation(Vector3D::new(90.0, 90.0, 0.0));
+        assert_vector(&both.transform_vector(&Vector3D::new(0.0, 1.0, 0.0)), &Vector3D::new(1.0, 0.0, 0.0));
+        assert_matrix(&(both * both.transpose()), &Matrix4::identity());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Transform::new(
+            Matrix4::rotation(Vector3D::new(0.0, 0.0, 30.0)) * Matrix4::scaling(Vector3D::new(4.0, 1.0, 1.0)),
+        )
+        .unwrap();
+        // the plane x + y = 0 of object space, with its normal and a vector lying in it
+        let normal = Vector3D::new(1.0, 1.0, 0.0).normalize();
+        let along = Vector3D::new(1.0, -1.0, 0.0);
+        let world_normal = transform.normal_to_world(&normal);
+        let world_along = transform.matrix.transform_vector(&along);
+        assert!(world_normal.dot(&world_along).abs() < 1e-9);
+        assert!((world_normal.length() - 1.0).abs() < 1e-9);
+        // translations leave normals alone
+        let moved = Transform::new(Matrix4::translation(Vector3D::new(5.0, 5.0, 5.0))).unwrap();
+        assert_vector(&moved.normal_to_world(&normal), &normal);
+        assert_vector(&moved.vector_to_object(&along), &along);
+    }
+}
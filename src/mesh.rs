@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::math::{Point3D, Vector3D};
+use crate::object::{Interval, Object};
+use crate::ply;
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-9;
+
+// vertices, optional vertex normals, optional vertex colors and triangle indices
+type MeshBuffers = (Vec<Point3D>, Option<Vec<Vector3D>>, Option<Vec<Vector3D>>, Vec<[usize; 3]>);
+
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Point3D>,
+    pub normals: Option<Vec<Vector3D>>,
+    pub colors: Option<Vec<Vector3D>>,
+    pub triangles: Vec<[usize; 3]>,
+    pub color: Vector3D,
+    bvh: Bvh,
+    // running total of the triangle areas, to pick triangles in proportion to their size
+    area_cdf: Vec<f64>,
+}
+
+impl Mesh {
+    pub fn new(
+        vertices: Vec<Point3D>,
+        normals: Option<Vec<Vector3D>>,
+        colors: Option<Vec<Vector3D>>,
+        triangles: Vec<[usize; 3]>,
+        color: Vector3D,
+    ) -> Mesh {
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|triangle| Aabb::from_points(&[vertices[triangle[0]], vertices[triangle[1]], vertices[triangle[2]]]))
+            .collect();
+        let bvh = Bvh::build(&bounds);
+        let mut area_cdf = Vec::with_capacity(triangles.len());
+        let mut area = 0.0;
+        for &[a, b, c] in &triangles {
+            area += (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]).length() * 0.5;
+            area_cdf.push(area);
+        }
+        Mesh { vertices, normals, colors, triangles, color, bvh, area_cdf }
+    }
+
+    // picks the loader from the file extension
+    pub fn load(path: &str, offset: Vector3D, color: Vector3D) -> Result<Mesh, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let (vertices, normals, colors, triangles) = match extension.as_deref() {
+            Some("ply") => Self::read_ply(path)?,
+            Some("stl") => Self::read_stl(path)?,
+            _ => return Err(format!("Unsupported mesh format '{}'", path).into()),
+        };
+        let vertices = vertices
+            .into_iter()
+            .map(|vertex| vertex + offset)
+            .collect();
+        Ok(Mesh::new(vertices, normals, colors, triangles, color))
+    }
+
+    fn read_ply(path: &str) -> Result<MeshBuffers, Box<dyn Error>> {
+        Self::ply_buffers(&ply::read(path)?)
+    }
+
+    fn ply_buffers(file: &ply::PlyFile) -> Result<MeshBuffers, Box<dyn Error>> {
+        let vertex_element = file.element("vertex").ok_or("PLY file has no vertex element")?;
+        let vertices = vertex_element.positions()?;
+        let normals = vertex_element.normals();
+        let colors = vertex_element.colors();
+
+        let mut triangles: Vec<[usize; 3]> = Vec::new();
+        if let Some(face_element) = file.element("face") {
+            let indices = face_element
+                .property_index(&["vertex_indices", "vertex_index"])
+                .ok_or("PLY faces have no vertex_indices")?;
+            for row in &face_element.rows {
+                // list values come as floats, a cast would turn negative or broken ones into vertex 0
+                let face = row[indices]
+                    .as_list()
+                    .iter()
+                    .map(|&value| {
+                        if !value.is_finite() || value < 0.0 || value.fract() != 0.0 {
+                            return Err(format!("PLY face index is not a vertex index, found {}", value));
+                        }
+                        Ok(value as usize)
+                    })
+                    .collect::<Result<Vec<usize>, String>>()?;
+                for i in 1..face.len().saturating_sub(1) {
+                    triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+        }
+        let vertex_count = vertex_element.rows.len();
+        if triangles.iter().flatten().any(|&index| index >= vertex_count) {
+            return Err("PLY face references a missing vertex".into());
+        }
+        Ok((vertices, normals, colors, triangles))
+    }
+
+    fn read_stl(path: &str) -> Result<MeshBuffers, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        // binary files may also start with "solid", so trust the size announced in the header first
+        let binary_count = bytes
+            .get(80..84)
+            .map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+        let vertices = match binary_count {
+            Some(count) if bytes.len() == 84 + count * 50 => Self::read_stl_binary(&bytes, count),
+            _ if bytes.starts_with(b"solid") => Self::read_stl_ascii(&String::from_utf8_lossy(&bytes))?,
+            _ => return Err("Invalid STL file".into()),
+        };
+        let triangles = (0..vertices.len() / 3).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect();
+        Ok((vertices, None, None, triangles))
+    }
+
+    fn read_stl_binary(bytes: &[u8], count: usize) -> Vec<Point3D> {
+        let read_f32 = |offset: usize| -> f64 {
+            f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as f64
+        };
+        let mut vertices = Vec::with_capacity(count * 3);
+        for i in 0..count {
+            // skip the 12 byte facet normal, the winding order is used instead
+            let facet = 84 + i * 50 + 12;
+            for corner in 0..3 {
+                let offset = facet + corner * 12;
+                vertices.push(Point3D::new(read_f32(offset), read_f32(offset + 4), read_f32(offset + 8)));
+            }
+        }
+        vertices
+    }
+
+    fn read_stl_ascii(text: &str) -> Result<Vec<Point3D>, Box<dyn Error>> {
+        let mut vertices = Vec::new();
+        let mut tokens = text.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token == "vertex" {
+                let mut coordinates = [0.0; 3];
+                for coordinate in coordinates.iter_mut() {
+                    *coordinate = tokens.next().ok_or("Unexpected end of STL data")?.parse()?;
+                }
+                vertices.push(Point3D::new(coordinates[0], coordinates[1], coordinates[2]));
+            }
+        }
+        if vertices.len() % 3 != 0 {
+            return Err("STL facet without three vertices".into());
+        }
+        Ok(vertices)
+    }
+
+    fn intersect_triangle(&self, index: usize, ray: &Ray) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.triangles[index];
+        intersect_triangle(&self.vertices[a], &self.vertices[b], &self.vertices[c], ray)
+    }
+
+    fn face_normal(&self, index: usize) -> Vector3D {
+        let [a, b, c] = self.triangles[index];
+        (self.vertices[b] - self.vertices[a])
+            .cross(self.vertices[c] - self.vertices[a])
+            .normalize()
+    }
+
+    // closest point of a triangle to `point`, as its distance and clamped barycentric coordinates
+    fn closest_on_triangle(&self, index: usize, point: &Point3D) -> (f64, f64, f64) {
+        let [a, b, c] = self.triangles[index];
+        let edge1 = self.vertices[b] - self.vertices[a];
+        let edge2 = self.vertices[c] - self.vertices[a];
+        let to_point = *point - self.vertices[a];
+        let d11 = edge1.dot(&edge1);
+        let d12 = edge1.dot(&edge2);
+        let d22 = edge2.dot(&edge2);
+        let d1p = edge1.dot(&to_point);
+        let d2p = edge2.dot(&to_point);
+        let denominator = d11 * d22 - d12 * d12;
+        let (mut u, mut v) = if denominator.abs() < EPSILON {
+            (0.0, 0.0)
+        } else {
+            ((d22 * d1p - d12 * d2p) / denominator, (d11 * d2p - d12 * d1p) / denominator)
+        };
+        // outside the triangle, fall back to the nearest of its edges
+        if u < 0.0 || v < 0.0 || u + v > 1.0 {
+            let project = |start: Point3D, edge: Vector3D| {
+                let length = edge.dot(&edge);
+                if length < EPSILON {
+                    0.0
+                } else {
+                    ((*point - start).dot(&edge) / length).clamp(0.0, 1.0)
+                }
+            };
+            let candidates = [
+                (project(self.vertices[a], edge1), 0.0),
+                (0.0, project(self.vertices[a], edge2)),
+                {
+                    let s = project(self.vertices[b], self.vertices[c] - self.vertices[b]);
+                    (1.0 - s, s)
+                },
+            ];
+            let distance = |(u, v): (f64, f64)| (self.vertices[a] + edge1 * u + edge2 * v - *point).length();
+            (u, v) = candidates
+                .into_iter()
+                .min_by(|first, second| distance(*first).total_cmp(&distance(*second)))
+                .unwrap_or((0.0, 0.0));
+        }
+        let distance = (self.vertices[a] + edge1 * u + edge2 * v - *point).length();
+        (distance, u, v)
+    }
+
+    // finds the triangle a hit point lies on and its barycentric coordinates, when the hit point
+    // drifted away from every triangle the nearest one is taken rather than guessing a normal
+    fn locate(&self, point: &Point3D) -> Option<(usize, f64, f64)> {
+        let tolerance = 1e-4;
+        let mut best: Option<(usize, f64, f64)> = None;
+        let mut best_distance = f64::INFINITY;
+        self.bvh.visit_point(point, tolerance, |index| {
+            let (distance, u, v) = self.closest_on_triangle(index, point);
+            if distance < best_distance {
+                best_distance = distance;
+                best = Some((index, u, v));
+            }
+        });
+        if best.is_some() {
+            return best;
+        }
+        let mut nearest: Option<(usize, f64, f64)> = None;
+        for index in 0..self.triangles.len() {
+            let (distance, u, v) = self.closest_on_triangle(index, point);
+            if distance < best_distance {
+                best_distance = distance;
+                nearest = Some((index, u, v));
+            }
+        }
+        nearest
+    }
+
+    fn interpolate(values: &[Vector3D], triangle: [usize; 3], u: f64, v: f64) -> Vector3D {
+        values[triangle[0]] * (1.0 - u - v) + values[triangle[1]] * u + values[triangle[2]] * v
+    }
+}
+
+impl Object for Mesh {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let (_, t) = self
+            .bvh
+            .intersect(&ray, |index| {
+                self.intersect_triangle(index, &ray)
+                    .map(|(t, _, _)| t)
+                    .filter(|t| *t > EPSILON)
+            })?;
+        Some(ray.origin + ray.direction * t)
+    }
+    // a closed mesh is crossed an even number of times, pair the crossings in order
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let mut crossings: Vec<f64> = Vec::new();
+        self.bvh.visit_ray(&ray, |index| {
+            if let Some((t, _, _)) = self.intersect_triangle(index, &ray) {
+                crossings.push(t);
+            }
+        });
+        crossings.sort_by(|a, b| a.total_cmp(b));
+        crossings.dedup_by(|a, b| (*a - *b).abs() < EPSILON);
+        crossings
+            .chunks_exact(2)
+            .map(|pair| Interval::new(pair[0], pair[1]))
+            .collect()
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        match (self.locate(hit_point), &self.normals) {
+            (Some((index, u, v)), Some(normals)) => Self::interpolate(normals, self.triangles[index], u, v).normalize(),
+            (Some((index, _, _)), None) => self.face_normal(index),
+            // only an empty mesh has no triangle to take the normal from
+            (None, _) => Vector3D::new(0.0, 1.0, 0.0),
+        }
+    }
+    fn get_center(&self) -> Point3D {
+        self.bvh.bounds().centroid()
+    }
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        match (&self.colors, self.locate(hit_point)) {
+            (Some(colors), Some((index, u, v))) => Self::interpolate(colors, self.triangles[index], u, v),
+            _ => self.color,
+        }
+    }
+    // triangle chosen by area with `u`, which is then reused inside it
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        let area = *self.area_cdf.last()?;
+        if area <= 0.0 {
+            return None;
+        }
+        let target = u * area;
+        let index = self.area_cdf.partition_point(|total| *total <= target).min(self.area_cdf.len() - 1);
+        let start = if index == 0 { 0.0 } else { self.area_cdf[index - 1] };
+        let size = self.area_cdf[index] - start;
+        let u = if size > 0.0 { ((target - start) / size).clamp(0.0, 1.0) } else { 0.5 };
+        let [a, b, c] = self.triangles[index];
+        let root = u.sqrt();
+        let point = self.vertices[a] * (1.0 - root) + self.vertices[b] * (root * (1.0 - v)) + self.vertices[c] * (root * v);
+        Some((point, self.face_normal(index)))
+    }
+    fn surface_density(&self, _point: &Point3D) -> f64 {
+        match self.area_cdf.last() {
+            Some(area) if *area > 0.0 => 1.0 / area,
+            _ => 0.0,
+        }
+    }
+}
+
+// Möller-Trumbore, returns the distance and the barycentric coordinates of the hit
+pub fn intersect_triangle(a: &Point3D, b: &Point3D, c: &Point3D, ray: &Ray) -> Option<(f64, f64, f64)> {
+    let edge1 = *b - *a;
+    let edge2 = *c - *a;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+    let inv_determinant = 1.0 / determinant;
+    let s = ray.origin - *a;
+    let u = s.dot(&p) * inv_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(&q) * inv_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(&q) * inv_determinant;
+    Some((t, u, v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Mesh {
+        let vertices = vec![
+            Point3D::new(0.0, 0.0, 0.0),
+            Point3D::new(1.0, 0.0, 0.0),
+            Point3D::new(1.0, 1.0, 0.0),
+            Point3D::new(0.0, 1.0, 0.0),
+        ];
+        Mesh::new(vertices, None, None, vec![[0, 1, 2], [0, 2, 3]], Vector3D::new(255.0, 255.0, 255.0))
+    }
+
+    #[test]
+    fn ply_faces_need_whole_vertex_indices() {
+        let ply = |face: &str| {
+            let text = format!(
+                "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                 element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n{}\n",
+                face
+            );
+            Mesh::ply_buffers(&ply::parse(text.as_bytes()).unwrap())
+        };
+        assert_eq!(ply("3 0 1 2").unwrap().3, vec![[0, 1, 2]]);
+        let error = ply("3 -1 1 2").err().unwrap();
+        assert_eq!(error.to_string(), "PLY face index is not a vertex index, found -1");
+        assert!(ply("3 0 1 3").is_err());
+    }
+
+    #[test]
+    fn reads_ascii_stl() {
+        let text = "solid test\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\n\
+                    endloop\nendfacet\nendsolid test\n";
+        let vertices = Mesh::read_stl_ascii(text).unwrap();
+        assert_eq!(vertices.len(), 3);
+        assert_eq!((vertices[1].x, vertices[1].y, vertices[1].z), (1.0, 0.0, 0.0));
+        assert!(Mesh::read_stl_ascii("solid test\nvertex 0 0 0\nvertex 1 0 0\n").is_err());
+    }
+
+    #[test]
+    fn reads_binary_stl() {
+        let mut bytes = vec![0u8; 80];
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend([0u8; 12]);
+        for value in [0.0f32, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 3.0, 0.0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0u8; 2]);
+        let vertices = Mesh::read_stl_binary(&bytes, 1);
+        assert_eq!(vertices.len(), 3);
+        assert_eq!((vertices[2].x, vertices[2].y, vertices[2].z), (0.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn hits_and_normals() {
+        let mesh = square();
+        let ray = Ray::new(Point3D::new(0.75, 0.25, 1.0), Vector3D::new(0.0, 0.0, -1.0));
+        let hit = mesh.hits(ray).unwrap();
+        assert!(hit.z.abs() < 1e-9);
+        let normal = mesh.surface_normal(&hit);
+        assert!((normal.z - 1.0).abs() < 1e-9);
+        let miss = Ray::new(Point3D::new(2.0, 0.25, 1.0), Vector3D::new(0.0, 0.0, -1.0));
+        assert!(mesh.hits(miss).is_none());
+    }
+
+    #[test]
+    fn stray_points_use_the_nearest_triangle() {
+        let mesh = square();
+        // well outside every bounding box, the lookup must not give up and guess a normal
+        let (index, u, v) = mesh.locate(&Point3D::new(2.0, -0.5, 0.3)).unwrap();
+        assert_eq!(index, 0);
+        assert!((u - 1.0).abs() < 1e-9 && v.abs() < 1e-9);
+        let normal = mesh.surface_normal(&Point3D::new(0.2, 0.9, 0.01));
+        assert!((normal.z - 1.0).abs() < 1e-9);
+    }
+}
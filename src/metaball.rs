@@ -0,0 +1,237 @@
+use crate::bvh::{Aabb, Bvh};
+use crate::math::{self, Point3D, Vector3D};
+use crate::object::{Interval, Object};
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-6;
+// samples taken per ball radius when looking for sign changes of the field, a lobe
+// thinner than the step between two samples can slip through unseen
+const SAMPLES_PER_RADIUS: f64 = 16.0;
+const BISECTION_STEPS: usize = 48;
+
+// a ball only influences the field inside its radius, its contribution falls from `weight` to 0 at the edge
+#[derive(Copy, Clone, Debug)]
+pub struct Metaball {
+    pub center: Point3D,
+    pub radius: f64,
+    pub weight: f64,
+    pub color: Option<Vector3D>,
+}
+
+impl Metaball {
+    pub fn new(center: Point3D, radius: f64, weight: f64, color: Option<Vector3D>) -> Metaball {
+        Metaball { center, radius, weight, color }
+    }
+    // Wyvill kernel, smooth and exactly zero outside the radius
+    fn field(&self, point: &Point3D) -> f64 {
+        let falloff = 1.0 - (*point - self.center).dot(&(*point - self.center)) / (self.radius * self.radius);
+        if falloff <= 0.0 {
+            return 0.0;
+        }
+        self.weight * falloff * falloff * falloff
+    }
+    fn gradient(&self, point: &Point3D) -> Vector3D {
+        let offset = *point - self.center;
+        let radius2 = self.radius * self.radius;
+        let falloff = 1.0 - offset.dot(&offset) / radius2;
+        if falloff <= 0.0 {
+            return Vector3D::default();
+        }
+        offset * (-6.0 * self.weight * falloff * falloff / radius2)
+    }
+    fn bounds(&self) -> Aabb {
+        let extent = Vector3D::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(&[self.center - extent, self.center + extent])
+    }
+    // part of the ray inside the radius of influence
+    fn span(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let oc = ray.origin - self.center;
+        let roots = math::solve_quadratic(
+            ray.direction.dot(&ray.direction),
+            2.0 * oc.dot(&ray.direction),
+            oc.dot(&oc) - self.radius * self.radius,
+        );
+        match roots.as_slice() {
+            [enter, exit] => Some((*enter, *exit)),
+            _ => None,
+        }
+    }
+}
+
+// surface where the summed field of the balls reaches `threshold`, which has to be above 0
+pub struct Metaballs {
+    pub balls: Vec<Metaball>,
+    pub threshold: f64,
+    pub color: Vector3D,
+    bvh: Bvh,
+}
+
+impl Metaballs {
+    pub fn new(balls: Vec<Metaball>, threshold: f64, color: Vector3D) -> Metaballs {
+        let bounds: Vec<Aabb> = balls.iter().map(Metaball::bounds).collect();
+        let bvh = Bvh::build(&bounds);
+        Metaballs { balls, threshold, color, bvh }
+    }
+
+    fn field(&self, point: &Point3D) -> f64 {
+        let mut field = 0.0;
+        self.bvh.visit_point(point, 0.0, |index| field += self.balls[index].field(point));
+        field
+    }
+
+    // every crossing of the threshold along the ray in ascending order, starting from outside
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        let mut spans: Vec<(f64, f64, f64)> = Vec::new();
+        self.bvh.visit_ray(ray, |index| {
+            let ball = &self.balls[index];
+            if let Some((enter, exit)) = ball.span(ray) {
+                spans.push((enter, exit, ball.radius));
+            }
+        });
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // overlapping spheres are merged, the field is zero between the merged spans
+        let mut merged: Vec<(f64, f64, f64)> = Vec::new();
+        for (enter, exit, radius) in spans {
+            match merged.last_mut() {
+                Some(last) if enter <= last.1 => {
+                    last.1 = last.1.max(exit);
+                    last.2 = last.2.min(radius);
+                }
+                _ => merged.push((enter, exit, radius)),
+            }
+        }
+        let speed = ray.direction.length();
+        let value = |t: f64| self.field(&(ray.origin + ray.direction * t)) - self.threshold;
+        let mut crossings = Vec::new();
+        for (enter, exit, radius) in merged {
+            let step = radius / (SAMPLES_PER_RADIUS * speed);
+            let samples = ((exit - enter) / step).ceil().max(1.0) as usize;
+            let mut previous_t = enter;
+            let mut previous_value = value(enter);
+            for sample in 1..=samples {
+                let t = enter + (exit - enter) * sample as f64 / samples as f64;
+                let current_value = value(t);
+                if (previous_value < 0.0) != (current_value < 0.0) {
+                    let (mut low, mut high) = (previous_t, t);
+                    for _ in 0..BISECTION_STEPS {
+                        let mid = 0.5 * (low + high);
+                        if (value(mid) < 0.0) == (previous_value < 0.0) {
+                            low = mid;
+                        } else {
+                            high = mid;
+                        }
+                    }
+                    crossings.push(0.5 * (low + high));
+                }
+                previous_t = t;
+                previous_value = current_value;
+            }
+        }
+        crossings
+    }
+}
+
+impl Object for Metaballs {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let t = self.crossings(&ray).into_iter().find(|t| *t > EPSILON)?;
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        self.crossings(&ray)
+            .chunks_exact(2)
+            .map(|pair| Interval::new(pair[0], pair[1]))
+            .collect()
+    }
+    // the field decreases outward, so the normal is the opposite of its gradient
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let mut gradient = Vector3D::default();
+        self.bvh.visit_point(hit_point, 0.0, |index| gradient += self.balls[index].gradient(hit_point));
+        (gradient * -1.0).normalize()
+    }
+    fn get_center(&self) -> Point3D {
+        let sum = self
+            .balls
+            .iter()
+            .fold(Vector3D::default(), |sum, ball| sum + (ball.center - Point3D::default()));
+        Point3D::default() + sum / self.balls.len().max(1) as f64
+    }
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    // colors of the balls blended by how much each one contributes to the field here
+    fn get_albedo(&self, hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        let mut color = Vector3D::default();
+        let mut total = 0.0;
+        self.bvh.visit_point(hit_point, 0.0, |index| {
+            let ball = &self.balls[index];
+            let contribution = ball.field(hit_point).abs();
+            color += ball.color.unwrap_or(self.color) * contribution;
+            total += contribution;
+        });
+        if total <= 0.0 {
+            return self.color;
+        }
+        color / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn ball(x: f64, radius: f64) -> Metaball {
+        Metaball::new(Point3D::new(x, 0.0, 0.0), radius, 1.0, None)
+    }
+
+    // distance from a lone ball's center where its field reaches `threshold`
+    fn surface_radius(radius: f64, threshold: f64) -> f64 {
+        radius * (1.0 - threshold.cbrt()).sqrt()
+    }
+
+    #[test]
+    fn single_ball_surface() {
+        let metaballs = Metaballs::new(vec![ball(0.0, 2.0)], 0.5, WHITE);
+        let ray = Ray::new(Point3D::new(-10.0, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        let expected = surface_radius(2.0, 0.5);
+        let hit = metaballs.hits(ray).unwrap();
+        assert!((hit.x + expected).abs() < 1e-9, "{:?}", hit);
+        let normal = metaballs.surface_normal(&hit);
+        assert!((normal.x + 1.0).abs() < 1e-9);
+        let intervals = metaballs.intervals(ray);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].exit - 10.0 - expected).abs() < 1e-9);
+        assert!(metaballs.hits(Ray::new(Point3D::new(-10.0, 3.0, 0.0), Vector3D::new(1.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn close_balls_blend_and_far_ones_stay_apart() {
+        let ray = Ray::new(Point3D::new(-10.0, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        let apart = Metaballs::new(vec![ball(-3.0, 1.0), ball(3.0, 1.0)], 0.5, WHITE);
+        assert_eq!(apart.intervals(ray).len(), 2);
+        // the fields add up in the middle, which a single ball alone would leave outside
+        let blended = Metaballs::new(vec![ball(-0.5, 1.0), ball(0.5, 1.0)], 0.5, WHITE);
+        assert_eq!(blended.intervals(ray).len(), 1);
+        assert!(blended.field(&Point3D::default()) > 0.5);
+        assert!(ball(-0.5, 1.0).field(&Point3D::default()) < 0.5);
+    }
+
+    #[test]
+    fn albedo_follows_the_nearest_ball() {
+        let red = Vector3D::new(255.0, 0.0, 0.0);
+        let blue = Vector3D::new(0.0, 0.0, 255.0);
+        let balls = vec![
+            Metaball::new(Point3D::new(-0.9, 0.0, 0.0), 1.0, 1.0, Some(red)),
+            Metaball::new(Point3D::new(0.9, 0.0, 0.0), 1.0, 1.0, Some(blue)),
+        ];
+        let metaballs = Metaballs::new(balls, 0.5, WHITE);
+        let left = metaballs.get_albedo(&Point3D::new(-1.2, 0.0, 0.0), &Vector3D::default());
+        assert!(left.x > left.z);
+        let middle = metaballs.get_albedo(&Point3D::new(0.0, 0.5, 0.0), &Vector3D::default());
+        assert!((middle.x - middle.z).abs() < 1e-9);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{math::{Point3D, Vector3D}, raytracer::Ray};
+use std::rc::Rc;
+
+use crate::{bvh::Aabb, math::{self, Matrix4, Point3D, Transform, Vector3D}, raytracer::Ray};
 use serde::{Serialize, Deserialize};
 #[derive(PartialEq)]
 pub enum HitResult {
@@ -7,14 +9,170 @@ pub enum HitResult {
 }
 
 
+// entry and exit distances of a ray through a solid
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub enter: f64,
+    pub exit: f64,
+}
+
+impl Interval {
+    pub fn new(enter: f64, exit: f64) -> Interval {
+        Interval { enter, exit }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpecularModel {
+    // mirror direction of the light against the view direction
+    Phong,
+    // half vector between light and view against the normal, wider highlights for the same shininess
+    BlinnPhong,
+}
+
+// how a surface reflects light on top of its albedo, `specular` is a 0-255 color, black for matte surfaces
+// `emission` is the linear radiance the surface gives off where 1 is white, black for surfaces that are no light
+// `transmission` is the fraction of each of red, green and blue light passing through, black for opaque objects
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
+    pub specular: Vector3D,
+    pub shininess: f64,
+    pub specular_model: SpecularModel,
+    pub emission: Vector3D,
+    pub transmission: Vector3D,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            specular: Vector3D::new(0.0, 0.0, 0.0),
+            shininess: 32.0,
+            specular_model: SpecularModel::BlinnPhong,
+            emission: Vector3D::new(0.0, 0.0, 0.0),
+            transmission: Vector3D::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Material {
+    pub fn new(
+        specular: Vector3D,
+        shininess: f64,
+        specular_model: SpecularModel,
+        emission: Vector3D,
+        transmission: Vector3D,
+    ) -> Material {
+        Material { specular, shininess, specular_model, emission, transmission }
+    }
+    pub fn is_emissive(&self) -> bool {
+        !self.emission.is_black()
+    }
+    pub fn is_transmissive(&self) -> bool {
+        !self.transmission.is_black()
+    }
+    // strength of the highlight, every direction is normalized and points away from the surface
+    pub fn highlight(&self, normal: &Vector3D, light_dir: &Vector3D, view_dir: &Vector3D) -> f64 {
+        let alignment = match self.specular_model {
+            SpecularModel::Phong => {
+                let reflected = *normal * (2.0 * normal.dot(light_dir)) - *light_dir;
+                reflected.dot(view_dir)
+            }
+            SpecularModel::BlinnPhong => normal.dot(&(*light_dir + *view_dir).normalize()),
+        };
+        alignment.max(0.0).powf(self.shininess)
+    }
+    // brings the peak of the highlight in line with its width so it keeps its energy whatever the shininess,
+    // these are the normalizations of each model times the pi the diffuse term leaves out for lights
+    pub fn highlight_scale(&self) -> f64 {
+        match self.specular_model {
+            SpecularModel::Phong => (self.shininess + 2.0) / 2.0,
+            SpecularModel::BlinnPhong => (self.shininess + 8.0) / 8.0,
+        }
+    }
+}
+
+// how an object takes part in each kind of ray, everything is on unless the scene file says otherwise
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Visibility {
+    // seen by rays from the camera
+    pub camera: bool,
+    // blocks or tints the light reaching other objects
+    pub shadows: bool,
+    // seen by the diffuse rays bounced off other surfaces, which only look for emissive objects,
+    // so a glowing object with it off lights nothing around it
+    pub reflections: bool,
+    // darkened by the objects standing between it and a light
+    pub receive_shadows: bool,
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility { camera: true, shadows: true, reflections: true, receive_shadows: true }
+    }
+}
+
+impl Visibility {
+    // hidden from a kind of ray by either side
+    pub fn and(&self, other: &Visibility) -> Visibility {
+        Visibility {
+            camera: self.camera && other.camera,
+            shadows: self.shadows && other.shadows,
+            reflections: self.reflections && other.reflections,
+            receive_shadows: self.receive_shadows && other.receive_shadows,
+        }
+    }
+}
+
+// position of an object in the scene as it was built, kept whatever order the renderer puts objects in
+pub type ObjectId = usize;
+
 pub trait Object {
     fn hits(&self, ray: Ray) -> Option<Point3D>;
+    fn intervals(&self, ray: Ray) -> Vec<Interval>;
     fn surface_normal(&self, hit_point: &Point3D) -> Vector3D;
     fn get_center(&self) -> Point3D;
+    // world space box around the object, infinite for unbounded surfaces
+    fn bounds(&self) -> Aabb;
     fn get_color(&self) -> Vector3D;
     fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D;
+    fn get_uv(&self, _hit_point: &Point3D) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+    // diffuse and specular weights of a light for objects with their own reflectance model,
+    // both directions point away from the hit point, None keeps the usual shading
+    fn shading(&self, _hit_point: &Point3D, _light_dir: &Vector3D, _view_dir: &Vector3D) -> Option<(f64, f64)> {
+        None
+    }
+    fn get_material(&self, _hit_point: &Point3D) -> Material {
+        Material::default()
+    }
+    // point and normal of the surface for the coordinates (u, v) of the unit square, None for
+    // surfaces that cannot be sampled and so never act as lights
+    fn sample_surface(&self, _u: f64, _v: f64) -> Option<(Point3D, Vector3D)> {
+        None
+    }
+    // density over area of `sample_surface` at a point of the surface
+    fn surface_density(&self, _point: &Point3D) -> f64 {
+        0.0
+    }
+    fn visibility(&self) -> Visibility {
+        Visibility::default()
+    }
+    // name given in the scene file, lights are linked to objects through it
+    fn name(&self) -> Option<&str> {
+        None
+    }
+    // no part of the shape can stand between a light and another part of it, so its own shadow rays
+    // skip it, everything else can shadow itself
+    fn is_convex(&self) -> bool {
+        false
+    }
 }
 
+const EPSILON: f64 = 1e-6;
+
 use std::fmt::{self, Debug};
 impl Debug for dyn Object {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -57,20 +215,46 @@ impl Object for Sphere {
         let hit_point = ray.origin + ray.direction * t;
         Some(hit_point)
     }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let oc = ray.origin - self.center;
+        let roots = math::solve_quadratic(
+            ray.direction.dot(&ray.direction),
+            2.0 * oc.dot(&ray.direction),
+            oc.dot(&oc) - self.radius.powi(2),
+        );
+        match roots.as_slice() {
+            [enter, exit] => vec![Interval::new(*enter, *exit)],
+            _ => Vec::new(),
+        }
+    }
     fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
         (*hit_point - self.center).normalize()
     }
     fn get_center(&self) -> Point3D {
         self.center
     }
+    fn bounds(&self) -> Aabb {
+        let extent = Vector3D::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(&[self.center - extent, self.center + extent])
+    }
     fn get_color(&self) -> Vector3D {
         self.color
     }
-    fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D {
-        let radius_sq = self.radius.powi(2);
-        let distance_sq = light_dir.length();
-        let factor = 4.0 * std::f64::consts::PI * radius_sq / distance_sq;
-        self.color * factor
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn is_convex(&self) -> bool {
+        true
+    }
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        let normal = Vector3D::new(r * phi.cos(), r * phi.sin(), z);
+        Some((self.center + normal * self.radius, normal))
+    }
+    fn surface_density(&self, _point: &Point3D) -> f64 {
+        1.0 / (4.0 * std::f64::consts::PI * self.radius * self.radius)
     }
 }
 #[derive(Debug, Deserialize, Serialize)]
@@ -169,18 +353,45 @@ impl Object for Plane {
         }
         None
     }
+    // the solid side is below the plane along its axis
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let (origin, direction) = match self.axis.as_str() {
+            "X" => (ray.origin.x, ray.direction.x),
+            "Y" => (ray.origin.y, ray.direction.y),
+            _ => (ray.origin.z, ray.direction.z),
+        };
+        let position = self.position as f64;
+        if direction.abs() < EPSILON {
+            if origin < position {
+                return vec![Interval::new(f64::NEG_INFINITY, f64::INFINITY)];
+            }
+            return Vec::new();
+        }
+        let t = (position - origin) / direction;
+        if direction > 0.0 {
+            vec![Interval::new(f64::NEG_INFINITY, t)]
+        } else {
+            vec![Interval::new(t, f64::INFINITY)]
+        }
+    }
     fn surface_normal(&self, _hit_point: &Point3D) -> Vector3D {
         self.normal * -1.0
     }
     fn get_center(&self) -> Point3D {
         self.origin
     }
+    fn bounds(&self) -> Aabb {
+        Aabb::infinite()
+    }
     fn get_color(&self) -> Vector3D {
         self.color
     }
     fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D {
         self.color
     }
+    fn is_convex(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -224,6 +435,23 @@ impl Object for Cylinder {
         Some(hit_point)
     }
 
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let oc = ray.origin - self.position;
+        let a = ray.direction.x.powi(2) + ray.direction.z.powi(2);
+        let c = oc.x.powi(2) + oc.z.powi(2) - self.radius.powi(2);
+        if a < EPSILON {
+            if c < 0.0 {
+                return vec![Interval::new(f64::NEG_INFINITY, f64::INFINITY)];
+            }
+            return Vec::new();
+        }
+        let b = 2.0 * (oc.x * ray.direction.x + oc.z * ray.direction.z);
+        match math::solve_quadratic(a, b, c).as_slice() {
+            [enter, exit] => vec![Interval::new(*enter, *exit)],
+            _ => Vec::new(),
+        }
+    }
+
     fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
         let y = hit_point.y;
         let normal = Vector3D::new(hit_point.x - self.position.x, 0.0, hit_point.z - self.position.z).normalize();
@@ -236,15 +464,977 @@ impl Object for Cylinder {
     fn get_center(&self) -> Point3D {
         self.position + Vector3D::new(0.0, 0.5, 0.0)
     }
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&[
+            Point3D::new(self.position.x - self.radius, f64::NEG_INFINITY, self.position.z - self.radius),
+            Point3D::new(self.position.x + self.radius, f64::INFINITY, self.position.z + self.radius),
+        ])
+    }
+
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct Cuboid {
+    pub center: Point3D,
+    pub half_size: Vector3D,
+    pub axes: [Vector3D; 3],
+    pub color: Vector3D,
+}
+
+impl Cuboid {
+    pub fn new(center: Point3D, size: Vector3D, rotation: Vector3D, color: Vector3D) -> Cuboid {
+        let rotation = Matrix4::rotation(rotation);
+        Cuboid {
+            center,
+            half_size: size * 0.5,
+            axes: [
+                rotation.transform_vector(&Vector3D::new(1.0, 0.0, 0.0)),
+                rotation.transform_vector(&Vector3D::new(0.0, 1.0, 0.0)),
+                rotation.transform_vector(&Vector3D::new(0.0, 0.0, 1.0)),
+            ],
+            color,
+        }
+    }
+    fn local_point(&self, point: &Point3D) -> Vector3D {
+        let offset = *point - self.center;
+        Vector3D::new(offset.dot(&self.axes[0]), offset.dot(&self.axes[1]), offset.dot(&self.axes[2]))
+    }
+    // index of the face axis the local point lies on
+    fn face_axis(&self, local: &Vector3D) -> usize {
+        let distances = [
+            (local.x / self.half_size.x).abs(),
+            (local.y / self.half_size.y).abs(),
+            (local.z / self.half_size.z).abs(),
+        ];
+        if distances[0] >= distances[1] && distances[0] >= distances[2] {
+            0
+        } else if distances[1] >= distances[2] {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+impl Object for Cuboid {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let interval = self.intervals(ray).pop()?;
+        if interval.exit < EPSILON {
+            return None;
+        }
+        let t = if interval.enter > EPSILON { interval.enter } else { interval.exit };
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let origin = self.local_point(&ray.origin);
+        let direction = Vector3D::new(
+            ray.direction.dot(&self.axes[0]),
+            ray.direction.dot(&self.axes[1]),
+            ray.direction.dot(&self.axes[2]),
+        );
+        let slabs = [
+            (origin.x, direction.x, self.half_size.x),
+            (origin.y, direction.y, self.half_size.y),
+            (origin.z, direction.z, self.half_size.z),
+        ];
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+        for (origin, direction, half) in slabs {
+            if direction.abs() < EPSILON {
+                if origin.abs() > half {
+                    return Vec::new();
+                }
+                continue;
+            }
+            let t1 = (-half - origin) / direction;
+            let t2 = (half - origin) / direction;
+            t_near = t_near.max(t1.min(t2));
+            t_far = t_far.min(t1.max(t2));
+        }
+        if t_near > t_far {
+            return Vec::new();
+        }
+        vec![Interval::new(t_near, t_far)]
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let local = self.local_point(hit_point);
+        let axis = self.face_axis(&local);
+        let side = [local.x, local.y, local.z][axis].signum();
+        self.axes[axis] * side
+    }
+    fn get_center(&self) -> Point3D {
+        self.center
+    }
+    fn bounds(&self) -> Aabb {
+        let corners: Vec<Point3D> = (0..8)
+            .map(|corner| {
+                let sign = |bit: usize| if corner & bit == 0 { -1.0 } else { 1.0 };
+                self.center
+                    + self.axes[0] * (self.half_size.x * sign(1))
+                    + self.axes[1] * (self.half_size.y * sign(2))
+                    + self.axes[2] * (self.half_size.z * sign(4))
+            })
+            .collect();
+        Aabb::from_points(&corners)
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn is_convex(&self) -> bool {
+        true
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        let local = self.local_point(hit_point);
+        let unit = Vector3D::new(
+            (local.x / self.half_size.x + 1.0) * 0.5,
+            (local.y / self.half_size.y + 1.0) * 0.5,
+            (local.z / self.half_size.z + 1.0) * 0.5,
+        );
+        match self.face_axis(&local) {
+            0 => (unit.z, unit.y),
+            1 => (unit.x, unit.z),
+            _ => (unit.x, unit.y),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Disk {
+    pub center: Point3D,
+    pub normal: Vector3D,
+    pub radius: f64,
+    pub color: Vector3D,
+}
+
+impl Disk {
+    pub fn new(center: Point3D, normal: Vector3D, radius: f64, color: Vector3D) -> Disk {
+        Disk { center, normal: normal.normalize(), radius, color }
+    }
+}
+
+impl Object for Disk {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator.abs() < EPSILON {
+            return None;
+        }
+        let t = (self.center - ray.origin).dot(&self.normal) / denominator;
+        if t < EPSILON {
+            return None;
+        }
+        let hit_point = ray.origin + ray.direction * t;
+        if (hit_point - self.center).length() > self.radius {
+            return None;
+        }
+        Some(hit_point)
+    }
+    // flat surfaces have no inside, they show up as empty intervals
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        match self.hits(ray) {
+            Some(hit_point) => {
+                let t = (hit_point - ray.origin).dot(&ray.direction) / ray.direction.dot(&ray.direction);
+                vec![Interval::new(t, t)]
+            }
+            None => Vec::new(),
+        }
+    }
+    fn surface_normal(&self, _hit_point: &Point3D) -> Vector3D {
+        self.normal
+    }
+    fn get_center(&self) -> Point3D {
+        self.center
+    }
+    fn bounds(&self) -> Aabb {
+        let extent = Vector3D::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(&[self.center - extent, self.center + extent])
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn is_convex(&self) -> bool {
+        true
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let offset = *hit_point - self.center;
+        let angle = offset.dot(&bitangent).atan2(offset.dot(&tangent));
+        (
+            offset.length() / self.radius,
+            (angle + std::f64::consts::PI) / (2.0 * std::f64::consts::PI),
+        )
+    }
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let (x, y) = math::concentric_disk(u, v);
+        Some((self.center + (tangent * x + bitangent * y) * self.radius, self.normal))
+    }
+    fn surface_density(&self, _point: &Point3D) -> f64 {
+        1.0 / (std::f64::consts::PI * self.radius * self.radius)
+    }
+}
+
+// parallelogram spanned by two edges from a corner
+#[derive(Copy, Clone, Debug)]
+pub struct Rectangle {
+    pub corner: Point3D,
+    pub u: Vector3D,
+    pub v: Vector3D,
+    pub normal: Vector3D,
+    pub color: Vector3D,
+}
+
+impl Rectangle {
+    pub fn new(corner: Point3D, u: Vector3D, v: Vector3D, color: Vector3D) -> Rectangle {
+        Rectangle { corner, u, v, normal: u.cross(v).normalize(), color }
+    }
+    fn plane_coordinates(&self, point: &Point3D) -> (f64, f64) {
+        let n = self.u.cross(self.v);
+        let w = n / n.dot(&n);
+        let offset = *point - self.corner;
+        (w.dot(&offset.cross(self.v)), w.dot(&self.u.cross(offset)))
+    }
+}
+
+impl Object for Rectangle {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator.abs() < EPSILON {
+            return None;
+        }
+        let t = (self.corner - ray.origin).dot(&self.normal) / denominator;
+        if t < EPSILON {
+            return None;
+        }
+        let hit_point = ray.origin + ray.direction * t;
+        let (alpha, beta) = self.plane_coordinates(&hit_point);
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(hit_point)
+    }
+    // flat surfaces have no inside, they show up as empty intervals
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        match self.hits(ray) {
+            Some(hit_point) => {
+                let t = (hit_point - ray.origin).dot(&ray.direction) / ray.direction.dot(&ray.direction);
+                vec![Interval::new(t, t)]
+            }
+            None => Vec::new(),
+        }
+    }
+    fn surface_normal(&self, _hit_point: &Point3D) -> Vector3D {
+        self.normal
+    }
+    fn get_center(&self) -> Point3D {
+        self.corner + (self.u + self.v) * 0.5
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&[
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ])
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn is_convex(&self) -> bool {
+        true
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        self.plane_coordinates(hit_point)
+    }
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        Some((self.corner + self.u * u + self.v * v, self.normal))
+    }
+    fn surface_density(&self, _point: &Point3D) -> f64 {
+        1.0 / self.u.cross(self.v).length()
+    }
+}
 
+#[derive(Copy, Clone, Debug)]
+pub struct Torus {
+    pub center: Point3D,
+    pub axis: Vector3D,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub color: Vector3D,
+}
+
+impl Torus {
+    pub fn new(center: Point3D, axis: Vector3D, major_radius: f64, minor_radius: f64, color: Vector3D) -> Torus {
+        Torus { center, axis: axis.normalize(), major_radius, minor_radius, color }
+    }
+    // local frame where the torus lies in the XZ plane around the Y axis
+    fn local_vector(&self, vector: &Vector3D) -> Vector3D {
+        let (tangent, bitangent) = self.axis.orthonormal_basis();
+        Vector3D::new(vector.dot(&tangent), vector.dot(&self.axis), vector.dot(&bitangent))
+    }
+    fn local_point(&self, point: &Point3D) -> Vector3D {
+        self.local_vector(&(*point - self.center))
+    }
+    // (|p|² + R² - r²)² - 4R²(x² + z²) in the local frame, negative inside the tube
+    fn value(&self, local: &Vector3D) -> f64 {
+        let major2 = self.major_radius * self.major_radius;
+        let g = local.dot(local) + major2 - self.minor_radius * self.minor_radius;
+        g * g - 4.0 * major2 * (local.x * local.x + local.z * local.z)
+    }
+    // every crossing of the surface along the ray, in ascending order
+    fn roots(&self, ray: Ray) -> Vec<f64> {
+        // start from the bounding sphere so the quartic coefficients stay small
+        let bounding = Sphere::new(self.center, self.major_radius + self.minor_radius, self.color);
+        let oc = ray.origin - self.center;
+        let start = if oc.length() > bounding.radius {
+            match bounding.hits(ray) {
+                Some(entry) => (entry - ray.origin).dot(&ray.direction) / ray.direction.dot(&ray.direction),
+                None => return Vec::new(),
+            }
+        } else {
+            0.0
+        };
+        let origin = self.local_point(&(ray.origin + ray.direction * start));
+        let direction = self.local_vector(&ray.direction);
+        let major2 = self.major_radius * self.major_radius;
+        let minor2 = self.minor_radius * self.minor_radius;
+        let a = direction.dot(&direction);
+        let b = 2.0 * origin.dot(&direction);
+        let g = origin.dot(&origin) + major2 - minor2;
+        let four_major2 = 4.0 * major2;
+        math::solve_quartic(
+            a * a,
+            2.0 * a * b,
+            b * b + 2.0 * a * g - four_major2 * (direction.x * direction.x + direction.z * direction.z),
+            2.0 * b * g - 2.0 * four_major2 * (origin.x * direction.x + origin.z * direction.z),
+            g * g - four_major2 * (origin.x * origin.x + origin.z * origin.z),
+        )
+        .into_iter()
+        .map(|t| t + start)
+        .collect()
+    }
+}
+
+impl Object for Torus {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let t = self.roots(ray).into_iter().find(|t| *t > EPSILON)?;
+        Some(ray.origin + ray.direction * t)
+    }
+    // the spans between crossings are told apart by the sign of the torus equation at their middle,
+    // so a ray grazing the surface, whose double root shows up once or not at all, keeps its exit
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let roots = self.roots(ray);
+        let mut intervals: Vec<Interval> = Vec::new();
+        for pair in roots.windows(2) {
+            let middle = ray.origin + ray.direction * ((pair[0] + pair[1]) * 0.5);
+            if self.value(&self.local_point(&middle)) >= 0.0 {
+                continue;
+            }
+            match intervals.last_mut() {
+                Some(last) if last.exit >= pair[0] => last.exit = pair[1],
+                _ => intervals.push(Interval::new(pair[0], pair[1])),
+            }
+        }
+        intervals
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let local = self.local_point(hit_point);
+        let ring = Vector3D::new(local.x, 0.0, local.z).normalize() * self.major_radius;
+        let offset = (local - ring).normalize();
+        let (tangent, bitangent) = self.axis.orthonormal_basis();
+        (tangent * offset.x + self.axis * offset.y + bitangent * offset.z).normalize()
+    }
+    fn get_center(&self) -> Point3D {
+        self.center
+    }
+    fn bounds(&self) -> Aabb {
+        let radius = self.major_radius + self.minor_radius;
+        let extent = Vector3D::new(radius, radius, radius);
+        Aabb::from_points(&[self.center - extent, self.center + extent])
+    }
     fn get_color(&self) -> Vector3D {
         self.color
     }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        let local = self.local_point(hit_point);
+        let around_axis = local.z.atan2(local.x);
+        let around_tube = local.y.atan2((local.x * local.x + local.z * local.z).sqrt() - self.major_radius);
+        (
+            (around_axis + std::f64::consts::PI) / (2.0 * std::f64::consts::PI),
+            (around_tube + std::f64::consts::PI) / (2.0 * std::f64::consts::PI),
+        )
+    }
+}
+
+// a x² + b y² + c z² + d xy + e yz + f xz + g x + h y + i z + j = 0 around `center`, negative inside
+// `clip` keeps the part of the surface inside a box given relative to the center
+#[derive(Copy, Clone, Debug)]
+pub struct Quadric {
+    pub center: Point3D,
+    pub coefficients: [f64; 10],
+    pub clip: Option<Aabb>,
+    pub color: Vector3D,
+}
 
+impl Quadric {
+    pub fn new(center: Point3D, coefficients: [f64; 10], clip: Option<Aabb>, color: Vector3D) -> Quadric {
+        Quadric { center, coefficients, clip, color }
+    }
+    // the following shapes use Y as their axis and `radii` as the semi-axes
+    pub fn ellipsoid(center: Point3D, radii: Vector3D, color: Vector3D) -> Quadric {
+        let (a, b, c) = Self::inverse_squares(&radii);
+        let extent = Point3D::new(radii.x.abs(), radii.y.abs(), radii.z.abs());
+        let clip = Aabb::from_points(&[extent * -1.0, extent]);
+        Quadric::new(center, [a, b, c, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0], Some(clip), color)
+    }
+    // opens upward, reaching the radii in x and z at a height of radii.y
+    pub fn paraboloid(center: Point3D, radii: Vector3D, color: Vector3D) -> Quadric {
+        let (a, _, c) = Self::inverse_squares(&radii);
+        Quadric::new(center, [a, 0.0, c, 0.0, 0.0, 0.0, 0.0, -1.0 / radii.y, 0.0, 0.0], None, color)
+    }
+    pub fn hyperboloid_one_sheet(center: Point3D, radii: Vector3D, color: Vector3D) -> Quadric {
+        let (a, b, c) = Self::inverse_squares(&radii);
+        Quadric::new(center, [a, -b, c, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0], None, color)
+    }
+    pub fn hyperboloid_two_sheets(center: Point3D, radii: Vector3D, color: Vector3D) -> Quadric {
+        let (a, b, c) = Self::inverse_squares(&radii);
+        Quadric::new(center, [a, -b, c, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0], None, color)
+    }
+    pub fn cone(center: Point3D, radii: Vector3D, color: Vector3D) -> Quadric {
+        let (a, b, c) = Self::inverse_squares(&radii);
+        Quadric::new(center, [a, -b, c, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], None, color)
+    }
+    fn inverse_squares(radii: &Vector3D) -> (f64, f64, f64) {
+        (1.0 / (radii.x * radii.x), 1.0 / (radii.y * radii.y), 1.0 / (radii.z * radii.z))
+    }
+    fn value(&self, p: &Vector3D) -> f64 {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coefficients;
+        a * p.x * p.x + b * p.y * p.y + c * p.z * p.z + d * p.x * p.y + e * p.y * p.z + f * p.x * p.z
+            + g * p.x + h * p.y + i * p.z + j
+    }
+    fn is_clipped(&self, point: &Point3D) -> bool {
+        match &self.clip {
+            Some(clip) => !clip.contains(&(Point3D::default() + (*point - self.center)), EPSILON),
+            None => false,
+        }
+    }
+    // roots of the ray equation and the inside intervals of the unclipped solid
+    fn solve(&self, ray: &Ray) -> (Vec<f64>, Vec<Interval>) {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients;
+        let o = ray.origin - self.center;
+        let r = ray.direction;
+        let qa = a * r.x * r.x + b * r.y * r.y + c * r.z * r.z + d * r.x * r.y + e * r.y * r.z + f * r.x * r.z;
+        let qb = 2.0 * (a * o.x * r.x + b * o.y * r.y + c * o.z * r.z)
+            + d * (o.x * r.y + o.y * r.x)
+            + e * (o.y * r.z + o.z * r.y)
+            + f * (o.x * r.z + o.z * r.x)
+            + g * r.x + h * r.y + i * r.z;
+        let qc = self.value(&o);
+        let roots = math::solve_quadratic(qa, qb, qc);
+        let intervals = match roots.as_slice() {
+            [] if qc < 0.0 => vec![Interval::new(f64::NEG_INFINITY, f64::INFINITY)],
+            [] => Vec::new(),
+            [t] if qb > 0.0 => vec![Interval::new(f64::NEG_INFINITY, *t)],
+            [t] => vec![Interval::new(*t, f64::INFINITY)],
+            [t0, t1, ..] if qa > 0.0 => vec![Interval::new(*t0, *t1)],
+            [t0, t1, ..] => vec![Interval::new(f64::NEG_INFINITY, *t0), Interval::new(*t1, f64::INFINITY)],
+        };
+        (roots, intervals)
+    }
+}
+
+impl Object for Quadric {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        self.solve(&ray)
+            .0
+            .into_iter()
+            .filter(|t| *t > EPSILON)
+            .map(|t| ray.origin + ray.direction * t)
+            .find(|point| !self.is_clipped(point))
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let intervals = self.solve(&ray).1;
+        let Some(clip) = &self.clip else {
+            return intervals;
+        };
+        let local_ray = Ray::new(Point3D::default() + (ray.origin - self.center), ray.direction);
+        let Some((clip_enter, clip_exit)) = clip.hit_range(&local_ray) else {
+            return Vec::new();
+        };
+        intervals
+            .into_iter()
+            .map(|interval| Interval::new(interval.enter.max(clip_enter), interval.exit.min(clip_exit)))
+            .filter(|interval| interval.enter <= interval.exit)
+            .collect()
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients;
+        let p = *hit_point - self.center;
+        Vector3D::new(
+            2.0 * a * p.x + d * p.y + f * p.z + g,
+            2.0 * b * p.y + d * p.x + e * p.z + h,
+            2.0 * c * p.z + e * p.y + f * p.x + i,
+        )
+        .normalize()
+    }
+    fn get_center(&self) -> Point3D {
+        self.center
+    }
+    fn bounds(&self) -> Aabb {
+        match &self.clip {
+            Some(clip) => {
+                let offset = self.center - Point3D::default();
+                Aabb::from_points(&[clip.min + offset, clip.max + offset])
+            }
+            None => Aabb::infinite(),
+        }
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+}
+
+// places an object with an arbitrary matrix, rays are brought into object space instead of moving the geometry
+// the wrapped object can be shared between many instances, `color` replaces its color and albedo when set,
+// its material is kept
+pub struct Transformed {
+    pub object: Rc<dyn Object>,
+    pub transform: Transform,
+    pub color: Option<Vector3D>,
+}
+
+impl Transformed {
+    pub fn new(object: Rc<dyn Object>, transform: Transform, color: Option<Vector3D>) -> Transformed {
+        Transformed { object, transform, color }
+    }
+    // the direction is not normalized so distances along the ray are the same in both spaces
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.transform.point_to_object(&ray.origin),
+            self.transform.vector_to_object(&ray.direction),
+        )
+    }
+}
+
+impl Object for Transformed {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let hit_point = self.object.hits(self.local_ray(&ray))?;
+        Some(self.transform.point_to_world(&hit_point))
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        self.object.intervals(self.local_ray(&ray))
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let local_normal = self.object.surface_normal(&self.transform.point_to_object(hit_point));
+        self.transform.normal_to_world(&local_normal)
+    }
+    fn get_center(&self) -> Point3D {
+        self.transform.point_to_world(&self.object.get_center())
+    }
+    fn bounds(&self) -> Aabb {
+        self.object.bounds().transformed(&self.transform.matrix)
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color.unwrap_or_else(|| self.object.get_color())
+    }
     fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D {
-        let radius_sq = self.radius.powi(2);
-        let distance_sq = light_dir.length();
-        let factor = 4.0 * std::f64::consts::PI * radius_sq / distance_sq;
-        self.color * factor
+        if let Some(color) = self.color {
+            return color;
+        }
+        let local_light_dir = self.transform.vector_to_object(light_dir).normalize();
+        self.object.get_albedo(&self.transform.point_to_object(hit_point), &local_light_dir)
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        self.object.get_uv(&self.transform.point_to_object(hit_point))
+    }
+    fn shading(&self, hit_point: &Point3D, light_dir: &Vector3D, view_dir: &Vector3D) -> Option<(f64, f64)> {
+        self.object.shading(
+            &self.transform.point_to_object(hit_point),
+            &self.transform.vector_to_object(light_dir).normalize(),
+            &self.transform.vector_to_object(view_dir).normalize(),
+        )
+    }
+    fn get_material(&self, hit_point: &Point3D) -> Material {
+        self.object.get_material(&self.transform.point_to_object(hit_point))
+    }
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        let (point, normal) = self.object.sample_surface(u, v)?;
+        Some((self.transform.point_to_world(&point), self.transform.normal_to_world(&normal)))
+    }
+    // the matrix stretches a small patch of the surface by the area spanned by its two moved tangents
+    fn surface_density(&self, point: &Point3D) -> f64 {
+        let local_point = self.transform.point_to_object(point);
+        let (tangent, bitangent) = self.object.surface_normal(&local_point).orthonormal_basis();
+        let stretch = self
+            .transform
+            .matrix
+            .transform_vector(&tangent)
+            .cross(self.transform.matrix.transform_vector(&bitangent))
+            .length();
+        if stretch <= 0.0 {
+            return 0.0;
+        }
+        self.object.surface_density(&local_point) / stretch
+    }
+    fn visibility(&self) -> Visibility {
+        self.object.visibility()
+    }
+    fn name(&self) -> Option<&str> {
+        self.object.name()
+    }
+    fn is_convex(&self) -> bool {
+        self.object.is_convex()
+    }
+}
+
+// gives an object the material set on it in the scene file
+pub struct Materialized {
+    pub object: Box<dyn Object>,
+    pub material: Material,
+}
+
+impl Materialized {
+    pub fn new(object: Box<dyn Object>, material: Material) -> Materialized {
+        Materialized { object, material }
+    }
+}
+
+impl Object for Materialized {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        self.object.hits(ray)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        self.object.intervals(ray)
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        self.object.surface_normal(hit_point)
+    }
+    fn get_center(&self) -> Point3D {
+        self.object.get_center()
+    }
+    fn bounds(&self) -> Aabb {
+        self.object.bounds()
+    }
+    fn get_color(&self) -> Vector3D {
+        self.object.get_color()
+    }
+    fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D {
+        self.object.get_albedo(hit_point, light_dir)
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        self.object.get_uv(hit_point)
+    }
+    fn shading(&self, hit_point: &Point3D, light_dir: &Vector3D, view_dir: &Vector3D) -> Option<(f64, f64)> {
+        self.object.shading(hit_point, light_dir, view_dir)
+    }
+    fn get_material(&self, _hit_point: &Point3D) -> Material {
+        self.material
+    }
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        self.object.sample_surface(u, v)
+    }
+    fn surface_density(&self, point: &Point3D) -> f64 {
+        self.object.surface_density(point)
+    }
+    fn visibility(&self) -> Visibility {
+        self.object.visibility()
+    }
+    fn name(&self) -> Option<&str> {
+        self.object.name()
+    }
+    fn is_convex(&self) -> bool {
+        self.object.is_convex()
+    }
+}
+
+// gives an object the name and ray visibility set on it in the scene file
+pub struct Tagged {
+    pub object: Box<dyn Object>,
+    pub name: Option<String>,
+    pub visibility: Visibility,
+}
+
+impl Tagged {
+    pub fn new(object: Box<dyn Object>, name: Option<String>, visibility: Visibility) -> Tagged {
+        Tagged { object, name, visibility }
+    }
+}
+
+impl Object for Tagged {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        self.object.hits(ray)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        self.object.intervals(ray)
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        self.object.surface_normal(hit_point)
+    }
+    fn get_center(&self) -> Point3D {
+        self.object.get_center()
+    }
+    fn bounds(&self) -> Aabb {
+        self.object.bounds()
+    }
+    fn get_color(&self) -> Vector3D {
+        self.object.get_color()
+    }
+    fn get_albedo(&self, hit_point: &Point3D, light_dir: &Vector3D) -> Vector3D {
+        self.object.get_albedo(hit_point, light_dir)
+    }
+    fn get_uv(&self, hit_point: &Point3D) -> (f64, f64) {
+        self.object.get_uv(hit_point)
+    }
+    fn shading(&self, hit_point: &Point3D, light_dir: &Vector3D, view_dir: &Vector3D) -> Option<(f64, f64)> {
+        self.object.shading(hit_point, light_dir, view_dir)
+    }
+    fn get_material(&self, hit_point: &Point3D) -> Material {
+        self.object.get_material(hit_point)
+    }
+    fn sample_surface(&self, u: f64, v: f64) -> Option<(Point3D, Vector3D)> {
+        self.object.sample_surface(u, v)
+    }
+    fn surface_density(&self, point: &Point3D) -> f64 {
+        self.object.surface_density(point)
+    }
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    fn is_convex(&self) -> bool {
+        self.object.is_convex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    fn same(a: &Vector3D, b: &Vector3D) -> bool {
+        close(a.x, b.x) && close(a.y, b.y) && close(a.z, b.z)
+    }
+
+    fn ray(origin: (f64, f64, f64), direction: (f64, f64, f64)) -> Ray {
+        Ray::new(Point3D::new(origin.0, origin.1, origin.2), Vector3D::new(direction.0, direction.1, direction.2))
+    }
+
+    #[test]
+    fn highlights_peak_in_the_mirror_direction() {
+        let normal = Vector3D::new(0.0, 1.0, 0.0);
+        let light = Vector3D::new(1.0, 1.0, 0.0).normalize();
+        let mirror = Vector3D::new(-1.0, 1.0, 0.0).normalize();
+        let aside = Vector3D::new(-1.0, 2.0, 0.0).normalize();
+        let grazing = Vector3D::new(1.0, 0.01, 0.0).normalize();
+        for model in [SpecularModel::Phong, SpecularModel::BlinnPhong] {
+            let material = Material { specular: WHITE, specular_model: model, ..Material::default() };
+            assert!(close(material.highlight(&normal, &light, &mirror), 1.0));
+            assert!(material.highlight(&normal, &light, &aside) < 1.0);
+            assert!(close(material.highlight(&normal, &light, &grazing), 0.0));
+        }
+        // the half vector moves half as far as the mirror direction, so Blinn-Phong spreads wider
+        let phong = Material { specular_model: SpecularModel::Phong, ..Material::default() };
+        let blinn = Material { specular_model: SpecularModel::BlinnPhong, ..Material::default() };
+        assert!(blinn.highlight(&normal, &light, &aside) > phong.highlight(&normal, &light, &aside));
+    }
+
+    #[test]
+    fn box_slabs_and_normals() {
+        let cuboid = Cuboid::new(Point3D::new(0.0, 0.0, -5.0), Vector3D::new(2.0, 4.0, 2.0), Vector3D::default(), WHITE);
+        let intervals = cuboid.intervals(ray((0.0, 0.0, 0.0), (0.0, 0.0, -1.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!(close(intervals[0].enter, 4.0) && close(intervals[0].exit, 6.0));
+        let hit = cuboid.hits(ray((0.0, 0.0, 0.0), (0.0, 0.0, -1.0))).unwrap();
+        assert!(same(&cuboid.surface_normal(&hit), &Vector3D::new(0.0, 0.0, 1.0)));
+        let top = cuboid.hits(ray((0.5, 10.0, -5.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!(close(top.y, 2.0));
+        assert!(same(&cuboid.surface_normal(&top), &Vector3D::new(0.0, 1.0, 0.0)));
+        // from inside the box the exit is the hit
+        let inside = cuboid.hits(ray((0.0, 0.0, -5.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!(close(inside.x, 1.0));
+        assert!(cuboid.hits(ray((3.0, 0.0, 0.0), (0.0, 0.0, -1.0))).is_none());
+    }
+
+    #[test]
+    fn rotated_box() {
+        let cuboid = Cuboid::new(Point3D::default(), Vector3D::new(2.0, 2.0, 2.0), Vector3D::new(0.0, 45.0, 0.0), WHITE);
+        let hit = cuboid.hits(ray((5.0, 0.0, 0.0), (-1.0, 0.0, 0.0))).unwrap();
+        // the corner now faces the ray
+        assert!(close(hit.x, 2f64.sqrt()));
+        let bounds = cuboid.bounds();
+        assert!(close(bounds.max.x, 2f64.sqrt()) && close(bounds.max.y, 1.0));
+    }
+
+    #[test]
+    fn disk_hits_inside_its_radius() {
+        let disk = Disk::new(Point3D::new(0.0, 0.0, -2.0), Vector3D::new(0.0, 0.0, 2.0), 1.0, WHITE);
+        let hit = disk.hits(ray((0.5, 0.0, 0.0), (0.0, 0.0, -1.0))).unwrap();
+        assert!(close(hit.z, -2.0));
+        assert!(same(&disk.surface_normal(&hit), &Vector3D::new(0.0, 0.0, 1.0)));
+        assert!(disk.hits(ray((1.5, 0.0, 0.0), (0.0, 0.0, -1.0))).is_none());
+        assert!(disk.hits(ray((0.0, 0.0, 0.0), (1.0, 0.0, 0.0))).is_none());
+        assert!(disk.hits(ray((0.0, 0.0, -3.0), (0.0, 0.0, -1.0))).is_none());
+    }
+
+    #[test]
+    fn rectangle_hits_and_uv() {
+        let rectangle = Rectangle::new(
+            Point3D::new(0.0, 0.0, -1.0),
+            Vector3D::new(2.0, 0.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            WHITE,
+        );
+        let hit = rectangle.hits(ray((1.5, 0.25, 0.0), (0.0, 0.0, -1.0))).unwrap();
+        let (u, v) = rectangle.get_uv(&hit);
+        assert!(close(u, 0.75) && close(v, 0.25));
+        assert!(same(&rectangle.surface_normal(&hit), &Vector3D::new(0.0, 0.0, 1.0)));
+        let center = rectangle.get_center();
+        assert!(close(center.x, 1.0) && close(center.y, 0.5) && close(center.z, -1.0));
+        assert!(rectangle.hits(ray((2.5, 0.25, 0.0), (0.0, 0.0, -1.0))).is_none());
+        assert!(rectangle.hits(ray((1.0, -0.1, 0.0), (0.0, 0.0, -1.0))).is_none());
+    }
+
+    fn torus() -> Torus {
+        Torus::new(Point3D::default(), Vector3D::new(0.0, 1.0, 0.0), 2.0, 0.5, WHITE)
+    }
+
+    #[test]
+    fn torus_through_both_tubes() {
+        let intervals = torus().intervals(ray((-5.0, 0.0, 0.0), (1.0, 0.0, 0.0)));
+        assert_eq!(intervals.len(), 2);
+        assert!(close(intervals[0].enter, 2.5) && close(intervals[0].exit, 3.5));
+        assert!(close(intervals[1].enter, 6.5) && close(intervals[1].exit, 7.5));
+        let hit = torus().hits(ray((-5.0, 0.0, 0.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!(same(&torus().surface_normal(&hit), &Vector3D::new(-1.0, 0.0, 0.0)));
+        // down the hole
+        assert!(torus().hits(ray((0.0, 5.0, 0.0), (0.0, -1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn torus_tangent_ray_keeps_its_exit() {
+        // grazes the top of the tube at x = -2 and x = 2, and crosses nothing else
+        let intervals = torus().intervals(ray((-5.0, 0.5, 0.0), (1.0, 0.0, 0.0)));
+        assert!(intervals.iter().all(|interval| interval.exit - interval.enter < 1e-3));
+        // touches the inner rim at x = 0, in the middle of the only span
+        let intervals = torus().intervals(ray((-5.0, 0.0, 1.5), (1.0, 0.0, 0.0)));
+        assert_eq!(intervals.len(), 1, "{:?}", intervals);
+        assert!(close(intervals[0].enter, 3.0) && close(intervals[0].exit, 7.0));
+    }
+
+    #[test]
+    fn torus_far_away_and_large() {
+        let torus = Torus::new(Point3D::new(0.0, 0.0, -1e4), Vector3D::new(0.0, 0.0, 1.0), 1000.0, 1.0, WHITE);
+        let hit = torus.hits(ray((1000.0, 0.0, 0.0), (0.0, 0.0, -1.0))).unwrap();
+        assert!((hit.z + 1e4 - 1.0).abs() < 1e-6, "{:?}", hit);
+        let intervals = torus.intervals(ray((-2000.0, 0.0, -1e4), (1.0, 0.0, 0.0)));
+        assert_eq!(intervals.len(), 2);
+        assert!((intervals[0].enter - 999.0).abs() < 1e-6 && (intervals[1].exit - 3001.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn transformed_sphere_becomes_an_ellipsoid() {
+        let matrix = Matrix4::translation(Vector3D::new(0.0, 0.0, -5.0)) * Matrix4::scaling(Vector3D::new(2.0, 1.0, 1.0));
+        let sphere: Rc<dyn Object> = Rc::new(Sphere::new(Point3D::default(), 1.0, WHITE));
+        let ellipsoid = Transformed::new(sphere, Transform::new(matrix).unwrap(), None);
+        let side = ellipsoid.hits(ray((-10.0, 0.0, -5.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!(close(side.x, -2.0));
+        assert!(same(&ellipsoid.surface_normal(&side), &Vector3D::new(-1.0, 0.0, 0.0)));
+        // distances along the ray are kept, so intervals are in world units
+        let intervals = ellipsoid.intervals(ray((-10.0, 0.0, -5.0), (1.0, 0.0, 0.0)));
+        assert!(close(intervals[0].enter, 8.0) && close(intervals[0].exit, 12.0));
+        // the gradient of x² / 4 + y² at (sqrt 2, sqrt 0.5), leaning towards the flat side
+        let point = Point3D::new(2f64.sqrt(), 0.5f64.sqrt(), -5.0);
+        let expected = Vector3D::new(1.0, 2.0, 0.0).normalize();
+        assert!(same(&ellipsoid.surface_normal(&point), &expected));
+        let bounds = ellipsoid.bounds();
+        assert!(close(bounds.min.x, -2.0) && close(bounds.max.x, 2.0) && close(bounds.min.z, -6.0));
+    }
+
+    #[test]
+    fn ellipsoid_hits_and_normals() {
+        let ellipsoid = Quadric::ellipsoid(Point3D::new(0.0, 0.0, -5.0), Vector3D::new(2.0, 1.0, 1.0), WHITE);
+        let intervals = ellipsoid.intervals(ray((-10.0, 0.0, -5.0), (1.0, 0.0, 0.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!(close(intervals[0].enter, 8.0) && close(intervals[0].exit, 12.0));
+        let top = ellipsoid.hits(ray((0.0, 10.0, -5.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!(close(top.y, 1.0));
+        assert!(same(&ellipsoid.surface_normal(&top), &Vector3D::new(0.0, 1.0, 0.0)));
+        let bounds = ellipsoid.bounds();
+        assert!(close(bounds.min.x, -2.0) && close(bounds.max.z, -4.0));
+    }
+
+    #[test]
+    fn open_quadrics() {
+        // y = x² + z², a vertical ray from inside the bowl leaves it through the bottom
+        let paraboloid = Quadric::paraboloid(Point3D::default(), Vector3D::new(1.0, 1.0, 1.0), WHITE);
+        let intervals = paraboloid.intervals(ray((0.0, 5.0, 0.0), (0.0, -1.0, 0.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!(intervals[0].enter == f64::NEG_INFINITY && close(intervals[0].exit, 5.0));
+        let side = paraboloid.hits(ray((-5.0, 1.0, 0.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!(close(side.x, -1.0));
+        assert!(same(&paraboloid.surface_normal(&side), &Vector3D::new(-2.0, -1.0, 0.0).normalize()));
+        // the solid side of the hyperboloid is its throat, which a ray down the axis never leaves
+        let hyperboloid = Quadric::hyperboloid_one_sheet(Point3D::default(), Vector3D::new(1.0, 1.0, 1.0), WHITE);
+        assert!(hyperboloid.hits(ray((0.0, 10.0, 0.0), (0.0, -1.0, 0.0))).is_none());
+        let down = hyperboloid.intervals(ray((0.0, 10.0, 0.0), (0.0, -1.0, 0.0)));
+        assert!(down.len() == 1 && down[0].enter == f64::NEG_INFINITY && down[0].exit == f64::INFINITY);
+        let across = hyperboloid.intervals(ray((-5.0, 0.0, 0.0), (1.0, 0.0, 0.0)));
+        assert_eq!(across.len(), 1);
+        assert!(close(across[0].enter, 4.0) && close(across[0].exit, 6.0));
+        // the throat widens away from its waist
+        let high = hyperboloid.intervals(ray((-5.0, 2.0, 0.0), (1.0, 0.0, 0.0)));
+        assert!(close(high[0].enter, 5.0 - 5f64.sqrt()) && close(high[0].exit, 5.0 + 5f64.sqrt()));
+        let sheets = Quadric::hyperboloid_two_sheets(Point3D::default(), Vector3D::new(1.0, 1.0, 1.0), WHITE);
+        let hit = sheets.hits(ray((0.0, 10.0, 0.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!(close(hit.y, 1.0));
+        let cone = Quadric::cone(Point3D::default(), Vector3D::new(1.0, 1.0, 1.0), WHITE);
+        let hit = cone.hits(ray((-5.0, 2.0, 0.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!(close(hit.x, -2.0));
+    }
+
+    #[test]
+    fn clipped_quadric() {
+        let clip = Aabb::from_points(&[Point3D::new(-5.0, 0.0, -5.0), Point3D::new(5.0, 2.0, 5.0)]);
+        let cone = Quadric::new(Point3D::default(), [1.0, -1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], Some(clip), WHITE);
+        // the lower nappe is cut away
+        assert!(cone.hits(ray((-5.0, -1.0, 0.0), (1.0, 0.0, 0.0))).is_none());
+        let hit = cone.hits(ray((-5.0, 1.0, 0.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!(close(hit.x, -1.0));
+        // a vertical ray enters the cone at the top of the clip box and leaves through its side
+        let intervals = cone.intervals(ray((0.5, 10.0, 0.0), (0.0, -1.0, 0.0)));
+        assert_eq!(intervals.len(), 1);
+        assert!(close(intervals[0].enter, 8.0) && close(intervals[0].exit, 9.5));
     }
 }
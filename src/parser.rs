@@ -1,6 +1,6 @@
-use std::{fs::File, env, io::Read};
+use std::{collections::HashMap, error::Error, fs::File, env, io::Read, rc::Rc};
 
-use crate::{object::{Object, Sphere, Plane, Cylinder}, math::{Point3D, Vector3D}, light::{Light, PointLight, DirectionalLight}, raytracer::{Camera, Rectangle3D}};
+use crate::{bezier::{self, BezierPatch}, bvh::Aabb, object::{Material, Materialized, Object, SpecularModel, Tagged, Visibility, Sphere, Plane, Cylinder, Cuboid, Disk, Rectangle, Torus, Quadric, Transformed}, mesh::Mesh, fractal::{Fractal, FractalKind}, heightfield::Heightfield, metaball::{Metaball, Metaballs}, pointcloud::{PointCloud, Splat}, sdf::{Sdf, SdfNode}, voxel::{self, VoxelGrid}, csg::{Csg, CsgOperation}, environment::Environment, sky::{self, Sky}, curve::{CurveBasis, CurveShape, Curves, Strand}, math::{Matrix4, Point3D, Transform, Vector3D}, light::{AmbientLight, AreaLight, AreaShape, Attenuation, Light, LightLinking, Lighting, Linked, PointLight, DirectionalLight, SpotLight}, raytracer::{Camera, Rectangle3D}};
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 
@@ -10,7 +10,8 @@ pub struct Parser {
     pub objects: Vec<Box<dyn Object>>,
     pub width_height: (u32, u32),
     pub lights: Vec<Box<dyn Light>>,
-
+    pub lighting: Lighting,
+    pub environment: Option<Environment>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,12 +28,16 @@ struct SphereData {
     z: f64,
     r: f64,
     color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
 }
 #[derive(Serialize, Deserialize, Debug)]
 struct PlaneData {
     axis: String,
     position: i32,
     color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -43,220 +48,1533 @@ struct CylinderData {
     radius: f64,
     axis: String,
     color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+#[derive(Serialize, Deserialize, Debug)]
+struct BoxData {
+    x: f64,
+    y: f64,
+    z: f64,
+    width: f64,
+    height: f64,
+    depth: f64,
+    #[serde(default)]
+    rotation: Vector3D,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DiskData {
+    x: f64,
+    y: f64,
+    z: f64,
+    normal: Vector3D,
+    radius: f64,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RectangleData {
+    x: f64,
+    y: f64,
+    z: f64,
+    u: Vector3D,
+    v: Vector3D,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TorusData {
+    x: f64,
+    y: f64,
+    z: f64,
+    axis: Vector3D,
+    major_radius: f64,
+    minor_radius: f64,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MeshData {
+    file: String,
+    #[serde(default)]
+    x: f64,
+    #[serde(default)]
+    y: f64,
+    #[serde(default)]
+    z: f64,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum QuadricKind {
+    Ellipsoid,
+    Paraboloid,
+    HyperboloidOneSheet,
+    HyperboloidTwoSheets,
+    Cone,
+}
+
+// either a named shape with its semi-axes or the raw coefficients a..j
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum QuadricShapeData {
+    Named { kind: QuadricKind, radii: Vector3D },
+    Coefficients { coefficients: [f64; 10] },
+}
+
+#[derive(Debug, Deserialize)]
+struct ClipData {
+    min: Point3D,
+    max: Point3D,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuadricData {
+    x: f64,
+    y: f64,
+    z: f64,
+    #[serde(flatten)]
+    shape: QuadricShapeData,
+    clip: Option<ClipData>,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+fn default_splat() -> Splat {
+    Splat::Sphere
+}
+
+// `radius` is used for the points the file gives no radius to
+#[derive(Debug, Deserialize)]
+struct PointCloudData {
+    file: String,
+    #[serde(default)]
+    x: f64,
+    #[serde(default)]
+    y: f64,
+    #[serde(default)]
+    z: f64,
+    radius: f64,
+    #[serde(default = "default_splat")]
+    splat: Splat,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+fn default_basis() -> CurveBasis {
+    CurveBasis::CatmullRom
+}
+
+fn default_curve_shape() -> CurveShape {
+    CurveShape::Tube
+}
+
+fn default_subdivisions() -> usize {
+    8
+}
+
+fn default_specular() -> f64 {
+    0.3
+}
+
+fn default_shininess() -> f64 {
+    40.0
 }
+
+// strands from a text `file` or listed inline as [x, y, z, width] control points, moved by `x`, `y`, `z`
+#[derive(Debug, Deserialize)]
+struct CurveData {
+    file: Option<String>,
+    strands: Option<Vec<Vec<[f64; 4]>>>,
+    #[serde(default)]
+    x: f64,
+    #[serde(default)]
+    y: f64,
+    #[serde(default)]
+    z: f64,
+    #[serde(default = "default_basis")]
+    basis: CurveBasis,
+    #[serde(default = "default_curve_shape")]
+    shape: CurveShape,
+    #[serde(default = "default_subdivisions")]
+    subdivisions: usize,
+    color: Color,
+    tip_color: Option<Color>,
+    #[serde(default = "default_specular")]
+    specular: f64,
+    #[serde(default = "default_shininess")]
+    shininess: f64,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+fn default_tolerance() -> f64 {
+    1e-3
+}
+
+// patches from a .bpt `file` or listed inline as 16 control points each, moved by `x`, `y`, `z`
+#[derive(Debug, Deserialize)]
+struct BezierData {
+    file: Option<String>,
+    patches: Option<Vec<[[f64; 3]; 16]>>,
+    #[serde(default)]
+    x: f64,
+    #[serde(default)]
+    y: f64,
+    #[serde(default)]
+    z: f64,
+    #[serde(default = "default_tolerance")]
+    tolerance: f64,
+    #[serde(default)]
+    flip_normals: bool,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+// `x`, `y`, `z` is the lowest corner, `height` the elevation of a white pixel
+#[derive(Debug, Deserialize)]
+struct HeightfieldData {
+    file: String,
+    x: f64,
+    y: f64,
+    z: f64,
+    width: f64,
+    height: f64,
+    depth: f64,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+// a MagicaVoxel `file`, or `dimensions` with `voxels` given as [x, y, z, palette index] and a `palette`
+// starting at index 1, `x`, `y`, `z` being the lowest corner
+#[derive(Debug, Deserialize)]
+struct VoxelData {
+    file: Option<String>,
+    #[serde(default)]
+    model: usize,
+    dimensions: Option<[usize; 3]>,
+    voxels: Option<Vec<[usize; 4]>>,
+    palette: Option<Vec<Color>>,
+    x: f64,
+    y: f64,
+    z: f64,
+    voxel_size: f64,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+struct MetaballData {
+    x: f64,
+    y: f64,
+    z: f64,
+    radius: f64,
+    #[serde(default = "one")]
+    weight: f64,
+    color: Option<Color>,
+}
+
+// the field is sampled every 1/16 of the smallest radius along a ray, so lobes thinner
+// than that, where weights barely reach the threshold, can be missed
+#[derive(Debug, Deserialize)]
+struct MetaballsData {
+    balls: Vec<MetaballData>,
+    threshold: f64,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+fn default_iterations() -> usize {
+    10
+}
+
+fn default_escape_radius() -> f64 {
+    2.0
+}
+
+#[derive(Debug, Deserialize)]
+struct FractalData {
+    x: f64,
+    y: f64,
+    z: f64,
+    kind: FractalKind,
+    #[serde(default = "one")]
+    scale: f64,
+    #[serde(default = "default_iterations")]
+    iterations: usize,
+    #[serde(default = "default_escape_radius")]
+    escape_radius: f64,
+    color: Color,
+    trap_color: Option<Color>,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Debug, Deserialize)]
+struct SdfData {
+    x: f64,
+    y: f64,
+    z: f64,
+    shape: SdfNode,
+    color: Color,
+    #[serde(flatten)]
+    transform: TransformData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum ScaleData {
+    Uniform(f64),
+    Axes(Vector3D),
+}
+
+// optional placement shared by every object: scale, then rotate (degrees), then translate, all around
+// the object's own center, then the raw row-major matrix, applied as given in world space
+// the material options, name and visibility ride along since every object takes them too
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TransformData {
+    translate: Option<Vector3D>,
+    rotate: Option<Vector3D>,
+    scale: Option<ScaleData>,
+    matrix: Option<[f64; 16]>,
+    #[serde(flatten)]
+    material: MaterialData,
+    #[serde(flatten)]
+    tag: TagData,
+}
+
+// `name` is what lights refer to in their include / exclude lists, `visibility` turns off
+// some of "camera", "shadows", "reflections" and "receive_shadows"
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TagData {
+    name: Option<String>,
+    visibility: Option<Visibility>,
+}
+
+// glossy highlights, objects without a `specular` color stay matte
+// an `emission` color turns a sphere, disk, rectangle or mesh into a light, `emission_intensity` 1 makes a white
+// emission as bright as white
+// a `transmission` color lets light of that tint through, white for clear glass, so the object casts a colored shadow
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct MaterialData {
+    specular: Option<Color>,
+    shininess: Option<f64>,
+    specular_model: Option<SpecularModel>,
+    emission: Option<Color>,
+    emission_intensity: Option<f64>,
+    transmission: Option<Color>,
+}
+
+impl MaterialData {
+    fn to_material(&self) -> Result<Option<Material>, Box<dyn Error>> {
+        if self.specular.is_none() && self.emission.is_none() && self.transmission.is_none() {
+            return Ok(None);
+        }
+        // the highlight is raised to the shininess, a negative one would blow up where it fades out
+        if let Some(shininess) = self.shininess.filter(|shininess| *shininess < 0.0) {
+            return Err(format!("Material shininess can not be negative, found {}", shininess).into());
+        }
+        if let Some(intensity) = self.emission_intensity.filter(|intensity| *intensity < 0.0) {
+            return Err(format!("Material emission intensity can not be negative, found {}", intensity).into());
+        }
+        let default = Material::default();
+        Ok(Some(Material::new(
+            self.specular.as_ref().map_or(default.specular, Color::to_vector),
+            self.shininess.unwrap_or(default.shininess),
+            self.specular_model.unwrap_or(default.specular_model),
+            self.emission
+                .as_ref()
+                .map_or(default.emission, |color| color.to_vector() / 255.0 * self.emission_intensity.unwrap_or(1.0)),
+            self.transmission.as_ref().map_or(default.transmission, |color| color.to_vector() / 255.0),
+        )))
+    }
+}
+
+impl TransformData {
+    fn to_matrix(&self) -> Matrix4 {
+        self.raw_matrix() * self.placement_matrix()
+    }
+    // scale, rotate and translate
+    fn placement_matrix(&self) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        if let Some(scale) = &self.scale {
+            let factors = match scale {
+                ScaleData::Uniform(factor) => Vector3D::new(*factor, *factor, *factor),
+                ScaleData::Axes(factors) => *factors,
+            };
+            matrix = Matrix4::scaling(factors) * matrix;
+        }
+        if let Some(angles) = self.rotate {
+            matrix = Matrix4::rotation(angles) * matrix;
+        }
+        if let Some(offset) = self.translate {
+            matrix = Matrix4::translation(offset) * matrix;
+        }
+        matrix
+    }
+    fn raw_matrix(&self) -> Matrix4 {
+        let Some(values) = self.matrix else {
+            return Matrix4::identity();
+        };
+        let mut rows = [[0.0; 4]; 4];
+        for (index, value) in values.iter().enumerate() {
+            rows[index / 4][index % 4] = *value;
+        }
+        Matrix4::new(rows)
+    }
+    fn apply(&self, object: Box<dyn Object>) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object: Box<dyn Object> = match self.material.to_material()? {
+            Some(material) => {
+                check_emission(object.as_ref(), &material)?;
+                Box::new(Materialized::new(object, material))
+            }
+            None => object,
+        };
+        let matrix = self.to_matrix();
+        let object: Box<dyn Object> = if matrix == Matrix4::identity() {
+            object
+        } else {
+            let center = object.get_center() - Point3D::default();
+            let placement = Matrix4::translation(center) * self.placement_matrix() * Matrix4::translation(center * -1.0);
+            let pivoted = self.raw_matrix() * placement;
+            let transform = Transform::new(pivoted).ok_or("Object transform is not invertible")?;
+            Box::new(Transformed::new(Rc::from(object), transform, None))
+        };
+        if self.tag.name.is_none() && self.tag.visibility.is_none() {
+            return Ok(object);
+        }
+        Ok(Box::new(Tagged::new(object, self.tag.name.clone(), self.tag.visibility.unwrap_or_default())))
+    }
+}
+
+// emissive objects only light the scene when points can be picked on their surface, see Scene::build_bvh
+fn check_emission(object: &dyn Object, material: &Material) -> Result<(), Box<dyn Error>> {
+    if material.is_emissive() && object.sample_surface(0.5, 0.5).is_none() {
+        return Err("Only spheres, disks, rectangles and meshes can be emissive".into());
+    }
+    Ok(())
+}
+
+impl Color {
+    fn to_vector(&self) -> Vector3D {
+        Vector3D {
+            x: self.r as f64,
+            y: self.g as f64,
+            z: self.b as f64,
+        }
+    }
+}
+
+impl SphereData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Sphere::new(
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.r,
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl PlaneData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Plane::new(self.axis, self.position, self.color.to_vector());
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl CylinderData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Cylinder::new(
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.radius,
+            self.axis.as_str(),
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl BoxData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        if [self.width, self.height, self.depth].iter().any(|size| *size <= 0.0) {
+            return Err(format!("Box sizes have to be above 0, found {}, {}, {}", self.width, self.height, self.depth).into());
+        }
+        let object = Cuboid::new(
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            Vector3D::new(self.width, self.height, self.depth),
+            self.rotation,
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl DiskData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        if self.normal.length() == 0.0 {
+            return Err("Disk normal can not be zero".into());
+        }
+        if self.radius <= 0.0 {
+            return Err(format!("Disk radius has to be above 0, found {}", self.radius).into());
+        }
+        let object = Disk::new(
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.normal,
+            self.radius,
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl RectangleData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        // zero or parallel edges leave no surface to hit and no normal
+        if self.u.cross(self.v).length() == 0.0 {
+            return Err("Rectangle edges can not be zero or parallel".into());
+        }
+        let object = Rectangle::new(
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.u,
+            self.v,
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl TorusData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        if self.axis.length() == 0.0 {
+            return Err("Torus axis can not be zero".into());
+        }
+        if self.minor_radius <= 0.0 || self.major_radius < 0.0 {
+            return Err(format!(
+                "Torus needs a minor radius above 0 and a major radius of at least 0, found {} and {}",
+                self.minor_radius, self.major_radius
+            )
+            .into());
+        }
+        let object = Torus::new(
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.axis,
+            self.major_radius,
+            self.minor_radius,
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl MeshData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Mesh::load(
+            &self.file,
+            Vector3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.color.to_vector(),
+        )
+        ?;
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl QuadricData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let center = Point3D {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let color = self.color.to_vector();
+        let mut object = match self.shape {
+            QuadricShapeData::Named { kind, radii } => match kind {
+                QuadricKind::Ellipsoid => Quadric::ellipsoid(center, radii, color),
+                QuadricKind::Paraboloid => Quadric::paraboloid(center, radii, color),
+                QuadricKind::HyperboloidOneSheet => Quadric::hyperboloid_one_sheet(center, radii, color),
+                QuadricKind::HyperboloidTwoSheets => Quadric::hyperboloid_two_sheets(center, radii, color),
+                QuadricKind::Cone => Quadric::cone(center, radii, color),
+            },
+            QuadricShapeData::Coefficients { coefficients } => Quadric::new(center, coefficients, None, color),
+        };
+        if let Some(clip) = self.clip {
+            object.clip = Some(Aabb::from_points(&[clip.min, clip.max]));
+        }
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl PointCloudData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = PointCloud::load(
+            &self.file,
+            Vector3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.radius,
+            self.splat,
+            self.color.to_vector(),
+        )
+        ?;
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl CurveData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let mut strands = match &self.file {
+            Some(file) => Curves::read_strands(file)?,
+            None => Vec::new(),
+        };
+        for points in self.strands.unwrap_or_default() {
+            strands.push(Strand::new(
+                points.iter().map(|[x, y, z, _]| Point3D::new(*x, *y, *z)).collect(),
+                points.iter().map(|point| point[3]).collect(),
+            ));
+        }
+        let offset = Vector3D {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        for strand in strands.iter_mut() {
+            for point in strand.points.iter_mut() {
+                *point = *point + offset;
+            }
+        }
+        let object = Curves::new(
+            &strands,
+            self.basis,
+            self.shape,
+            self.subdivisions,
+            self.color.to_vector(),
+            self.tip_color.map(|color| color.to_vector()),
+            self.specular,
+            self.shininess,
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl BezierData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let mut patches = match &self.file {
+            Some(file) => bezier::read_bpt(file)?,
+            None => Vec::new(),
+        };
+        for points in self.patches.unwrap_or_default() {
+            patches.push(BezierPatch::new(points.map(|[x, y, z]| Point3D::new(x, y, z))));
+        }
+        let object = bezier::tessellate(
+            &patches,
+            self.tolerance,
+            self.flip_normals,
+            Vector3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl HeightfieldData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Heightfield::load(
+            &self.file,
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            Vector3D {
+                x: self.width,
+                y: self.height,
+                z: self.depth,
+            },
+            self.color.to_vector(),
+        )
+        ?;
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl VoxelData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let corner = Point3D {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let color = self.color.to_vector();
+        let object = match &self.file {
+            Some(file) => VoxelGrid::load_vox(file, self.model, corner, self.voxel_size, color)
+                ?,
+            None => {
+                let dimensions = self.dimensions.ok_or("Voxel grid needs a file or dimensions")?;
+                let mut cells = vec![0; voxel::cell_count(dimensions)?];
+                for [x, y, z, index] in self.voxels.unwrap_or_default() {
+                    if x >= dimensions[0] || y >= dimensions[1] || z >= dimensions[2] {
+                        continue;
+                    }
+                    cells[x + y * dimensions[0] + z * dimensions[0] * dimensions[1]] = index as u8;
+                }
+                let palette = std::iter::once(color)
+                    .chain(self.palette.unwrap_or_default().into_iter().map(|color| color.to_vector()))
+                    .collect();
+                VoxelGrid::new(corner, self.voxel_size, dimensions, cells, palette, color)
+            }
+        };
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl MetaballsData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        // with a threshold of 0 the surface would be the edge of every radius, and empty balls have no field
+        if self.threshold <= 0.0 {
+            return Err(format!("Metaballs need a threshold above 0, found {}", self.threshold).into());
+        }
+        if let Some(ball) = self.balls.iter().find(|ball| ball.radius <= 0.0) {
+            return Err(format!("Metaball radius has to be above 0, found {}", ball.radius).into());
+        }
+        let balls = self
+            .balls
+            .into_iter()
+            .map(|ball| {
+                Metaball::new(
+                    Point3D {
+                        x: ball.x,
+                        y: ball.y,
+                        z: ball.z,
+                    },
+                    ball.radius,
+                    ball.weight,
+                    ball.color.map(|color| color.to_vector()),
+                )
+            })
+            .collect();
+        let object = Metaballs::new(balls, self.threshold, self.color.to_vector());
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl FractalData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Fractal::new(
+            self.kind,
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.scale,
+            self.iterations,
+            self.escape_radius,
+            self.color.to_vector(),
+            self.trap_color.map(|color| color.to_vector()),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+impl SdfData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let object = Sdf::new(
+            self.shape,
+            Point3D {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            },
+            self.color.to_vector(),
+        );
+        self.transform.apply(Box::new(object))
+    }
+}
+
+// a single primitive keyed by its type, e.g. { "sphere": { ... } }
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PrimitiveData {
+    Sphere(SphereData),
+    Plane(PlaneData),
+    Cylinder(CylinderData),
+    Box(BoxData),
+    Disk(DiskData),
+    Rectangle(RectangleData),
+    Torus(TorusData),
+    Quadric(QuadricData),
+    Mesh(MeshData),
+    Bezier(BezierData),
+    Points(PointCloudData),
+    Curves(CurveData),
+    Heightfield(HeightfieldData),
+    Voxels(VoxelData),
+    Metaballs(MetaballsData),
+    Sdf(SdfData),
+    Fractal(FractalData),
+}
+
+impl PrimitiveData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        match self {
+            PrimitiveData::Sphere(data) => data.build(),
+            PrimitiveData::Plane(data) => data.build(),
+            PrimitiveData::Cylinder(data) => data.build(),
+            PrimitiveData::Box(data) => data.build(),
+            PrimitiveData::Disk(data) => data.build(),
+            PrimitiveData::Rectangle(data) => data.build(),
+            PrimitiveData::Torus(data) => data.build(),
+            PrimitiveData::Quadric(data) => data.build(),
+            PrimitiveData::Mesh(data) => data.build(),
+            PrimitiveData::Bezier(data) => data.build(),
+            PrimitiveData::Points(data) => data.build(),
+            PrimitiveData::Curves(data) => data.build(),
+            PrimitiveData::Heightfield(data) => data.build(),
+            PrimitiveData::Voxels(data) => data.build(),
+            PrimitiveData::Metaballs(data) => data.build(),
+            PrimitiveData::Sdf(data) => data.build(),
+            PrimitiveData::Fractal(data) => data.build(),
+        }
+    }
+}
+
+// { "csg": "difference", "left": ..., "right": ... } where children are primitives or other csg nodes
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CsgData {
+    Node {
+        csg: CsgOperation,
+        left: Box<CsgData>,
+        right: Box<CsgData>,
+        #[serde(flatten)]
+        transform: TransformData,
+    },
+    Leaf(PrimitiveData),
+}
+
+impl CsgData {
+    fn build(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        match self {
+            CsgData::Node { csg, left, right, transform } => {
+                let (left, right) = (left.build()?, right.build()?);
+                // the tree is not sampled as a light, a glowing part of it would light nothing
+                let glows = |part: &dyn Object| {
+                    part.sample_surface(0.5, 0.5)
+                        .is_some_and(|(point, _)| part.get_material(&point).is_emissive())
+                };
+                if glows(left.as_ref()) || glows(right.as_ref()) {
+                    return Err("Csg parts can not be emissive".into());
+                }
+                transform.apply(Box::new(Csg::new(csg, left, right)))
+            }
+            CsgData::Leaf(primitive) => primitive.build(),
+        }
+    }
+}
+
+// scene graph node: a group of nodes, an instance of a named definition, or a csg tree / primitive
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum NodeData {
+    Group {
+        group: Vec<NodeData>,
+        color: Option<Color>,
+        #[serde(flatten)]
+        transform: TransformData,
+    },
+    Instance {
+        instance: String,
+        color: Option<Color>,
+        #[serde(flatten)]
+        transform: TransformData,
+    },
+    Leaf(CsgData),
+}
+
+// what a group or instance hands down to every leaf below it
+#[derive(Clone, Default)]
+struct Inherited {
+    color: Option<Vector3D>,
+    material: Option<Material>,
+    name: Option<String>,
+    visibility: Option<Visibility>,
+}
+
+impl Inherited {
+    fn from_node(color: &Option<Color>, transform: &TransformData) -> Result<Inherited, Box<dyn Error>> {
+        Ok(Inherited {
+            color: color.as_ref().map(Color::to_vector),
+            material: transform.material.to_material()?,
+            name: transform.tag.name.clone(),
+            visibility: transform.tag.visibility,
+        })
+    }
+    // the outer settings win and the inner ones fill what they leave unset,
+    // except visibility where a kind of ray turned off at any level stays off
+    fn over(&self, inner: &Inherited) -> Inherited {
+        let visibility = match (self.visibility, inner.visibility) {
+            (Some(outer), Some(inner)) => Some(outer.and(&inner)),
+            (outer, inner) => outer.or(inner),
+        };
+        Inherited {
+            color: self.color.or(inner.color),
+            material: self.material.or(inner.material),
+            name: self.name.clone().or_else(|| inner.name.clone()),
+            visibility,
+        }
+    }
+}
+
+// a leaf of the scene graph with the transform, color, material and tag inherited from its parents
+struct Placed {
+    object: Rc<dyn Object>,
+    matrix: Matrix4,
+    inherited: Inherited,
+}
+
+impl Placed {
+    fn into_object(self) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        let transform = Transform::new(self.matrix).ok_or("Group transform is not invertible")?;
+        let Inherited { color, material, name, visibility } = self.inherited;
+        let object: Box<dyn Object> = Box::new(Transformed::new(self.object, transform, color));
+        let object: Box<dyn Object> = match material {
+            Some(material) => {
+                check_emission(object.as_ref(), &material)?;
+                Box::new(Materialized::new(object, material))
+            }
+            None => object,
+        };
+        if name.is_none() && visibility.is_none() {
+            return Ok(object);
+        }
+        // a leaf keeps its own name where its parents set none
+        let name = name.or_else(|| object.name().map(String::from));
+        let visibility = visibility.map_or_else(|| object.visibility(), |visibility| visibility.and(&object.visibility()));
+        Ok(Box::new(Tagged::new(object, name, visibility)))
+    }
+}
+
+// flattens groups and instances, each definition is built once and its geometry shared by every instance
+struct SceneGraph {
+    definitions: HashMap<String, NodeData>,
+    built: HashMap<String, Rc<Vec<Placed>>>,
+    building: Vec<String>,
+}
+
+impl SceneGraph {
+    fn new(definitions: HashMap<String, NodeData>) -> SceneGraph {
+        SceneGraph { definitions, built: HashMap::new(), building: Vec::new() }
+    }
+
+    // transforms compose from the outside in, the outermost color, material and name win
+    fn resolve(&mut self, node: NodeData, matrix: Matrix4, inherited: &Inherited, placed: &mut Vec<Placed>) -> Result<(), Box<dyn Error>> {
+        match node {
+            NodeData::Group { group, color, transform } => {
+                let matrix = matrix * transform.to_matrix();
+                let inherited = inherited.over(&Inherited::from_node(&color, &transform)?);
+                for child in group {
+                    self.resolve(child, matrix, &inherited, placed)?;
+                }
+            }
+            NodeData::Instance { instance, color, transform } => {
+                let matrix = matrix * transform.to_matrix();
+                let inherited = inherited.over(&Inherited::from_node(&color, &transform)?);
+                for leaf in self.definition(&instance)?.iter() {
+                    placed.push(Placed {
+                        object: Rc::clone(&leaf.object),
+                        matrix: matrix * leaf.matrix,
+                        inherited: inherited.over(&leaf.inherited),
+                    });
+                }
+            }
+            NodeData::Leaf(leaf) => placed.push(Placed { object: Rc::from(leaf.build()?), matrix, inherited: inherited.clone() }),
+        }
+        Ok(())
+    }
+
+    fn definition(&mut self, name: &str) -> Result<Rc<Vec<Placed>>, Box<dyn Error>> {
+        if let Some(built) = self.built.get(name) {
+            return Ok(Rc::clone(built));
+        }
+        if self.building.iter().any(|building| building == name) {
+            return Err(format!("Definition '{}' instances itself", name).into());
+        }
+        let node = self
+            .definitions
+            .remove(name)
+            .ok_or_else(|| format!("Unknown definition '{}'", name))?;
+        self.building.push(name.to_string());
+        let mut placed = Vec::new();
+        self.resolve(node, Matrix4::identity(), &Inherited::default(), &mut placed)?;
+        self.building.pop();
+        let placed = Rc::new(placed);
+        self.built.insert(name.to_string(), Rc::clone(&placed));
+        Ok(placed)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct PrimitivesData {
     spheres: Option<Vec<SphereData>>,
     planes: Option<Vec<PlaneData>>,
     cylinders: Option<Vec<CylinderData>>,
+    boxes: Option<Vec<BoxData>>,
+    disks: Option<Vec<DiskData>>,
+    rectangles: Option<Vec<RectangleData>>,
+    tori: Option<Vec<TorusData>>,
+    quadrics: Option<Vec<QuadricData>>,
+    meshes: Option<Vec<MeshData>>,
+    beziers: Option<Vec<BezierData>>,
+    point_clouds: Option<Vec<PointCloudData>>,
+    curves: Option<Vec<CurveData>>,
+    heightfields: Option<Vec<HeightfieldData>>,
+    voxels: Option<Vec<VoxelData>>,
+    metaballs: Option<Vec<MetaballsData>>,
+    sdfs: Option<Vec<SdfData>>,
+    fractals: Option<Vec<FractalData>>,
+    csg: Option<Vec<CsgData>>,
+    definitions: Option<HashMap<String, NodeData>>,
+    groups: Option<Vec<NodeData>>,
+    instances: Option<Vec<NodeData>>,
+}
+
+// equirectangular map around the scene, `rotation` in degrees around Y,
+// `lighting` false shows it behind the objects without lighting them
+#[derive(Debug, Deserialize)]
+struct EnvironmentData {
+    file: String,
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default = "one")]
+    intensity: f64,
+    #[serde(default = "default_light_samples")]
+    samples: usize,
+    #[serde(default = "default_lighting")]
+    lighting: bool,
+}
+
+// where the sun stands: `elevation` and `azimuth` in degrees (0 north toward -Z, 90 east toward +X),
+// or a "YYYY-MM-DD" `date` with a local solar `time` in hours and a `latitude`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SunPositionData {
+    Angles { elevation: f64, azimuth: f64 },
+    Date { date: String, time: f64, latitude: f64 },
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+// procedural daylight used as the environment, with a directional sun of the matching color
+#[derive(Debug, Deserialize)]
+struct SkyData {
+    #[serde(flatten)]
+    sun: SunPositionData,
+    #[serde(default = "default_turbidity")]
+    turbidity: f64,
+    #[serde(default = "one")]
+    intensity: f64,
+    #[serde(default = "one")]
+    sun_intensity: f64,
+    #[serde(default = "default_light_samples")]
+    samples: usize,
+}
+
+impl SkyData {
+    fn sky(&self) -> Result<Sky, Box<dyn Error>> {
+        // 1 is air without any haze, below that the aerosol terms of the model turn negative
+        if self.turbidity < 1.0 {
+            return Err(format!("Sky turbidity has to be at least 1, found {}", self.turbidity).into());
+        }
+        let (elevation, azimuth) = match &self.sun {
+            SunPositionData::Angles { elevation, azimuth } => (*elevation, *azimuth),
+            SunPositionData::Date { date, time, latitude } => {
+                let day = sky::day_of_year(date).ok_or_else(|| format!("Invalid sky date '{}'", date))?;
+                sky::solar_position(day, *time, *latitude)
+            }
+        };
+        Ok(Sky::new(self.turbidity, elevation, azimuth))
+    }
+}
+
+fn default_lighting() -> bool {
+    true
+}
+
+impl EnvironmentData {
+    fn build(self) -> Result<Environment, Box<dyn Error>> {
+        let mut environment = Environment::load(&self.file, self.rotation, self.intensity, self.samples)?;
+        environment.lighting = self.lighting;
+        Ok(environment)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct LightData {
+    ambient: Option<AmbientData>,
+    diffuse: Option<f64>,
+    specular: Option<f64>,
+    emitter_samples: Option<usize>,
     point: Option<Vec<PointLightData>>,
     directional: Option<Vec<DirectionalLightData>>,
+    spot: Option<Vec<SpotLightData>>,
+    area: Option<Vec<AreaLightData>>,
+}
+
+// a bare number is the intensity of a white ambient light
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AmbientData {
+    Intensity(f64),
+    Light { color: Color, intensity: f64 },
+}
+
+impl AmbientData {
+    fn to_light(&self) -> AmbientLight {
+        match self {
+            AmbientData::Intensity(intensity) => AmbientLight::new(Vector3D::new(255.0, 255.0, 255.0), *intensity),
+            AmbientData::Light { color, intensity } => AmbientLight::new(color.to_vector(), *intensity),
+        }
+    }
+}
+
+fn white() -> Color {
+    Color { r: 255, g: 255, b: 255 }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FalloffKind {
+    None,
+    InverseSquare,
 }
 
+// "inverse_square", "none" or the constant, linear and quadratic terms, missing terms being those of "none"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AttenuationData {
+    Named(FalloffKind),
+    Coefficients {
+        #[serde(default = "one")]
+        constant: f64,
+        #[serde(default)]
+        linear: f64,
+        #[serde(default)]
+        quadratic: f64,
+    },
+}
+
+impl AttenuationData {
+    fn to_attenuation(&self) -> Result<Attenuation, Box<dyn Error>> {
+        match self {
+            AttenuationData::Named(FalloffKind::None) => Ok(Attenuation::default()),
+            AttenuationData::Named(FalloffKind::InverseSquare) => Ok(Attenuation::inverse_square()),
+            AttenuationData::Coefficients { constant, linear, quadratic } => {
+                // negative terms would make the light brighter or flip its sign at some distance
+                if [constant, linear, quadratic].iter().any(|term| **term < 0.0) {
+                    return Err(format!(
+                        "Light attenuation terms can not be negative, found {}, {}, {}",
+                        constant, linear, quadratic
+                    )
+                    .into());
+                }
+                if *constant + *linear + *quadratic == 0.0 {
+                    return Err("Light attenuation needs at least one term above 0".into());
+                }
+                Ok(Attenuation::new(*constant, *linear, *quadratic))
+            }
+        }
+    }
+}
+
+// lights do not fade with distance unless an `attenuation` is given
 #[derive(Debug, Deserialize)]
 struct PointLightData {
     x: f64,
     y: f64,
     z: f64,
+    #[serde(default = "white")]
     color: Color,
+    #[serde(default = "one")]
     intensity: f64,
+    attenuation: Option<AttenuationData>,
+    #[serde(flatten)]
+    linking: LinkingData,
 }
 
+fn default_outer_angle() -> f64 {
+    30.0
+}
+
+// `direction` is where the beam points, the angles are half angles of the cone in degrees
 #[derive(Debug, Deserialize)]
-struct DirectionalLightData {
+struct SpotLightData {
     x: f64,
     y: f64,
     z: f64,
+    direction: Vector3D,
+    #[serde(default)]
+    inner_angle: f64,
+    #[serde(default = "default_outer_angle")]
+    outer_angle: f64,
+    #[serde(default = "white")]
     color: Color,
+    #[serde(default = "one")]
     intensity: f64,
+    attenuation: Option<AttenuationData>,
+    #[serde(flatten)]
+    linking: LinkingData,
 }
 
-impl Parser {
-    pub fn new() -> Parser {
-        Parser {
-            camera: Self::get_camera_data(),
-            objects: Self::get_objects_data(),
-            width_height: Self::get_height_width_data(),
-            lights: Self::get_lights_data(),
-        }
-    }
+fn default_light_samples() -> usize {
+    16
+}
 
+// `x`, `y`, `z` is the center of the light, `shape` one of "rectangle" (with edges `u` and `v`, lit toward u x v),
+// "disk" (with `normal` and `radius`, lit toward the normal) or "sphere" (with `radius`)
+#[derive(Debug, Deserialize)]
+struct AreaLightData {
+    x: f64,
+    y: f64,
+    z: f64,
+    #[serde(flatten)]
+    shape: AreaShape,
+    #[serde(default = "white")]
+    color: Color,
+    #[serde(default = "one")]
+    intensity: f64,
+    attenuation: Option<AttenuationData>,
+    #[serde(default = "default_light_samples")]
+    samples: usize,
+    #[serde(flatten)]
+    linking: LinkingData,
+}
 
-    pub fn get_camera_data() -> Camera {
-        let args: Vec<String> = env::args().collect();
-        let mut file = File::open(args.get(1).expect("error")).expect("Failed to open file");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Failed to read file");
-        let json: Value = serde_json::from_str(&contents).expect("err");
-        let cam2 = Self::parse_camera(&json);
-        let cam = cam2.unwrap();
-        return cam;
-    }
+#[derive(Debug, Deserialize)]
+struct DirectionalLightData {
+    x: f64,
+    y: f64,
+    z: f64,
+    #[serde(default = "white")]
+    color: Color,
+    #[serde(default = "one")]
+    intensity: f64,
+    #[serde(flatten)]
+    linking: LinkingData,
+}
 
-    pub fn get_objects_data() -> Vec<Box<dyn Object>> {
-        let args: Vec<String> = env::args().collect();
-        let mut file = File::open(args.get(1).expect("error")).expect("Failed to open file");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Failed to read file");
+// names of the objects a light is limited to or kept away from
+#[derive(Debug, Default, Deserialize)]
+struct LinkingData {
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+}
 
-        let json_data: serde_json::Value = serde_json::from_str(&contents).expect("err");
-        let primitives_json_str = json_data["primitives"].to_string();
+impl LinkingData {
+    fn apply(self, light: Box<dyn Light>) -> Box<dyn Light> {
+        let linking = LightLinking::new(self.include, self.exclude.unwrap_or_default());
+        if linking.is_empty() {
+            return light;
+        }
+        Box::new(Linked::new(light, linking))
+    }
+}
 
-        let data: PrimitivesData = serde_json::from_str(&primitives_json_str).unwrap();
-        let spheres2: Vec<Sphere> = data
-            .spheres
-            .unwrap_or(Vec::new())
-            .into_iter()
-            .map(|sphere_data| {
-                Sphere::new(
-                    Point3D {
-                        x: sphere_data.x,
-                        y: sphere_data.y,
-                        z: sphere_data.z,
-                    },
-                    sphere_data.r,
-                    Vector3D {
-                        x: sphere_data.color.r as f64,
-                        y: sphere_data.color.g as f64,
-                        z: sphere_data.color.b as f64,
-                    },
-                )
-            })
-            .collect();
-        let planes2: Vec<Plane> = data
-            .planes
-            .unwrap_or(Vec::new())
-            .into_iter()
-            .map(|plane_data| {
-                Plane::new(
-                    plane_data.axis,
-                    plane_data.position,
-                    Vector3D {
-                        x: plane_data.color.r as f64,
-                        y: plane_data.color.g as f64,
-                        z: plane_data.color.b as f64,
-                    },
-                )
-            })
-            .collect();
-        let cylinders2: Vec<Cylinder> = data
-            .cylinders
-            .unwrap_or(Vec::new())
-            .into_iter()
-            .map(|cylinder_data| {
-                Cylinder::new(
-                    Point3D {
-                        x: cylinder_data.x,
-                        y: cylinder_data.y,
-                        z: cylinder_data.z,
-                    },
-                    cylinder_data.radius,
-                    cylinder_data.axis.as_str(),
-                    Vector3D {
-                        x: cylinder_data.color.r as f64,
-                        y: cylinder_data.color.g as f64,
-                        z: cylinder_data.color.b as f64,
-                    },
-                )
-            })
-            .collect();
-        let objects: Vec<Box<dyn Object>> = planes2
-            .into_iter()
-            .map(|plane| Box::new(plane) as Box<dyn Object>)
-            .chain(
-                spheres2
-                    .into_iter()
-                    .map(|sphere| Box::new(sphere) as Box<dyn Object>),
-            )
-            .chain(
-                cylinders2
-                    .into_iter()
-                    .map(|cylinder| Box::new(cylinder) as Box<dyn Object>),
-            )
-            .collect();
-        return objects;
+impl Parser {
+    // the scene file is read and parsed once, every section is built from the same document
+    pub fn new() -> Result<Parser, Box<dyn Error>> {
+        let json = Self::read_scene()?;
+        Ok(Parser {
+            camera: Self::parse_camera(&json)?,
+            objects: Self::parse_objects(&json)?,
+            width_height: Self::parse_width_height(&json)?,
+            lights: Self::parse_lights(&json)?,
+            lighting: Self::parse_lighting(&json["lights"])?,
+            environment: Self::parse_environment(&json)?,
+        })
     }
 
-    pub fn get_lights_data() -> Vec<Box<dyn Light>> {
+    // the scene file given on the command line
+    fn read_scene() -> Result<Value, Box<dyn Error>> {
         let args: Vec<String> = env::args().collect();
-        let mut file = File::open(args.get(1).expect("error")).expect("Failed to open file");
+        let mut file = File::open(args.get(1).ok_or("Missing scene file")?)?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Failed to read file");
+        file.read_to_string(&mut contents)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
 
-        let json_data: serde_json::Value = serde_json::from_str(&contents).expect("err");
-        let primitives_json_str = json_data["lights"].to_string();
+    pub fn parse_objects(json_data: &Value) -> Result<Vec<Box<dyn Object>>, Box<dyn Error>> {
+        let data = PrimitivesData::deserialize(&json_data["primitives"])?;
+        let mut objects: Vec<Box<dyn Object>> = Vec::new();
+        for plane_data in data.planes.unwrap_or_default() {
+            objects.push(plane_data.build()?);
+        }
+        for sphere_data in data.spheres.unwrap_or_default() {
+            objects.push(sphere_data.build()?);
+        }
+        for cylinder_data in data.cylinders.unwrap_or_default() {
+            objects.push(cylinder_data.build()?);
+        }
+        for box_data in data.boxes.unwrap_or_default() {
+            objects.push(box_data.build()?);
+        }
+        for disk_data in data.disks.unwrap_or_default() {
+            objects.push(disk_data.build()?);
+        }
+        for rectangle_data in data.rectangles.unwrap_or_default() {
+            objects.push(rectangle_data.build()?);
+        }
+        for torus_data in data.tori.unwrap_or_default() {
+            objects.push(torus_data.build()?);
+        }
+        for quadric_data in data.quadrics.unwrap_or_default() {
+            objects.push(quadric_data.build()?);
+        }
+        for mesh_data in data.meshes.unwrap_or_default() {
+            objects.push(mesh_data.build()?);
+        }
+        for bezier_data in data.beziers.unwrap_or_default() {
+            objects.push(bezier_data.build()?);
+        }
+        for point_cloud_data in data.point_clouds.unwrap_or_default() {
+            objects.push(point_cloud_data.build()?);
+        }
+        for curve_data in data.curves.unwrap_or_default() {
+            objects.push(curve_data.build()?);
+        }
+        for heightfield_data in data.heightfields.unwrap_or_default() {
+            objects.push(heightfield_data.build()?);
+        }
+        for voxel_data in data.voxels.unwrap_or_default() {
+            objects.push(voxel_data.build()?);
+        }
+        for metaballs_data in data.metaballs.unwrap_or_default() {
+            objects.push(metaballs_data.build()?);
+        }
+        for sdf_data in data.sdfs.unwrap_or_default() {
+            objects.push(sdf_data.build()?);
+        }
+        for fractal_data in data.fractals.unwrap_or_default() {
+            objects.push(fractal_data.build()?);
+        }
+        for csg_data in data.csg.unwrap_or_default() {
+            objects.push(csg_data.build()?);
+        }
+        let mut graph = SceneGraph::new(data.definitions.unwrap_or_default());
+        let mut placed: Vec<Placed> = Vec::new();
+        let nodes = data.groups.unwrap_or_default().into_iter().chain(data.instances.unwrap_or_default());
+        for node in nodes {
+            graph.resolve(node, Matrix4::identity(), &Inherited::default(), &mut placed)?;
+        }
+        for leaf in placed {
+            objects.push(leaf.into_object()?);
+        }
+        Ok(objects)
+    }
 
-        let data: LightData = serde_json::from_str(&primitives_json_str).unwrap();
-        let point: Vec<PointLight> = data
-            .point
-            .unwrap_or(Vec::new())
-            .into_iter()
-            .map(|point_data| {
-                PointLight::new(
-                    Point3D {
-                        x: point_data.x,
-                        y: point_data.y,
-                        z: point_data.z,
-                    },
-                    Vector3D {
-                        x: point_data.color.r as f64,
-                        y: point_data.color.g as f64,
-                        z: point_data.color.b as f64,
-                    },
-                    point_data.intensity,
-                )
-            })
-            .collect();
-        let direct: Vec<DirectionalLight> = data
-            .directional
-            .unwrap_or(Vec::new())
-            .into_iter()
-            .map(|direct_data| {
-                DirectionalLight::new(
-                    Vector3D {
-                        x: direct_data.x,
-                        y: direct_data.y,
-                        z: direct_data.z,
-                    },
-                    Vector3D {
-                        x: direct_data.color.r as f64,
-                        y: direct_data.color.g as f64,
-                        z: direct_data.color.b as f64,
-                    },
-                    direct_data.intensity,
+    // every light of the scene, the sun of the "sky" entry included
+    pub fn parse_lights(json_data: &Value) -> Result<Vec<Box<dyn Light>>, Box<dyn Error>> {
+        let data = LightData::deserialize(&json_data["lights"])?;
+        let sky: Option<SkyData> = serde_json::from_value(json_data["sky"].clone())?;
+        let sun = match &sky {
+            Some(sky) => Some(sky.sky()?.sun_light(sky.sun_intensity)),
+            None => None,
+        };
+        let point = data.point.unwrap_or_default().into_iter().map(|point_data| -> Result<Box<dyn Light>, Box<dyn Error>> {
+            let light = PointLight::new(
+                Point3D {
+                    x: point_data.x,
+                    y: point_data.y,
+                    z: point_data.z,
+                },
+                Vector3D {
+                    x: point_data.color.r as f64,
+                    y: point_data.color.g as f64,
+                    z: point_data.color.b as f64,
+                },
+                point_data.intensity,
+                point_data
+                    .attenuation
+                    .as_ref()
+                    .map_or_else(|| Ok(Attenuation::default()), AttenuationData::to_attenuation)?,
+            );
+            Ok(point_data.linking.apply(Box::new(light)))
+        });
+        let direct = data.directional.unwrap_or_default().into_iter().map(|direct_data| -> Result<Box<dyn Light>, Box<dyn Error>> {
+            let light = DirectionalLight::new(
+                Vector3D {
+                    x: direct_data.x,
+                    y: direct_data.y,
+                    z: direct_data.z,
+                },
+                Vector3D {
+                    x: direct_data.color.r as f64,
+                    y: direct_data.color.g as f64,
+                    z: direct_data.color.b as f64,
+                },
+                direct_data.intensity,
+            );
+            Ok(direct_data.linking.apply(Box::new(light)))
+        });
+        let spot = data.spot.unwrap_or_default().into_iter().map(|spot_data| -> Result<Box<dyn Light>, Box<dyn Error>> {
+            if spot_data.direction.length() == 0.0 {
+                return Err("Spot light direction can not be zero".into());
+            }
+            if spot_data.inner_angle > spot_data.outer_angle {
+                return Err(format!(
+                    "Spot light inner angle can not be wider than its outer angle, found {} and {}",
+                    spot_data.inner_angle, spot_data.outer_angle
                 )
-            })
-            .collect();
-        let objects: Vec<Box<dyn Light>> = point
-            .into_iter()
-            .map(|plane| Box::new(plane) as Box<dyn Light>)
-            .chain(
-                direct
-                    .into_iter()
-                    .map(|sphere| Box::new(sphere) as Box<dyn Light>),
-            )
-            .collect();
-        return objects;
+                .into());
+            }
+            let light = SpotLight::new(
+                Point3D {
+                    x: spot_data.x,
+                    y: spot_data.y,
+                    z: spot_data.z,
+                },
+                spot_data.direction,
+                spot_data.inner_angle,
+                spot_data.outer_angle,
+                spot_data.color.to_vector(),
+                spot_data.intensity,
+                spot_data
+                    .attenuation
+                    .as_ref()
+                    .map_or_else(|| Ok(Attenuation::default()), AttenuationData::to_attenuation)?,
+            );
+            Ok(spot_data.linking.apply(Box::new(light)))
+        });
+        let area = data.area.unwrap_or_default().into_iter().map(|area_data| -> Result<Box<dyn Light>, Box<dyn Error>> {
+            if let AreaShape::Rectangle { u, v } = area_data.shape {
+                if u.cross(v).length() == 0.0 {
+                    return Err("Rectangle area light edges can not be parallel".into());
+                }
+            }
+            if let AreaShape::Disk { normal, .. } = area_data.shape {
+                if normal.length() == 0.0 {
+                    return Err("Disk area light normal can not be zero".into());
+                }
+            }
+            if let AreaShape::Disk { radius, .. } | AreaShape::Sphere { radius } = area_data.shape {
+                if radius <= 0.0 {
+                    return Err(format!("Area light radius has to be above 0, found {}", radius).into());
+                }
+            }
+            let light = AreaLight::new(
+                Point3D {
+                    x: area_data.x,
+                    y: area_data.y,
+                    z: area_data.z,
+                },
+                area_data.shape,
+                area_data.color.to_vector(),
+                area_data.intensity,
+                area_data
+                    .attenuation
+                    .as_ref()
+                    .map_or_else(|| Ok(Attenuation::default()), AttenuationData::to_attenuation)?,
+                area_data.samples,
+            );
+            Ok(area_data.linking.apply(Box::new(light)))
+        });
+        point
+            .chain(direct)
+            .chain(spot)
+            .chain(area)
+            .chain(sun.into_iter().map(|sun| Ok(Box::new(sun) as Box<dyn Light>)))
+            .collect()
     }
 
-    pub fn get_height_width_data() -> (u32, u32) {
-        let args: Vec<String> = env::args().collect();
-        let mut file = File::open(args.get(1).expect("error")).expect("Failed to open file");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Failed to read file");
-        let json: Value = serde_json::from_str(&contents).expect("err");
-        let width_height = Self::parse_width_height(&json).expect("error");
-        return width_height;
+    // optional "environment" entry of the scene file, or else the "sky" baked into one
+    pub fn parse_environment(json_data: &Value) -> Result<Option<Environment>, Box<dyn Error>> {
+        let data: Option<EnvironmentData> = serde_json::from_value(json_data["environment"].clone())?;
+        if let Some(data) = data {
+            return Ok(Some(data.build()?));
+        }
+        let sky: Option<SkyData> = serde_json::from_value(json_data["sky"].clone())?;
+        match sky {
+            Some(sky) => Ok(Some(sky.sky()?.to_environment(sky.intensity, sky.samples))),
+            None => Ok(None),
+        }
     }
 
+    // ambient light and diffuse / specular weights, missing entries keep the scene lit as before
+    pub fn parse_lighting(lights: &Value) -> Result<Lighting, Box<dyn Error>> {
+        let data = LightData::deserialize(lights)?;
+        let default = Lighting::default();
+        let lighting = Lighting::new(
+            data.ambient.as_ref().map_or(default.ambient, AmbientData::to_light),
+            data.diffuse.unwrap_or(default.diffuse),
+            data.specular.unwrap_or(default.specular),
+            data.emitter_samples.unwrap_or(default.emitter_samples),
+        );
+        // a negative weight would take light away from the other terms
+        for (name, weight) in [("ambient", lighting.ambient.intensity), ("diffuse", lighting.diffuse), ("specular", lighting.specular)] {
+            if weight < 0.0 {
+                return Err(format!("Lighting {} can not be negative, found {}", name, weight).into());
+            }
+        }
+        Ok(lighting)
+    }
 
     pub fn parse_width_height(json: &Value) -> Result<(u32, u32), Box<dyn std::error::Error>> {
         let camera_json = json
@@ -306,4 +1624,301 @@ impl Parser {
 
         Ok(camera)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::Ray;
+    use serde_json::json;
+
+    fn ball(x: f64) -> Value {
+        json!({ "sphere": { "x": x, "y": 0.0, "z": 0.0, "r": 1.0, "color": { "r": 255, "g": 0, "b": 0 } } })
+    }
+
+    fn same(a: Vector3D, b: Vector3D) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    // every leaf of the given groups, resolved against the given definitions
+    fn resolve(definitions: Value, groups: Value) -> Result<Vec<Box<dyn Object>>, Box<dyn Error>> {
+        let mut graph = SceneGraph::new(serde_json::from_value(definitions).unwrap());
+        let nodes: Vec<NodeData> = serde_json::from_value(groups).unwrap();
+        let mut placed = Vec::new();
+        for node in nodes {
+            graph.resolve(node, Matrix4::identity(), &Inherited::default(), &mut placed)?;
+        }
+        placed.into_iter().map(Placed::into_object).collect()
+    }
+
+    #[test]
+    fn instances_share_and_move_definitions() {
+        let definitions = json!({ "pair": { "group": [ball(0.0), ball(3.0)] } });
+        let groups = json!([
+            { "instance": "pair" },
+            { "instance": "pair", "translate": { "x": 0.0, "y": 10.0, "z": 0.0 } },
+        ]);
+        let objects = resolve(definitions, groups).unwrap();
+        assert_eq!(objects.len(), 4);
+        assert_eq!(objects[3].get_center(), Point3D::new(3.0, 10.0, 0.0));
+    }
+
+    #[test]
+    fn groups_hand_their_settings_down() {
+        let groups = json!([{
+            "group": [
+                ball(0.0),
+                { "group": [ball(3.0)], "specular": { "r": 0, "g": 255, "b": 0 }, "name": "inner" },
+            ],
+            "color": { "r": 0, "g": 0, "b": 255 },
+            "specular": { "r": 255, "g": 255, "b": 255 },
+            "visibility": { "shadows": false },
+        }]);
+        let objects = resolve(json!({}), groups).unwrap();
+        let point = Point3D::new(0.0, 1.0, 0.0);
+        for object in &objects {
+            assert!(same(object.get_color(), Vector3D::new(0.0, 0.0, 255.0)));
+            assert!(same(object.get_material(&point).specular, Vector3D::new(255.0, 255.0, 255.0)));
+            assert!(!object.visibility().shadows && object.visibility().camera);
+        }
+        assert_eq!(objects[0].name(), None);
+        assert_eq!(objects[1].name(), Some("inner"));
+    }
+
+    #[test]
+    fn leaves_keep_what_their_parents_leave_unset() {
+        let mut leaf = ball(0.0);
+        leaf["sphere"]["name"] = json!("leaf");
+        leaf["sphere"]["visibility"] = json!({ "camera": false });
+        leaf["sphere"]["specular"] = json!({ "r": 10, "g": 10, "b": 10 });
+        let definitions = json!({ "single": { "group": [leaf] } });
+        let groups = json!([{ "instance": "single", "visibility": { "reflections": false } }]);
+        let objects = resolve(definitions, groups).unwrap();
+        assert_eq!(objects[0].name(), Some("leaf"));
+        assert!(!objects[0].visibility().reflections && !objects[0].visibility().camera);
+        assert!(objects[0].visibility().shadows);
+        let point = Point3D::new(0.0, 1.0, 0.0);
+        assert!(same(objects[0].get_material(&point).specular, Vector3D::new(10.0, 10.0, 10.0)));
+    }
+
+    #[test]
+    fn bad_instances_are_errors() {
+        let cyclic = json!({ "a": { "group": [{ "instance": "b" }] }, "b": { "instance": "a" } });
+        let error = resolve(cyclic, json!([{ "instance": "a" }])).err().unwrap();
+        assert!(error.to_string().contains("instances itself"));
+        let error = resolve(json!({}), json!([{ "instance": "missing" }])).err().unwrap();
+        assert_eq!(error.to_string(), "Unknown definition 'missing'");
+    }
+
+    fn metaballs(threshold: f64, radius: f64) -> MetaballsData {
+        serde_json::from_value(json!({
+            "balls": [{ "x": 0.0, "y": 0.0, "z": 0.0, "radius": radius }],
+            "threshold": threshold,
+            "color": { "r": 255, "g": 255, "b": 255 },
+        }))
+        .unwrap()
+    }
+
+    fn primitive(data: Value) -> Result<Box<dyn Object>, Box<dyn Error>> {
+        serde_json::from_value::<PrimitiveData>(data).unwrap().build()
+    }
+
+    #[test]
+    fn flat_shapes_and_boxes_need_a_surface() {
+        let white = json!({ "r": 255, "g": 255, "b": 255 });
+        let x = json!({ "x": 1.0, "y": 0.0, "z": 0.0 });
+        let zero = json!({ "x": 0.0, "y": 0.0, "z": 0.0 });
+        let disk = |normal: &Value, radius: f64| {
+            primitive(json!({ "disk": { "x": 0.0, "y": 0.0, "z": 0.0, "normal": normal, "radius": radius, "color": white } }))
+        };
+        assert!(disk(&x, 1.0).is_ok());
+        assert_eq!(disk(&zero, 1.0).err().unwrap().to_string(), "Disk normal can not be zero");
+        assert_eq!(disk(&x, 0.0).err().unwrap().to_string(), "Disk radius has to be above 0, found 0");
+        let rectangle = |u: &Value, v: &Value| {
+            primitive(json!({ "rectangle": { "x": 0.0, "y": 0.0, "z": 0.0, "u": u, "v": v, "color": white } }))
+        };
+        assert!(rectangle(&x, &json!({ "x": 0.0, "y": 1.0, "z": 0.0 })).is_ok());
+        let error = rectangle(&x, &json!({ "x": -2.0, "y": 0.0, "z": 0.0 })).err().unwrap();
+        assert_eq!(error.to_string(), "Rectangle edges can not be zero or parallel");
+        assert!(rectangle(&x, &zero).is_err());
+        let cuboid = |depth: f64| {
+            primitive(json!({ "box": { "x": 0.0, "y": 0.0, "z": 0.0, "width": 1.0, "height": 1.0, "depth": depth, "color": white } }))
+        };
+        assert!(cuboid(1.0).is_ok());
+        assert_eq!(cuboid(-1.0).err().unwrap().to_string(), "Box sizes have to be above 0, found 1, 1, -1");
+    }
+
+    #[test]
+    fn tori_need_an_axis_and_a_tube() {
+        let torus = |axis: Value, major_radius: f64, minor_radius: f64| {
+            primitive(json!({ "torus": {
+                "x": 0.0, "y": 0.0, "z": 0.0, "axis": axis, "major_radius": major_radius,
+                "minor_radius": minor_radius, "color": { "r": 255, "g": 255, "b": 255 }
+            } }))
+        };
+        let up = json!({ "x": 0.0, "y": 1.0, "z": 0.0 });
+        assert!(torus(up.clone(), 2.0, 0.5).is_ok());
+        let error = torus(json!({ "x": 0.0, "y": 0.0, "z": 0.0 }), 2.0, 0.5).err().unwrap();
+        assert_eq!(error.to_string(), "Torus axis can not be zero");
+        let error = torus(up.clone(), 2.0, 0.0).err().unwrap();
+        assert_eq!(error.to_string(), "Torus needs a minor radius above 0 and a major radius of at least 0, found 0 and 2");
+        assert!(torus(up, -1.0, 0.5).is_err());
+    }
+
+    #[test]
+    fn only_sampled_shapes_can_be_emissive() {
+        let glow = json!({ "emission": { "r": 255, "g": 255, "b": 255 } });
+        let with_glow = |mut primitive: Value| {
+            let data = primitive.as_object_mut().unwrap().values_mut().next().unwrap();
+            data.as_object_mut().unwrap().extend(glow.as_object().unwrap().clone());
+            primitive
+        };
+        let lamp = primitive(with_glow(ball(0.0))).unwrap();
+        assert!(lamp.get_material(&Point3D::new(1.0, 0.0, 0.0)).is_emissive());
+        let torus = json!({ "torus": {
+            "x": 0.0, "y": 0.0, "z": 0.0, "axis": { "x": 0.0, "y": 1.0, "z": 0.0 },
+            "major_radius": 2.0, "minor_radius": 0.5, "color": { "r": 255, "g": 255, "b": 255 }
+        } });
+        let error = primitive(with_glow(torus.clone())).err().unwrap();
+        assert_eq!(error.to_string(), "Only spheres, disks, rectangles and meshes can be emissive");
+        let csg: CsgData =
+            serde_json::from_value(json!({ "csg": "union", "left": with_glow(ball(0.0)), "right": ball(1.0) })).unwrap();
+        assert_eq!(csg.build().err().unwrap().to_string(), "Csg parts can not be emissive");
+        let groups = json!([{ "group": [torus], "emission": { "r": 255, "g": 255, "b": 255 } }]);
+        let error = resolve(json!({}), groups).err().unwrap();
+        assert_eq!(error.to_string(), "Only spheres, disks, rectangles and meshes can be emissive");
+    }
+
+    #[test]
+    fn metaballs_take_positive_parameters() {
+        assert!(metaballs(0.5, 1.0).build().unwrap().hits(Ray::new(Point3D::new(0.0, 0.0, 5.0), Vector3D::new(0.0, 0.0, -1.0))).is_some());
+    }
+
+    #[test]
+    fn metaballs_reject_a_zero_threshold() {
+        let error = metaballs(0.0, 1.0).build().err().unwrap();
+        assert_eq!(error.to_string(), "Metaballs need a threshold above 0, found 0");
+    }
+
+    #[test]
+    fn metaballs_reject_empty_balls() {
+        let error = metaballs(0.5, 0.0).build().err().unwrap();
+        assert_eq!(error.to_string(), "Metaball radius has to be above 0, found 0");
+    }
+
+    #[test]
+    fn attenuation_reads_names_and_terms() {
+        let named: AttenuationData = serde_json::from_value(json!("inverse_square")).unwrap();
+        assert_eq!(named.to_attenuation().unwrap().factor(2.0), 0.25);
+        let terms: AttenuationData = serde_json::from_value(json!({ "linear": 1.0 })).unwrap();
+        assert_eq!(terms.to_attenuation().unwrap().factor(3.0), 0.25);
+    }
+
+    #[test]
+    fn attenuation_rejects_bad_terms() {
+        let terms: AttenuationData = serde_json::from_value(json!({ "constant": 1.0, "linear": -0.5 })).unwrap();
+        let error = terms.to_attenuation().err().unwrap();
+        assert_eq!(error.to_string(), "Light attenuation terms can not be negative, found 1, -0.5, 0");
+        let terms: AttenuationData = serde_json::from_value(json!({ "constant": 0.0 })).unwrap();
+        let error = terms.to_attenuation().err().unwrap();
+        assert_eq!(error.to_string(), "Light attenuation needs at least one term above 0");
+    }
+
+    #[test]
+    fn spot_lights_need_a_direction() {
+        let lights = json!({ "lights": { "spot": [{ "x": 0.0, "y": 4.0, "z": 0.0, "direction": { "x": 0.0, "y": 0.0, "z": 0.0 } }] } });
+        let error = Parser::parse_lights(&lights).err().unwrap();
+        assert_eq!(error.to_string(), "Spot light direction can not be zero");
+        let lights = json!({ "lights": { "spot": [{ "x": 0.0, "y": 4.0, "z": 0.0, "direction": { "x": 0.0, "y": -1.0, "z": 0.0 } }] } });
+        assert_eq!(Parser::parse_lights(&lights).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn spot_lights_keep_their_inner_angle_within_the_outer_one() {
+        let spot = |inner_angle: f64, outer_angle: f64| {
+            let down = json!({ "x": 0.0, "y": -1.0, "z": 0.0 });
+            let light = json!({ "x": 0.0, "y": 4.0, "z": 0.0, "direction": down, "inner_angle": inner_angle, "outer_angle": outer_angle });
+            Parser::parse_lights(&json!({ "lights": { "spot": [light] } }))
+        };
+        assert!(spot(20.0, 20.0).is_ok());
+        let error = spot(40.0, 20.0).err().unwrap();
+        assert_eq!(error.to_string(), "Spot light inner angle can not be wider than its outer angle, found 40 and 20");
+    }
+
+    #[test]
+    fn area_lights_need_a_surface() {
+        let area = |shape: Value| {
+            let mut light = json!({ "x": 0.0, "y": 4.0, "z": 0.0 });
+            light.as_object_mut().unwrap().extend(shape.as_object().unwrap().clone());
+            Parser::parse_lights(&json!({ "lights": { "area": [light] } }))
+        };
+        let x = json!({ "x": 1.0, "y": 0.0, "z": 0.0 });
+        let zero = json!({ "x": 0.0, "y": 0.0, "z": 0.0 });
+        let error = area(json!({ "shape": "rectangle", "u": x, "v": x })).err().unwrap();
+        assert_eq!(error.to_string(), "Rectangle area light edges can not be parallel");
+        let error = area(json!({ "shape": "disk", "normal": zero, "radius": 1.0 })).err().unwrap();
+        assert_eq!(error.to_string(), "Disk area light normal can not be zero");
+        let error = area(json!({ "shape": "sphere", "radius": -1.0 })).err().unwrap();
+        assert_eq!(error.to_string(), "Area light radius has to be above 0, found -1");
+        assert_eq!(area(json!({ "shape": "disk", "normal": x, "radius": 1.0 })).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn skies_need_clear_air_and_a_real_date() {
+        let sky = |sky: Value| Parser::parse_lights(&json!({ "lights": {}, "sky": sky }));
+        let error = sky(json!({ "elevation": 30.0, "azimuth": 0.0, "turbidity": 0.5 })).err().unwrap();
+        assert_eq!(error.to_string(), "Sky turbidity has to be at least 1, found 0.5");
+        let error = sky(json!({ "date": "2024-13-01", "time": 12.0, "latitude": 45.0 })).err().unwrap();
+        assert_eq!(error.to_string(), "Invalid sky date '2024-13-01'");
+        // the sun of the sky is the only light
+        assert_eq!(sky(json!({ "date": "2024-06-21", "time": 12.0, "latitude": 45.0 })).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn lighting_reads_ambient_and_weights() {
+        let lighting = Parser::parse_lighting(&json!({ "ambient": 0.4, "diffuse": 0.6, "point": [] })).unwrap();
+        assert_eq!(lighting.ambient.intensity, 0.4);
+        assert_eq!(lighting.ambient.color.x, 255.0);
+        assert_eq!((lighting.diffuse, lighting.specular), (0.6, 1.0));
+        let ambient = json!({ "ambient": { "color": { "r": 0, "g": 0, "b": 255 }, "intensity": 0.2 } });
+        assert_eq!(Parser::parse_lighting(&ambient).unwrap().ambient.color.z, 255.0);
+        // missing entries leave the scene lit as before
+        assert_eq!(Parser::parse_lighting(&json!({})).unwrap().ambient.intensity, 0.0);
+        let error = Parser::parse_lighting(&json!({ "specular": -1.0 })).err().unwrap();
+        assert_eq!(error.to_string(), "Lighting specular can not be negative, found -1");
+    }
+
+    #[test]
+    fn materials_reject_negative_shininess() {
+        let material: MaterialData =
+            serde_json::from_value(json!({ "specular": { "r": 255, "g": 255, "b": 255 }, "shininess": -4.0 })).unwrap();
+        let error = material.to_material().err().unwrap();
+        assert_eq!(error.to_string(), "Material shininess can not be negative, found -4");
+    }
+
+    #[test]
+    fn materials_reject_negative_emission() {
+        let material: MaterialData =
+            serde_json::from_value(json!({ "emission": { "r": 255, "g": 200, "b": 100 }, "emission_intensity": -2.0 })).unwrap();
+        let error = material.to_material().err().unwrap();
+        assert_eq!(error.to_string(), "Material emission intensity can not be negative, found -2");
+    }
+
+    fn sphere_at(x: f64, transform: Value) -> Box<dyn Object> {
+        let mut data = json!({ "x": x, "y": 0.0, "z": 0.0, "r": 1.0, "color": { "r": 255, "g": 255, "b": 255 } });
+        data.as_object_mut().unwrap().extend(transform.as_object().unwrap().clone());
+        serde_json::from_value::<SphereData>(data).unwrap().build().unwrap()
+    }
+
+    #[test]
+    fn placement_turns_around_the_center_and_matrices_around_the_origin() {
+        // a quarter turn around Y keeps the ball in place, the same turn as a matrix swings it round the origin
+        let turned = sphere_at(3.0, json!({ "rotate": { "x": 0.0, "y": 90.0, "z": 0.0 } }));
+        assert!(same(turned.get_center() - Point3D::default(), Vector3D::new(3.0, 0.0, 0.0)));
+        let matrix = json!({ "matrix": [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0] });
+        let swung = sphere_at(3.0, matrix);
+        assert!(same(swung.get_center() - Point3D::default(), Vector3D::new(0.0, 0.0, -3.0)));
+        // the matrix comes after the placement
+        let both = json!({ "translate": { "x": 1.0, "y": 0.0, "z": 0.0 }, "matrix": [2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 1.0] });
+        assert!(same(sphere_at(3.0, both).get_center() - Point3D::default(), Vector3D::new(8.0, 0.0, 0.0)));
+    }
+}
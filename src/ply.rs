@@ -0,0 +1,426 @@
+use std::error::Error;
+use std::fs;
+
+use crate::math::{Point3D, Vector3D};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, Box<dyn Error>> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(format!("Unknown PLY property type '{}'", name).into()),
+        }
+    }
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+    fn is_integer(&self) -> bool {
+        !matches!(self, ScalarType::Float32 | ScalarType::Float64)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    List(ScalarType, ScalarType),
+}
+
+impl PropertyKind {
+    // fewest bytes a value can take in the body, an empty list still stores its length
+    fn min_size(&self, format: Format) -> usize {
+        match (format, self) {
+            // a digit and the space or line break after it
+            (Format::Ascii, _) => 2,
+            (_, PropertyKind::Scalar(scalar)) => scalar.size(),
+            (_, PropertyKind::List(count, _)) => count.size(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlyProperty {
+    pub name: String,
+    kind: PropertyKind,
+}
+
+impl PlyProperty {
+    // integer color channels are stored as 0-255, float ones as 0-1
+    pub fn is_integer(&self) -> bool {
+        match &self.kind {
+            PropertyKind::Scalar(scalar) => scalar.is_integer(),
+            PropertyKind::List(_, item) => item.is_integer(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PlyValue {
+    Scalar(f64),
+    List(Vec<f64>),
+}
+
+impl PlyValue {
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            PlyValue::Scalar(value) => *value,
+            PlyValue::List(values) => values.first().copied().unwrap_or_default(),
+        }
+    }
+    pub fn as_list(&self) -> &[f64] {
+        match self {
+            PlyValue::Scalar(value) => std::slice::from_ref(value),
+            PlyValue::List(values) => values,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlyElement {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<PlyProperty>,
+    pub rows: Vec<Vec<PlyValue>>,
+}
+
+impl PlyElement {
+    pub fn property_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&property.name.as_str()))
+    }
+
+    // values of the first property found among `names`, for every row
+    pub fn scalars(&self, names: &[&str]) -> Option<Vec<f64>> {
+        let index = self.property_index(names)?;
+        Some(self.rows.iter().map(|row| row[index].as_f64()).collect())
+    }
+
+    fn vectors(&self, x: &[&str], y: &[&str], z: &[&str]) -> Option<Vec<Vector3D>> {
+        let (x, y, z) = (self.scalars(x)?, self.scalars(y)?, self.scalars(z)?);
+        Some((0..x.len()).map(|i| Vector3D::new(x[i], y[i], z[i])).collect())
+    }
+
+    pub fn positions(&self) -> Result<Vec<Point3D>, Box<dyn Error>> {
+        let positions = self.vectors(&["x"], &["y"], &["z"]).ok_or("PLY vertices have no x, y and z")?;
+        Ok(positions.into_iter().map(|position| Point3D::default() + position).collect())
+    }
+
+    pub fn normals(&self) -> Option<Vec<Vector3D>> {
+        let normals = self.vectors(&["nx"], &["ny"], &["nz"])?;
+        Some(normals.into_iter().map(|normal| normal.normalize()).collect())
+    }
+
+    // colors in the 0-255 range, float channels being scaled up from 0-1
+    pub fn colors(&self) -> Option<Vec<Vector3D>> {
+        let colors = self.vectors(
+            &["red", "r", "diffuse_red"],
+            &["green", "g", "diffuse_green"],
+            &["blue", "b", "diffuse_blue"],
+        )?;
+        let red = self.property_index(&["red", "r", "diffuse_red"])?;
+        let factor = if self.properties[red].is_integer() { 1.0 } else { 255.0 };
+        Some(colors.into_iter().map(|color| color * factor).collect())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlyFile {
+    pub elements: Vec<PlyElement>,
+}
+
+impl PlyFile {
+    pub fn element(&self, name: &str) -> Option<&PlyElement> {
+        self.elements.iter().find(|element| element.name == name)
+    }
+}
+
+pub fn read(path: &str) -> Result<PlyFile, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    parse(&bytes)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<PlyFile, Box<dyn Error>> {
+    let (header, body_start) = split_header(bytes)?;
+    let mut lines = header.lines().map(|line| line.trim());
+    if lines.next() != Some("ply") {
+        return Err("Missing 'ply' magic number".into());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: Vec::new(),
+                rows: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let element = elements.last_mut().ok_or("PLY property declared before any element")?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PropertyKind::List(ScalarType::parse(count_type)?, ScalarType::parse(item_type)?),
+                });
+            }
+            ["property", scalar_type, name] => {
+                let element = elements.last_mut().ok_or("PLY property declared before any element")?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PropertyKind::Scalar(ScalarType::parse(scalar_type)?),
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | ["end_header"] | [] => {}
+            _ => return Err(format!("Unexpected PLY header line '{}'", line).into()),
+        }
+    }
+    let format = format.ok_or("Missing PLY format line")?;
+    let body = &bytes[body_start..];
+    check_counts(&elements, format, body.len())?;
+    if format == Format::Ascii {
+        read_ascii(body, &mut elements)?;
+    } else {
+        read_binary(body, format == Format::BinaryLittleEndian, &mut elements)?;
+    }
+    Ok(PlyFile { elements })
+}
+
+// counts come straight from the header, they are checked against what the body can hold before
+// anything is allocated so that a broken file gives an error instead of running out of memory
+fn check_counts(elements: &[PlyElement], format: Format, body_length: usize) -> Result<(), Box<dyn Error>> {
+    let mut needed: usize = 0;
+    for element in elements {
+        let row_size: usize = element.properties.iter().map(|property| property.kind.min_size(format)).sum();
+        if row_size == 0 && element.count > 0 {
+            return Err(format!("PLY element '{}' has rows but no properties", element.name).into());
+        }
+        needed = element
+            .count
+            .checked_mul(row_size)
+            .and_then(|size| needed.checked_add(size))
+            .ok_or_else(|| format!("PLY element '{}' is too large", element.name))?;
+    }
+    // the last ascii value may end the file without a separator
+    if needed.saturating_sub(1) > body_length {
+        return Err("PLY header announces more data than the file holds".into());
+    }
+    Ok(())
+}
+
+fn split_header(bytes: &[u8]) -> Result<(String, usize), Box<dyn Error>> {
+    let marker = b"end_header";
+    let position = bytes
+        .windows(marker.len())
+        .position(|window| window == marker)
+        .ok_or("Missing PLY 'end_header'")?;
+    let mut body_start = position + marker.len();
+    if bytes.get(body_start) == Some(&b'\r') {
+        body_start += 1;
+    }
+    if bytes.get(body_start) == Some(&b'\n') {
+        body_start += 1;
+    }
+    let header = String::from_utf8_lossy(&bytes[..position + marker.len()]).into_owned();
+    Ok((header, body_start))
+}
+
+fn read_ascii(body: &[u8], elements: &mut [PlyElement]) -> Result<(), Box<dyn Error>> {
+    let text = String::from_utf8_lossy(body);
+    let mut tokens = text.split_whitespace();
+    let mut next = || -> Result<f64, Box<dyn Error>> {
+        let token = tokens.next().ok_or("Unexpected end of PLY data")?;
+        Ok(token.parse::<f64>()?)
+    };
+    for element in elements.iter_mut() {
+        element.rows.reserve(element.count);
+        for _ in 0..element.count {
+            let mut row = Vec::with_capacity(element.properties.len());
+            for property in &element.properties {
+                row.push(match property.kind {
+                    PropertyKind::Scalar(_) => PlyValue::Scalar(next()?),
+                    PropertyKind::List(_, _) => {
+                        let length = next()? as usize;
+                        let mut items = Vec::with_capacity(length.min(body.len() / 2));
+                        for _ in 0..length {
+                            items.push(next()?);
+                        }
+                        PlyValue::List(items)
+                    }
+                });
+            }
+            element.rows.push(row);
+        }
+    }
+    Ok(())
+}
+
+fn read_binary(body: &[u8], little_endian: bool, elements: &mut [PlyElement]) -> Result<(), Box<dyn Error>> {
+    let mut offset = 0;
+    let mut next = |scalar: ScalarType| -> Result<f64, Box<dyn Error>> {
+        let size = scalar.size();
+        let data = body.get(offset..offset + size).ok_or("Unexpected end of PLY data")?;
+        offset += size;
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(data);
+        if !little_endian {
+            raw[..size].reverse();
+        }
+        Ok(match scalar {
+            ScalarType::Int8 => raw[0] as i8 as f64,
+            ScalarType::UInt8 => raw[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(raw),
+        })
+    };
+    for element in elements.iter_mut() {
+        element.rows.reserve(element.count);
+        for _ in 0..element.count {
+            let mut row = Vec::with_capacity(element.properties.len());
+            for property in &element.properties {
+                row.push(match property.kind {
+                    PropertyKind::Scalar(scalar) => PlyValue::Scalar(next(scalar)?),
+                    PropertyKind::List(count_type, item_type) => {
+                        let length = next(count_type)? as usize;
+                        let mut items = Vec::with_capacity(length.min(body.len() / item_type.size()));
+                        for _ in 0..length {
+                            items.push(next(item_type)?);
+                        }
+                        PlyValue::List(items)
+                    }
+                });
+            }
+            element.rows.push(row);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(format: &str) -> Vec<u8> {
+        format!(
+            "ply\nformat {} 1.0\ncomment test\nelement vertex 2\nproperty float x\nproperty float y\n\
+             property float z\nproperty uchar red\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format
+        )
+        .into_bytes()
+    }
+
+    fn check(file: &PlyFile) {
+        let vertices = file.element("vertex").unwrap();
+        let positions = vertices.positions().unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!((positions[1].x, positions[1].y, positions[1].z), (4.0, 5.0, -6.5));
+        assert_eq!(vertices.scalars(&["red"]).unwrap(), vec![255.0, 7.0]);
+        assert!(vertices.properties[3].is_integer());
+        let faces = file.element("face").unwrap();
+        assert_eq!(faces.rows[0][0].as_list(), &[0.0, 1.0, 1.0]);
+    }
+
+    fn binary(little_endian: bool) -> Vec<u8> {
+        let mut bytes = header(if little_endian { "binary_little_endian" } else { "binary_big_endian" });
+        let float = |bytes: &mut Vec<u8>, value: f32| {
+            bytes.extend(if little_endian { value.to_le_bytes() } else { value.to_be_bytes() })
+        };
+        for (position, red) in [([1.0, 2.0, 3.0], 255u8), ([4.0, 5.0, -6.5], 7)] {
+            for value in position {
+                float(&mut bytes, value);
+            }
+            bytes.push(red);
+        }
+        bytes.push(3);
+        for index in [0i32, 1, 1] {
+            bytes.extend(if little_endian { index.to_le_bytes() } else { index.to_be_bytes() });
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let mut bytes = header("ascii");
+        bytes.extend(b"1 2 3 255\n4 5 -6.5 7\n3 0 1 1\n");
+        check(&parse(&bytes).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_little_endian() {
+        check(&parse(&binary(true)).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_big_endian() {
+        check(&parse(&binary(false)).unwrap());
+    }
+
+    #[test]
+    fn float_colors_are_scaled_to_255() {
+        let bytes = b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float red\nproperty float green\n\
+            property float blue\nend_header\n1 0.5 0\n";
+        let colors = parse(bytes).unwrap().element("vertex").unwrap().colors().unwrap();
+        assert_eq!((colors[0].x, colors[0].y, colors[0].z), (255.0, 127.5, 0.0));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let mut bytes = binary(true);
+        bytes.truncate(bytes.len() - 2);
+        assert!(parse(&bytes).is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n").is_err());
+    }
+
+    #[test]
+    fn rejects_counts_larger_than_the_file() {
+        let huge = b"ply\nformat binary_little_endian 1.0\nelement vertex 4000000000000\nproperty double x\nend_header\n";
+        assert!(parse(huge).is_err());
+        let overflow = b"ply\nformat binary_little_endian 1.0\nelement vertex 18446744073709551615\n\
+            property double x\nend_header\n";
+        assert!(parse(overflow).is_err());
+        let empty_rows = b"ply\nformat ascii 1.0\nelement vertex 1000000000000\nend_header\n";
+        assert!(parse(empty_rows).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(parse(b"plx\nformat ascii 1.0\nend_header\n").is_err());
+        assert!(parse(b"ply\nelement vertex 0\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty quad x\nend_header\n").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::math::{self, Point3D, Vector3D};
+use crate::object::{Interval, Object};
+use crate::ply;
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-9;
+
+// how every point is drawn, disks need normals and points without one are drawn as spheres
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Splat {
+    Sphere,
+    Disk,
+}
+
+// positions, optional normals, optional colors and optional radii
+type PointBuffers = (Vec<Point3D>, Option<Vec<Vector3D>>, Option<Vec<Vector3D>>, Option<Vec<f64>>);
+
+pub struct PointCloud {
+    pub positions: Vec<Point3D>,
+    pub normals: Option<Vec<Vector3D>>,
+    pub colors: Option<Vec<Vector3D>>,
+    pub radii: Option<Vec<f64>>,
+    // used for the points without a radius of their own
+    pub radius: f64,
+    pub splat: Splat,
+    pub color: Vector3D,
+    bvh: Bvh,
+}
+
+impl PointCloud {
+    pub fn new(
+        positions: Vec<Point3D>,
+        normals: Option<Vec<Vector3D>>,
+        colors: Option<Vec<Vector3D>>,
+        radii: Option<Vec<f64>>,
+        radius: f64,
+        splat: Splat,
+        color: Vector3D,
+    ) -> PointCloud {
+        let mut cloud = PointCloud { positions, normals, colors, radii, radius, splat, color, bvh: Bvh::default() };
+        let bounds: Vec<Aabb> = (0..cloud.positions.len()).map(|index| cloud.point_bounds(index)).collect();
+        cloud.bvh = Bvh::build(&bounds);
+        cloud
+    }
+
+    // picks the loader from the file extension
+    pub fn load(path: &str, offset: Vector3D, radius: f64, splat: Splat, color: Vector3D) -> Result<PointCloud, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let (positions, normals, colors, radii) = match extension.as_deref() {
+            Some("ply") => Self::read_ply(path)?,
+            Some("xyz") => Self::read_xyz(path)?,
+            _ => return Err(format!("Unsupported point cloud format '{}'", path).into()),
+        };
+        let positions = positions.into_iter().map(|position| position + offset).collect();
+        Ok(PointCloud::new(positions, normals, colors, radii, radius, splat, color))
+    }
+
+    fn read_ply(path: &str) -> Result<PointBuffers, Box<dyn Error>> {
+        let file = ply::read(path)?;
+        let vertex_element = file.element("vertex").ok_or("PLY file has no vertex element")?;
+        Ok((
+            vertex_element.positions()?,
+            vertex_element.normals(),
+            vertex_element.colors(),
+            vertex_element.scalars(&["radius", "scale"]),
+        ))
+    }
+
+    // one point per line: "x y z", "x y z radius", "x y z r g b" or "x y z r g b radius"
+    // colors are 0-255 unless no channel in the file goes above 1
+    fn read_xyz(path: &str) -> Result<PointBuffers, Box<dyn Error>> {
+        Self::parse_xyz(&fs::read_to_string(path)?)
+    }
+
+    fn parse_xyz(text: &str) -> Result<PointBuffers, Box<dyn Error>> {
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+        let mut radii = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+            let values = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|error| format!("XYZ line {}: {}", number + 1, error))?;
+            if values.len() < 3 {
+                return Err(format!("XYZ line {} has fewer than three values", number + 1).into());
+            }
+            positions.push(Point3D::new(values[0], values[1], values[2]));
+            match values.len() {
+                4 => radii.push(values[3]),
+                6 => colors.push(Vector3D::new(values[3], values[4], values[5])),
+                7 => {
+                    colors.push(Vector3D::new(values[3], values[4], values[5]));
+                    radii.push(values[6]);
+                }
+                _ => {}
+            }
+        }
+        let colors = if !colors.is_empty() && colors.len() == positions.len() {
+            let brightest = colors.iter().fold(0.0, |max: f64, color| max.max(color.x).max(color.y).max(color.z));
+            let factor = if brightest <= 1.0 { 255.0 } else { 1.0 };
+            Some(colors.into_iter().map(|color| color * factor).collect())
+        } else {
+            None
+        };
+        let radii = if !radii.is_empty() && radii.len() == positions.len() { Some(radii) } else { None };
+        Ok((positions, None, colors, radii))
+    }
+
+    fn point_radius(&self, index: usize) -> f64 {
+        self.radii.as_ref().map_or(self.radius, |radii| radii[index])
+    }
+
+    fn disk_normal(&self, index: usize) -> Option<Vector3D> {
+        match (self.splat, &self.normals) {
+            (Splat::Disk, Some(normals)) => Some(normals[index]),
+            _ => None,
+        }
+    }
+
+    fn point_bounds(&self, index: usize) -> Aabb {
+        let radius = self.point_radius(index);
+        let extent = Vector3D::new(radius, radius, radius);
+        Aabb::from_points(&[self.positions[index] - extent, self.positions[index] + extent])
+    }
+
+    // entry and exit distances of the ray through one splat, the same for a disk
+    fn intersect_point(&self, index: usize, ray: &Ray) -> Option<(f64, f64)> {
+        let center = self.positions[index];
+        let radius = self.point_radius(index);
+        match self.disk_normal(index) {
+            Some(normal) => {
+                let denominator = normal.dot(&ray.direction);
+                if denominator.abs() < EPSILON {
+                    return None;
+                }
+                let t = (center - ray.origin).dot(&normal) / denominator;
+                let offset = ray.origin + ray.direction * t - center;
+                if offset.dot(&offset) > radius * radius {
+                    return None;
+                }
+                Some((t, t))
+            }
+            None => {
+                let oc = ray.origin - center;
+                let roots = math::solve_quadratic(
+                    ray.direction.dot(&ray.direction),
+                    2.0 * oc.dot(&ray.direction),
+                    oc.dot(&oc) - radius * radius,
+                );
+                match roots.as_slice() {
+                    [enter, exit] => Some((*enter, *exit)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    // how far a point is from the surface of one splat
+    fn splat_distance(&self, index: usize, point: &Point3D) -> f64 {
+        let offset = *point - self.positions[index];
+        let radius = self.point_radius(index);
+        match self.disk_normal(index) {
+            Some(normal) => {
+                let height = offset.dot(&normal);
+                let spread = (offset - normal * height).length();
+                height.abs() + (spread - radius).max(0.0)
+            }
+            None => (offset.length() - radius).abs(),
+        }
+    }
+
+    // the splat a hit point lies on, or the nearest one for a point outside every splat box,
+    // None only for an empty cloud
+    fn locate(&self, point: &Point3D) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        let mut best_distance = f64::INFINITY;
+        self.bvh.visit_point(point, 1e-6, |index| {
+            let distance = self.splat_distance(index, point);
+            if distance < best_distance {
+                best_distance = distance;
+                best = Some(index);
+            }
+        });
+        best.or_else(|| {
+            (0..self.positions.len())
+                .min_by(|a, b| self.splat_distance(*a, point).total_cmp(&self.splat_distance(*b, point)))
+        })
+    }
+}
+
+impl Object for PointCloud {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let (_, t) = self.bvh.intersect(&ray, |index| {
+            let (enter, exit) = self.intersect_point(index, &ray)?;
+            [enter, exit].into_iter().find(|t| *t > EPSILON)
+        })?;
+        Some(ray.origin + ray.direction * t)
+    }
+    // overlapping splats are merged so the cloud acts as one solid
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let mut spans: Vec<(f64, f64)> = Vec::new();
+        self.bvh.visit_ray(&ray, |index| {
+            if let Some(span) = self.intersect_point(index, &ray) {
+                spans.push(span);
+            }
+        });
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut intervals: Vec<Interval> = Vec::new();
+        for (enter, exit) in spans {
+            match intervals.last_mut() {
+                Some(last) if enter <= last.exit => last.exit = last.exit.max(exit),
+                _ => intervals.push(Interval::new(enter, exit)),
+            }
+        }
+        intervals
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        match self.locate(hit_point) {
+            Some(index) => self
+                .disk_normal(index)
+                .unwrap_or_else(|| (*hit_point - self.positions[index]).normalize()),
+            // an empty cloud is never hit
+            None => Vector3D::new(0.0, 1.0, 0.0),
+        }
+    }
+    fn get_center(&self) -> Point3D {
+        self.bounds().centroid()
+    }
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        match (&self.colors, self.locate(hit_point)) {
+            (Some(colors), Some(index)) => colors[index],
+            _ => self.color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn balls(positions: Vec<Point3D>) -> PointCloud {
+        PointCloud::new(positions, None, None, None, 0.5, Splat::Sphere, WHITE)
+    }
+
+    #[test]
+    fn reads_xyz_columns() {
+        let (positions, normals, colors, radii) = PointCloud::parse_xyz("# comment\n0 0 0\n1,2,3\n\n").unwrap();
+        assert_eq!(positions, vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(1.0, 2.0, 3.0)]);
+        assert!(normals.is_none() && colors.is_none() && radii.is_none());
+        let (_, _, colors, radii) = PointCloud::parse_xyz("0 0 0 1 0.5 0 0.2\n1 1 1 0 0 1 0.3\n").unwrap();
+        // channels that all stay within 1 are scaled up to 255
+        let colors = colors.unwrap();
+        assert_eq!((colors[0].x, colors[0].y, colors[1].z), (255.0, 127.5, 255.0));
+        assert_eq!(radii.unwrap(), vec![0.2, 0.3]);
+        let (_, _, colors, radii) = PointCloud::parse_xyz("0 0 0 200 10 10\n1 1 1\n").unwrap();
+        // columns missing on some lines are dropped for the whole cloud
+        assert!(colors.is_none() && radii.is_none());
+        assert!(PointCloud::parse_xyz("0 0\n").is_err());
+        assert!(PointCloud::parse_xyz("0 0 x\n").is_err());
+    }
+
+    #[test]
+    fn sphere_splats_merge_into_one_solid() {
+        let cloud = balls(vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(0.8, 0.0, 0.0), Point3D::new(3.0, 0.0, 0.0)]);
+        let ray = Ray::new(Point3D::new(-5.0, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        let hit = cloud.hits(ray).unwrap();
+        assert!((hit.x + 0.5).abs() < 1e-9);
+        let normal = cloud.surface_normal(&hit);
+        assert!((normal.x + 1.0).abs() < 1e-9);
+        let intervals = cloud.intervals(ray);
+        assert_eq!(intervals.len(), 2);
+        assert!((intervals[0].exit - 6.3).abs() < 1e-9 && (intervals[1].enter - 7.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn disk_splats_face_their_normals() {
+        let normals = Some(vec![Vector3D::new(0.0, 1.0, 0.0)]);
+        let cloud = PointCloud::new(vec![Point3D::default()], normals, None, Some(vec![1.0]), 0.1, Splat::Disk, WHITE);
+        let hit = cloud.hits(Ray::new(Point3D::new(0.5, 5.0, 0.0), Vector3D::new(0.0, -1.0, 0.0))).unwrap();
+        assert!(hit.y.abs() < 1e-9);
+        assert_eq!(cloud.surface_normal(&hit).y, 1.0);
+        assert!(cloud.hits(Ray::new(Point3D::new(1.5, 5.0, 0.0), Vector3D::new(0.0, -1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn stray_points_use_the_nearest_splat() {
+        let colors = Some(vec![Vector3D::new(255.0, 0.0, 0.0), Vector3D::new(0.0, 0.0, 255.0)]);
+        let positions = vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(5.0, 0.0, 0.0)];
+        let cloud = PointCloud::new(positions, None, colors, None, 0.5, Splat::Sphere, WHITE);
+        // far outside every splat box, closer to the second ball
+        let stray = Point3D::new(7.0, 0.0, 0.0);
+        let normal = cloud.surface_normal(&stray);
+        assert!((normal.x - 1.0).abs() < 1e-9);
+        assert_eq!(cloud.get_albedo(&stray, &normal).z, 255.0);
+    }
+}
@@ -1,9 +1,12 @@
+use std::f64::consts::PI;
 use std::fs::OpenOptions;
 use std::io::Write;
 
-use crate::light::{DirectionalLight, Light};
-use crate::math::{Point3D, Vector3D};
-use crate::object::{HitResult, Object, Plane, Sphere};
+use crate::bvh::{Aabb, Bvh};
+use crate::environment::Environment;
+use crate::light::{Light, Lighting};
+use crate::math::{self, Point3D, Vector3D};
+use crate::object::{Material, Object, ObjectId, Plane};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
@@ -120,8 +123,15 @@ pub struct Scene {
     pub height: u32,
     pub camera: Camera,                // camera of the scene
     pub objects: Vec<Box<dyn Object>>, // list of Objects
+    ids: Vec<ObjectId>,                // id of each object, follows it when objects are reordered
     pub lights: Vec<Box<dyn Light>>,   // list of Lights
+    pub lighting: Lighting,            // ambient light and weights of the lights
+    pub environment: Option<Environment>, // seen by rays leaving the scene, may light it too
     pub plane: Plane,                  // plane of the scene
+    bvh: Bvh,                          // bounded objects, rebuilt by render
+    bounded: Vec<usize>,               // object index of each bvh primitive
+    unbounded: Vec<usize>,             // objects tested against every ray
+    emitters: Vec<usize>,              // objects with an emissive material, sampled as lights
 }
 
 impl Default for Scene {
@@ -129,10 +139,17 @@ impl Default for Scene {
         Scene {
             camera: Camera::default(),
             objects: Vec::new(),
+            ids: Vec::new(),
             lights: Vec::new(),
+            lighting: Lighting::default(),
+            environment: None,
             plane: Plane::default(),
             width: 0,
             height: 0,
+            bvh: Bvh::default(),
+            bounded: Vec::new(),
+            unbounded: Vec::new(),
+            emitters: Vec::new(),
         }
     }
 }
@@ -148,19 +165,33 @@ impl Scene {
     ) -> Scene {
         Scene {
             camera,
+            ids: (0..objects.len()).collect(),
             objects,
             lights,
+            lighting: Lighting::default(),
+            environment: None,
             plane,
             width,
             height,
+            bvh: Bvh::default(),
+            bounded: Vec::new(),
+            unbounded: Vec::new(),
+            emitters: Vec::new(),
         }
     }
     pub fn add_object(&mut self, object: Box<dyn Object>) {
+        self.ids.push(self.ids.len());
         self.objects.push(object);
     }
     pub fn add_light(&mut self, light: Box<dyn Light>) {
         self.lights.push(light);
     }
+    pub fn set_lighting(&mut self, lighting: Lighting) {
+        self.lighting = lighting;
+    }
+    pub fn set_environment(&mut self, environment: Option<Environment>) {
+        self.environment = environment;
+    }
     pub fn add_plane(&mut self, plane: Plane) {
         self.plane = plane;
     }
@@ -179,43 +210,296 @@ impl Scene {
             .write_all(format!("{} {} {}\n", color.0, color.1, color.2).as_bytes())
             .expect("cannot write to file");
     }
+    fn build_bvh(&mut self) {
+        let (bounded, unbounded): (Vec<usize>, Vec<usize>) =
+            (0..self.objects.len()).partition(|index| self.objects[*index].bounds().is_finite());
+        let bounds: Vec<Aabb> = bounded.iter().map(|index| self.objects[*index].bounds()).collect();
+        self.bvh = Bvh::build(&bounds);
+        self.bounded = bounded;
+        self.unbounded = unbounded;
+        // an emitter hidden from bounced rays lights nothing, its surface is not sampled either
+        self.emitters = (0..self.objects.len())
+            .filter(|index| {
+                let object = &self.objects[*index];
+                object.visibility().reflections
+                    && object
+                        .sample_surface(0.5, 0.5)
+                        .is_some_and(|(point, _)| object.get_material(&point).is_emissive())
+            })
+            .collect();
+    }
+    // indices of the objects the ray may hit, in scene order
+    fn candidates(&self, ray: &Ray) -> Vec<usize> {
+        let mut indices = self.unbounded.clone();
+        self.bvh.visit_ray(ray, |index| indices.push(self.bounded[index]));
+        indices.sort_unstable();
+        indices
+    }
+    // fraction of each of red, green and blue light reaching `origin` from a light `distance` away,
+    // every shadow casting object in between tints it by its transmission, opaque ones stop it,
+    // `id` itself included unless it is convex, `origin` being already lifted off its surface
+    fn transmittance(&self, id: ObjectId, origin: Point3D, direction: Vector3D, distance: f64) -> Vector3D {
+        let shadow_ray = Ray::new(origin, direction);
+        let mut transmittance = Vector3D::new(1.0, 1.0, 1.0);
+        for index in self.candidates(&shadow_ray) {
+            let other_object = &self.objects[index];
+            if (self.ids[index] == id && other_object.is_convex()) || !other_object.visibility().shadows {
+                continue;
+            }
+            let Some(blocker) = other_object.hits(shadow_ray) else {
+                continue;
+            };
+            if (blocker - shadow_ray.origin).length() >= distance {
+                continue;
+            }
+            let material = other_object.get_material(&blocker);
+            if !material.is_transmissive() {
+                return Vector3D::new(0.0, 0.0, 0.0);
+            }
+            transmittance *= material.transmission;
+        }
+        transmittance
+    }
+    // what is left of a light on its way to `object`, all of it for objects that receive no shadows
+    fn shadowing(&self, object: &dyn Object, id: ObjectId, origin: Point3D, direction: Vector3D, distance: f64) -> Vector3D {
+        if !object.visibility().receive_shadows {
+            return Vector3D::new(1.0, 1.0, 1.0);
+        }
+        self.transmittance(id, origin, direction, distance)
+    }
+    // nearest object along a bounced ray other than `id` that light does not go through, with its index
+    fn closest_hit(&self, id: ObjectId, ray: &Ray) -> Option<(usize, Point3D)> {
+        let mut closest: Option<(usize, Point3D)> = None;
+        let mut closest_distance = f64::INFINITY;
+        for index in self.candidates(ray) {
+            let other_object = &self.objects[index];
+            if self.ids[index] == id || !other_object.visibility().reflections {
+                continue;
+            }
+            if let Some(hit_point) = other_object.hits(*ray) {
+                if other_object.get_material(&hit_point).is_transmissive() {
+                    continue;
+                }
+                let distance = (hit_point - ray.origin).length();
+                if distance < closest_distance {
+                    closest_distance = distance;
+                    closest = Some((index, hit_point));
+                }
+            }
+        }
+        closest
+    }
+    // diffuse and specular weights of a direction, from the object itself or Lambert and the material,
+    // shared by the lights and the emitters so both give a material the same highlights
+    fn shading_weights(
+        object: &dyn Object,
+        material: &Material,
+        hit_point: &Point3D,
+        surface_normal: &Vector3D,
+        direction: &Vector3D,
+        view_direction: &Vector3D,
+    ) -> (f64, f64) {
+        object.shading(hit_point, direction, view_direction).unwrap_or_else(|| {
+            let diffuse = surface_normal.dot(direction).max(0.0);
+            if diffuse > 0.0 {
+                (diffuse, material.highlight(surface_normal, direction, view_direction) * material.highlight_scale())
+            } else {
+                (0.0, 0.0)
+            }
+        })
+    }
+    // density over solid angle of reaching `light_point` on `emitter` by sampling its surface
+    fn emitter_density(emitter: &dyn Object, origin: &Point3D, light_point: &Point3D, light_normal: &Vector3D) -> f64 {
+        let offset = *light_point - *origin;
+        let distance_squared = offset.dot(&offset);
+        let cos_light = light_normal.dot(&offset.normalize()).abs();
+        if cos_light <= 1e-6 {
+            return 0.0;
+        }
+        emitter.surface_density(light_point) * distance_squared / cos_light
+    }
+    // light of the emissive objects, found both by sampling their surfaces and by sending rays around the
+    // normal as a diffuse surface reflects them, the two estimates are weighed by the power heuristic so
+    // small bright emitters and large dim ones are both sampled well
+    pub fn compute_lighting_emitters(&self, object: &dyn Object, id: ObjectId, hit_point: &Point3D, ray: &Ray) -> Vector3D {
+        if self.emitters.is_empty() {
+            return Vector3D::new(0.0, 0.0, 0.0);
+        }
+        let surface_normal = object.surface_normal(hit_point);
+        let view_direction = (ray.direction * -1.0).normalize();
+        let material = object.get_material(hit_point);
+        let origin = *hit_point + (surface_normal * 0.001);
+        let side = (self.lighting.emitter_samples.max(1) as f64).sqrt().ceil() as usize;
+        let count = (side * side) as f64;
+        let mut diffuse_color = Vector3D::new(0.0, 0.0, 0.0);
+        let mut specular_color = Vector3D::new(0.0, 0.0, 0.0);
+
+        // samples on the surface of each emitter, seeds kept apart from the ones of the other lights
+        for (number, &emitter_index) in self.emitters.iter().enumerate() {
+            let emitter = self.objects[emitter_index].as_ref();
+            if self.ids[emitter_index] == id {
+                continue;
+            }
+            let seed = (number as u64 + 1) << 32;
+            for j in 0..side {
+                for i in 0..side {
+                    let index = seed + 2 * (j * side + i) as u64;
+                    let u = (i as f64 + math::hash_unit(math::point_seed(hit_point, index))) / side as f64;
+                    let v = (j as f64 + math::hash_unit(math::point_seed(hit_point, index + 1))) / side as f64;
+                    let Some((light_point, light_normal)) = emitter.sample_surface(u, v) else {
+                        continue;
+                    };
+                    let offset = light_point - origin;
+                    let distance = offset.length();
+                    let direction = offset.normalize();
+                    let light_pdf = Self::emitter_density(emitter, &origin, &light_point, &light_normal);
+                    if light_pdf <= 0.0 {
+                        continue;
+                    }
+                    let (diffuse, specular) = Self::shading_weights(
+                        object,
+                        &material,
+                        hit_point,
+                        &surface_normal,
+                        &direction,
+                        &view_direction,
+                    );
+                    if diffuse <= 0.0 && specular <= 0.0 {
+                        continue;
+                    }
+                    let transmittance = self.shadowing(object, id, origin, direction, distance * (1.0 - 1e-3));
+                    if transmittance.is_black() {
+                        continue;
+                    }
+                    let emission = emitter.get_material(&light_point).emission * transmittance;
+                    let reflection_pdf = surface_normal.dot(&direction).max(0.0) / PI;
+                    let weight = light_pdf * light_pdf / (light_pdf * light_pdf + reflection_pdf * reflection_pdf);
+                    let albedo = object.get_albedo(hit_point, &direction);
+                    diffuse_color += albedo / 255.0 * emission * (diffuse * weight / (PI * light_pdf * count));
+                    // highlights are too narrow for the diffuse rays, they only come from the light samples,
+                    // over pi like the diffuse term
+                    specular_color +=
+                        material.specular / 255.0 * emission * (specular / (PI * light_pdf * count));
+                }
+            }
+        }
+
+        // rays spread around the normal in proportion to the cosine
+        let (tangent, bitangent) = surface_normal.orthonormal_basis();
+        let seed = 1 << 48;
+        for j in 0..side {
+            for i in 0..side {
+                let index = seed + 2 * (j * side + i) as u64;
+                let u = (i as f64 + math::hash_unit(math::point_seed(hit_point, index))) / side as f64;
+                let v = (j as f64 + math::hash_unit(math::point_seed(hit_point, index + 1))) / side as f64;
+                let (x, y) = math::concentric_disk(u, v);
+                let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+                let direction = (tangent * x + bitangent * y + surface_normal * z).normalize();
+                let cos_surface = surface_normal.dot(&direction);
+                if cos_surface <= 0.0 {
+                    continue;
+                }
+                let Some((emitter_index, light_point)) = self.closest_hit(id, &Ray::new(origin, direction)) else {
+                    continue;
+                };
+                if !self.emitters.contains(&emitter_index) {
+                    continue;
+                }
+                let emitter = self.objects[emitter_index].as_ref();
+                let light_normal = emitter.surface_normal(&light_point);
+                let light_pdf = Self::emitter_density(emitter, &origin, &light_point, &light_normal);
+                let reflection_pdf = cos_surface / PI;
+                let weight = reflection_pdf * reflection_pdf / (light_pdf * light_pdf + reflection_pdf * reflection_pdf);
+                let (diffuse, _) =
+                    Self::shading_weights(object, &material, hit_point, &surface_normal, &direction, &view_direction);
+                if diffuse <= 0.0 {
+                    continue;
+                }
+                let distance = (light_point - origin).length();
+                let transmittance = self.shadowing(object, id, origin, direction, distance * (1.0 - 1e-3));
+                let emission = emitter.get_material(&light_point).emission * transmittance;
+                let albedo = object.get_albedo(hit_point, &direction);
+                diffuse_color += albedo / 255.0 * emission * (diffuse / cos_surface * weight / count);
+            }
+        }
+
+        (diffuse_color * self.lighting.diffuse + specular_color * self.lighting.specular) * 255.0
+    }
+
+    // light given off by the surface itself
+    pub fn compute_emission(&self, object: &dyn Object, hit_point: &Point3D) -> Vector3D {
+        object.get_material(hit_point).emission * 255.0
+    }
+
+    // sum over the samples of the light, the ones blocked on their way to the point add nothing,
+    // so lights with a size give soft shadows, and the ones going through glass take its color
     pub fn compute_lighting_directional(
-        object: &Box<dyn Object>,
-        light: &Box<dyn Light>,
+        &self,
+        object: &dyn Object,
+        id: ObjectId,
+        light: &dyn Light,
         hit_point: &Point3D,
         ray: &Ray,
-        objects: &Vec<Box<dyn Object>>,
     ) -> Vector3D {
         let surface_normal = object.surface_normal(hit_point);
-        let direction_to_light = light.get_direction().normalize();
-        let light_power =
-            (surface_normal.dot(&direction_to_light)).max(0.0) * light.get_intensity();
+        let view_direction = (ray.direction * -1.0).normalize();
+        let material = object.get_material(hit_point);
         let mut color: Vector3D = Vector3D::new(0.0, 0.0, 0.0);
 
-        let shadow_ray = Ray::new(hit_point.clone() + (surface_normal * 0.001), direction_to_light);
-
-        let mut is_shadowed = false;
-        for other_object in objects.iter() {
-            if object.get_center() != other_object.get_center() {
-                if let Some(_) = other_object.hits(shadow_ray) {
-                    is_shadowed = true;
-                    break;
-                }
+        for light_sample in light.samples(hit_point) {
+            let direction_to_light = light_sample.direction;
+            let (diffuse, specular) = Self::shading_weights(
+                object,
+                &material,
+                hit_point,
+                &surface_normal,
+                &direction_to_light,
+                &view_direction,
+            );
+            if (diffuse <= 0.0 && specular <= 0.0) || light_sample.radiance <= 0.0 {
+                continue;
+            }
+            let transmittance = self.shadowing(
+                object,
+                id,
+                *hit_point + (surface_normal * 0.001),
+                direction_to_light,
+                light_sample.distance,
+            );
+            if transmittance.is_black() {
+                continue;
             }
+            let light_power = diffuse * light_sample.radiance * self.lighting.diffuse;
+            // colors are taken as fractions of 255 so that dim surfaces and lights stay dim,
+            // glass on the way tints them
+            let light_color = light_sample.color / 255.0 * transmittance;
+            let highlight =
+                material.specular / 255.0 * light_color * (specular * light_sample.radiance * self.lighting.specular);
+            let albedo = object.get_albedo(hit_point, &direction_to_light);
+            color += albedo / 255.0 * light_color * light_power + highlight;
         }
 
-        if is_shadowed {
-            color = (object.get_color() * light.get_color()).normalize() * light_power;
-            let mut shadow_color: Vector3D = Vector3D::new(color.x * 255.0, color.y * 255.0, color.z * 255.0);
-            shadow_color *= 0.5;
-            return shadow_color;
-        } else {
-            color = (object.get_color() * light.get_color()).normalize() * light_power;
-        }
+        Vector3D::new(color.x * 255.0, color.y * 255.0, color.z * 255.0)
+    }
 
-        let new_color: Vector3D = Vector3D::new(color.x * 255.0, color.y * 255.0, color.z * 255.0);
+    // light from the environment map, unless it is only a backdrop
+    pub fn compute_lighting_environment(&self, object: &dyn Object, id: ObjectId, hit_point: &Point3D, ray: &Ray) -> Vector3D {
+        match &self.environment {
+            Some(environment) if environment.lighting => {
+                self.compute_lighting_directional(object, id, environment, hit_point, ray)
+            }
+            _ => Vector3D::new(0.0, 0.0, 0.0),
+        }
+    }
 
-        new_color
+    // light reaching every point alike, so the sides no light faces are not pure black
+    pub fn compute_lighting_ambient(&self, object: &dyn Object, hit_point: &Point3D) -> Vector3D {
+        let ambient = &self.lighting.ambient;
+        if ambient.intensity <= 0.0 {
+            return Vector3D::new(0.0, 0.0, 0.0);
+        }
+        let albedo = object.get_albedo(hit_point, &object.surface_normal(hit_point));
+        albedo / 255.0 * (ambient.color / 255.0) * ambient.intensity * 255.0
     }
 
     pub fn find_greater_z(&self, hitting_points: &Vec<Point3D>) -> usize {
@@ -232,8 +516,10 @@ impl Scene {
         j
     }
     pub fn render(&mut self) {
-        self.objects
-            .sort_by(|a, b| b.get_center().z.partial_cmp(&a.get_center().z).unwrap());
+        let mut tagged: Vec<(ObjectId, Box<dyn Object>)> = self.ids.drain(..).zip(self.objects.drain(..)).collect();
+        tagged.sort_by(|(_, a), (_, b)| b.get_center().z.partial_cmp(&a.get_center().z).unwrap());
+        (self.ids, self.objects) = tagged.into_iter().unzip();
+        self.build_bvh();
         for y in (0..self.height).rev() {
             for x in 0..self.width {
                 let u = x as f64 / (self.width - 1) as f64;
@@ -242,37 +528,72 @@ impl Scene {
                 let mut hit_color = Vector3D::new(0.0, 0.0, 0.0);
                 let mut multiple_hit = 0;
                 let mut hitting_points: Vec<Point3D> = Vec::new();
-                let mut hitting_shapes: Vec<&Box<dyn Object>> = Vec::new();
-                for s in self.objects.iter() {
+                let mut hitting_shapes: Vec<&dyn Object> = Vec::new();
+                let mut hitting_ids: Vec<ObjectId> = Vec::new();
+                for index in self.candidates(&r) {
+                    let s = self.objects[index].as_ref();
+                    if !s.visibility().camera {
+                        continue;
+                    }
                     if let Some(hit_point) = s.hits(r) {
                         multiple_hit += 1;
                         hitting_points.push(hit_point);
                         hitting_shapes.push(s);
+                        hitting_ids.push(self.ids[index]);
                     }
                 }
                 if multiple_hit == 0 {
-                    hit_color = Vector3D::new(0.0, 0.0, 0.0);
+                    hit_color = match &self.environment {
+                        Some(environment) => environment.background(&r.direction),
+                        None => Vector3D::new(0.0, 0.0, 0.0),
+                    };
                     Self::write_color(hit_color);
                 } else if multiple_hit > 1 {
                     let index = self.find_greater_z(&hitting_points);
+                    hit_color += self.compute_lighting_ambient(hitting_shapes[index], &hitting_points[index]);
+                    hit_color += self.compute_lighting_environment(
+                        hitting_shapes[index],
+                        hitting_ids[index],
+                        &hitting_points[index],
+                        &r,
+                    );
+                    hit_color +=
+                        self.compute_lighting_emitters(hitting_shapes[index], hitting_ids[index], &hitting_points[index], &r);
+                    hit_color += self.compute_emission(hitting_shapes[index], &hitting_points[index]);
                     for light in &self.lights {
-                        hit_color += Self::compute_lighting_directional(
+                        if !light.illuminates(hitting_shapes[index]) {
+                            continue;
+                        }
+                        hit_color += self.compute_lighting_directional(
                             hitting_shapes[index],
-                            light,
+                            hitting_ids[index],
+                            light.as_ref(),
                             &hitting_points[index],
                             &r,
-                            &self.objects,
                         );
                     }
                     Self::write_color(hit_color);
                 } else if multiple_hit == 1 {
+                    hit_color += self.compute_lighting_ambient(hitting_shapes[0], &hitting_points[0]);
+                    hit_color += self.compute_lighting_environment(
+                        hitting_shapes[0],
+                        hitting_ids[0],
+                        &hitting_points[0],
+                        &r,
+                    );
+                    hit_color +=
+                        self.compute_lighting_emitters(hitting_shapes[0], hitting_ids[0], &hitting_points[0], &r);
+                    hit_color += self.compute_emission(hitting_shapes[0], &hitting_points[0]);
                     for light in &self.lights {
-                        hit_color += Self::compute_lighting_directional(
+                        if !light.illuminates(hitting_shapes[0]) {
+                            continue;
+                        }
+                        hit_color += self.compute_lighting_directional(
                             hitting_shapes[0],
-                            light,
+                            hitting_ids[0],
+                            light.as_ref(),
                             &hitting_points[0],
                             &r,
-                            &self.objects,
                         );
                     }
                     Self::write_color(hit_color);
@@ -281,3 +602,202 @@ impl Scene {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csg::{Csg, CsgOperation};
+    use crate::heightfield::Heightfield;
+    use crate::light::{AreaLight, AreaShape, Attenuation, PointLight};
+    use crate::object::{Materialized, Rectangle, SpecularModel, Sphere, Tagged, Visibility};
+    use crate::pointcloud::{PointCloud, Splat};
+    use crate::voxel::VoxelGrid;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn scene(objects: Vec<Box<dyn Object>>) -> Scene {
+        let mut scene = Scene::new(Camera::default(), objects, Vec::new(), Plane::new("Y".to_string(), -100, WHITE), 1, 1);
+        scene.build_bvh();
+        scene
+    }
+
+    #[test]
+    fn concave_objects_shadow_themselves() {
+        let heights = vec![0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0];
+        let ridge = Heightfield::new(heights, 5, 2, Point3D::default(), Vector3D::new(4.0, 4.0, 1.0), WHITE);
+        let scene = scene(vec![Box::new(ridge)]);
+        // the valley floor on the left, lit from low on the right over the ridge or from high above
+        let origin = Point3D::new(0.5, 0.001, 0.5);
+        let low = Vector3D::new(1.0, 0.5, 0.0).normalize();
+        assert!(scene.transmittance(0, origin, low, 100.0).is_black());
+        let high = Vector3D::new(0.2, 1.0, 0.0).normalize();
+        assert!(!scene.transmittance(0, origin, high, 100.0).is_black());
+    }
+
+    #[test]
+    fn convex_objects_skip_their_own_shadow_rays() {
+        let scene = scene(vec![Box::new(Sphere::new(Point3D::default(), 1.0, WHITE))]);
+        // a ray from the underside going up through the ball, which its shading turns away anyway
+        let origin = Point3D::new(0.0, -1.001, 0.0);
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        assert!(!scene.transmittance(0, origin, up, 100.0).is_black());
+        assert!(scene.transmittance(1, origin, up, 100.0).is_black());
+    }
+
+    #[test]
+    fn voxel_grids_shadow_themselves() {
+        // a floor of three voxels with a tower on its right end
+        let mut cells = vec![0; 3 * 3];
+        for index in [0, 1, 2, 5, 8] {
+            cells[index] = 1;
+        }
+        let grid = VoxelGrid::new(Point3D::default(), 1.0, [3, 3, 1], cells, vec![WHITE; 2], WHITE);
+        let scene = scene(vec![Box::new(grid)]);
+        let origin = Point3D::new(0.5, 1.001, 0.5);
+        assert!(scene.transmittance(0, origin, Vector3D::new(1.0, 0.3, 0.0).normalize(), 100.0).is_black());
+        assert!(!scene.transmittance(0, origin, Vector3D::new(0.0, 1.0, 0.0), 100.0).is_black());
+    }
+
+    #[test]
+    fn point_clouds_shadow_themselves() {
+        // a ball resting on the right of a small one
+        let positions = vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(3.0, 0.0, 0.0)];
+        let radii = Some(vec![0.5, 2.0]);
+        let cloud = PointCloud::new(positions, None, None, radii, 0.5, Splat::Sphere, WHITE);
+        let scene = scene(vec![Box::new(cloud)]);
+        let origin = Point3D::new(0.0, 0.501, 0.0);
+        assert!(scene.transmittance(0, origin, Vector3D::new(1.0, 0.2, 0.0).normalize(), 100.0).is_black());
+        assert!(!scene.transmittance(0, origin, Vector3D::new(0.0, 1.0, 0.0), 100.0).is_black());
+    }
+
+    #[test]
+    fn shadow_rays_stop_at_the_light() {
+        let scene = scene(vec![Box::new(Sphere::new(Point3D::new(0.0, 5.0, 0.0), 1.0, WHITE))]);
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        // a light below the ball is not shadowed by it, one above it is
+        assert!(!scene.transmittance(1, Point3D::default(), up, 3.0).is_black());
+        assert!(scene.transmittance(1, Point3D::default(), up, 10.0).is_black());
+    }
+
+    #[test]
+    fn area_lights_cast_soft_shadows() {
+        let floor = || Rectangle::new(Point3D::new(-10.0, 0.0, -10.0), Vector3D::new(0.0, 0.0, 20.0), Vector3D::new(20.0, 0.0, 0.0), WHITE);
+        // a board at half height covering the half of the light with x above 0
+        let board = Rectangle::new(Point3D::new(0.0, 2.0, -5.0), Vector3D::new(0.0, 0.0, 10.0), Vector3D::new(5.0, 0.0, 0.0), WHITE);
+        let shape = AreaShape::Rectangle { u: Vector3D::new(2.0, 0.0, 0.0), v: Vector3D::new(0.0, 0.0, 2.0) };
+        let light = AreaLight::new(Point3D::new(0.0, 4.0, 0.0), shape, WHITE, 1.0, Attenuation::default(), 16);
+        let ray = Ray::new(Point3D::new(0.0, 5.0, 5.0), Vector3D::new(0.0, -1.0, -1.0).normalize());
+        let lit = |scene: &Scene| scene.compute_lighting_directional(scene.objects[0].as_ref(), 0, &light, &Point3D::default(), &ray).x;
+        let open = lit(&scene(vec![Box::new(floor())]));
+        let shaded = lit(&scene(vec![Box::new(floor()), Box::new(board)]));
+        assert!(shaded > 0.3 * open && shaded < 0.7 * open);
+    }
+
+    fn glowing(object: Box<dyn Object>) -> Box<dyn Object> {
+        Box::new(Materialized::new(object, Material { emission: Vector3D::new(1.0, 1.0, 1.0), ..Material::default() }))
+    }
+
+    #[test]
+    fn emitters_light_diffuse_surfaces() {
+        let floor = Rectangle::new(Point3D::new(-10.0, 0.0, -10.0), Vector3D::new(0.0, 0.0, 20.0), Vector3D::new(20.0, 0.0, 0.0), WHITE);
+        let ray = Ray::new(Point3D::new(0.0, 0.5, 0.5), Vector3D::new(0.0, -1.0, -1.0).normalize());
+        let lit = |emitter: Box<dyn Object>| {
+            let mut scene = scene(vec![Box::new(floor), emitter]);
+            scene.lighting.emitter_samples = 256;
+            scene.compute_lighting_emitters(scene.objects[0].as_ref(), 0, &Point3D::default(), &ray).x
+        };
+        // a white ceiling of radiance 1 covering nearly the whole sky gives a white floor its full color
+        let ceiling = Rectangle::new(Point3D::new(-100.0, 1.0, -100.0), Vector3D::new(200.0, 0.0, 0.0), Vector3D::new(0.0, 0.0, 200.0), WHITE);
+        assert!((lit(glowing(Box::new(ceiling))) / 255.0 - 1.0).abs() < 0.05);
+        // a small ball reflects as its radiance times the square of its radius over its distance
+        let ball = Sphere::new(Point3D::new(0.0, 2.0, 0.0), 0.1, WHITE);
+        assert!((lit(glowing(Box::new(ball))) / 255.0 / 0.0025 - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn point_lights_and_emitters_give_the_same_highlights() {
+        // a black floor only shows its highlight, seen from straight above under the light
+        let ray = Ray::new(Point3D::new(0.0, 3.0, 0.0), Vector3D::new(0.0, -1.0, 0.0));
+        for specular_model in [SpecularModel::Phong, SpecularModel::BlinnPhong] {
+            let floor = || -> Box<dyn Object> {
+                let rectangle = Rectangle::new(Point3D::new(-10.0, 0.0, -10.0), Vector3D::new(0.0, 0.0, 20.0), Vector3D::new(20.0, 0.0, 0.0), Vector3D::default());
+                let material = Material { specular: WHITE, shininess: 8.0, specular_model, ..Material::default() };
+                Box::new(Materialized::new(Box::new(rectangle), material))
+            };
+            let ball = glowing(Box::new(Sphere::new(Point3D::new(0.0, 2.0, 0.0), 0.1, WHITE)));
+            let mut emitter_scene = scene(vec![floor(), ball]);
+            emitter_scene.lighting.emitter_samples = 256;
+            let from_emitter =
+                emitter_scene.compute_lighting_emitters(emitter_scene.objects[0].as_ref(), 0, &Point3D::default(), &ray).x;
+            // a ball of radiance 1 gives off as much as a point light of its radius squared over its distance squared
+            let light = PointLight::new(Point3D::new(0.0, 2.0, 0.0), WHITE, 0.0025, Attenuation::default());
+            let light_scene = scene(vec![floor()]);
+            let from_light =
+                light_scene.compute_lighting_directional(light_scene.objects[0].as_ref(), 0, &light, &Point3D::default(), &ray).x;
+            assert!(from_light > 0.0 && (from_emitter / from_light - 1.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn emitters_hidden_from_reflections_light_nothing() {
+        let floor = Rectangle::new(Point3D::new(-10.0, 0.0, -10.0), Vector3D::new(0.0, 0.0, 20.0), Vector3D::new(20.0, 0.0, 0.0), WHITE);
+        let ray = Ray::new(Point3D::new(0.0, 0.5, 0.5), Vector3D::new(0.0, -1.0, -1.0).normalize());
+        let lit = |reflections: bool| {
+            let ball = glowing(Box::new(Sphere::new(Point3D::new(0.0, 2.0, 0.0), 0.5, WHITE)));
+            let ball = Tagged::new(ball, None, Visibility { reflections, ..Visibility::default() });
+            let scene = scene(vec![Box::new(floor), Box::new(ball)]);
+            scene.compute_lighting_emitters(scene.objects[0].as_ref(), 0, &Point3D::default(), &ray).x
+        };
+        assert!(lit(true) > 0.0);
+        assert_eq!(lit(false), 0.0);
+    }
+
+    #[test]
+    fn glass_tints_the_light_going_through_it() {
+        let pane = |height: f64, transmission: Vector3D| -> Box<dyn Object> {
+            let rectangle = Rectangle::new(Point3D::new(-1.0, height, -1.0), Vector3D::new(2.0, 0.0, 0.0), Vector3D::new(0.0, 0.0, 2.0), WHITE);
+            Box::new(Materialized::new(Box::new(rectangle), Material { transmission, ..Material::default() }))
+        };
+        let red = Vector3D::new(1.0, 0.2, 0.2);
+        let yellow = Vector3D::new(1.0, 1.0, 0.5);
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        let light = scene(vec![pane(1.0, red)]).transmittance(9, Point3D::default(), up, 10.0);
+        assert!((light - red).length() < 1e-9);
+        // panes stacked on the way multiply, an opaque one stops the light
+        let light = scene(vec![pane(1.0, red), pane(2.0, yellow)]).transmittance(9, Point3D::default(), up, 10.0);
+        assert!((light - Vector3D::new(1.0, 0.2, 0.1)).length() < 1e-9);
+        let opaque = Rectangle::new(Point3D::new(-1.0, 3.0, -1.0), Vector3D::new(2.0, 0.0, 0.0), Vector3D::new(0.0, 0.0, 2.0), WHITE);
+        assert!(scene(vec![pane(1.0, red), Box::new(opaque)]).transmittance(9, Point3D::default(), up, 10.0).is_black());
+    }
+
+    #[test]
+    fn bounced_rays_go_through_the_glass_parts_only() {
+        let glass = |center: Point3D| -> Box<dyn Object> {
+            let material = Material { transmission: Vector3D::new(1.0, 1.0, 1.0), ..Material::default() };
+            Box::new(Materialized::new(Box::new(Sphere::new(center, 1.0, WHITE)), material))
+        };
+        // the union is centered on its glass ball but the ray meets its opaque one
+        let union = Csg::new(CsgOperation::Union, glass(Point3D::new(10.0, 5.0, 0.0)), Box::new(Sphere::new(Point3D::new(0.0, 5.0, 0.0), 1.0, WHITE)));
+        let up = Ray::new(Point3D::default(), Vector3D::new(0.0, 1.0, 0.0));
+        let (_, hit_point) = scene(vec![Box::new(union)]).closest_hit(9, &up).unwrap();
+        assert!((hit_point.y - 4.0).abs() < 1e-9);
+        assert!(scene(vec![glass(Point3D::new(0.0, 5.0, 0.0))]).closest_hit(9, &up).is_none());
+    }
+
+    #[test]
+    fn visibility_flags_control_shadows() {
+        let tagged = |visibility: Visibility| -> Box<dyn Object> {
+            Box::new(Tagged::new(Box::new(Sphere::new(Point3D::new(0.0, 5.0, 0.0), 1.0, WHITE)), None, visibility))
+        };
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        let blocked = |scene: &Scene| scene.transmittance(9, Point3D::default(), up, 10.0).is_black();
+        assert!(blocked(&scene(vec![tagged(Visibility::default())])));
+        assert!(!blocked(&scene(vec![tagged(Visibility { shadows: false, ..Visibility::default() })])));
+        // a ball hidden from the camera still casts its shadow
+        assert!(blocked(&scene(vec![tagged(Visibility { camera: false, ..Visibility::default() })])));
+        // a floor that receives no shadows ignores the ball
+        let floor = Tagged::new(Box::new(Sphere::new(Point3D::new(0.0, -1.0, 0.0), 1.0, WHITE)), None, Visibility { receive_shadows: false, ..Visibility::default() });
+        let scene = scene(vec![tagged(Visibility::default())]);
+        assert!(!scene.shadowing(&floor, 9, Point3D::default(), up, 10.0).is_black());
+    }
+}
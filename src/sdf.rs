@@ -0,0 +1,431 @@
+use serde::Deserialize;
+
+use crate::bvh::Aabb;
+use crate::math::{Point3D, Vector3D};
+use crate::object::{Interval, Object};
+use crate::raytracer::Ray;
+
+const SURFACE_EPSILON: f64 = 1e-4;
+const NORMAL_EPSILON: f64 = 1e-4;
+const MAX_STEPS: usize = 512;
+// how far past its entry a ray is marched when the bounds never end along it
+const MAX_DISTANCE: f64 = 100.0;
+
+fn zero() -> f64 {
+    0.0
+}
+
+// distance field expression, primitives are centered on the origin of their local space
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SdfNode {
+    Sphere {
+        radius: f64,
+    },
+    Box {
+        half_size: Vector3D,
+    },
+    RoundBox {
+        half_size: Vector3D,
+        radius: f64,
+    },
+    Capsule {
+        a: Point3D,
+        b: Point3D,
+        radius: f64,
+    },
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Translate {
+        offset: Vector3D,
+        node: Box<SdfNode>,
+    },
+    Union {
+        left: Box<SdfNode>,
+        right: Box<SdfNode>,
+        #[serde(default = "zero")]
+        smoothness: f64,
+    },
+    Subtraction {
+        left: Box<SdfNode>,
+        right: Box<SdfNode>,
+        #[serde(default = "zero")]
+        smoothness: f64,
+    },
+    Intersection {
+        left: Box<SdfNode>,
+        right: Box<SdfNode>,
+        #[serde(default = "zero")]
+        smoothness: f64,
+    },
+    // infinite copies, a period of 0 leaves that axis alone
+    Repeat {
+        period: Vector3D,
+        node: Box<SdfNode>,
+    },
+    // rotates around Y by `amount` radians per unit of height
+    Twist {
+        amount: f64,
+        node: Box<SdfNode>,
+    },
+}
+
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+fn smooth_max(a: f64, b: f64, k: f64) -> f64 {
+    -smooth_min(-a, -b, k)
+}
+
+fn box_distance(p: &Vector3D, half_size: &Vector3D) -> f64 {
+    let q = Vector3D::new(p.x.abs() - half_size.x, p.y.abs() - half_size.y, p.z.abs() - half_size.z);
+    let outside = Vector3D::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+    outside + q.x.max(q.y.max(q.z)).min(0.0)
+}
+
+fn repeat_axis(value: f64, period: f64) -> f64 {
+    if period <= 0.0 {
+        return value;
+    }
+    value - period * (value / period).round()
+}
+
+impl SdfNode {
+    pub fn distance(&self, p: &Vector3D) -> f64 {
+        match self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Box { half_size } => box_distance(p, half_size),
+            SdfNode::RoundBox { half_size, radius } => {
+                let inner = Vector3D::new(half_size.x - radius, half_size.y - radius, half_size.z - radius);
+                box_distance(p, &inner) - radius
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let pa = *p - (*a - Point3D::default());
+                let ba = *b - *a;
+                // equal end points leave a sphere around them
+                let length2 = ba.dot(&ba);
+                let h = if length2 > 0.0 { (pa.dot(&ba) / length2).clamp(0.0, 1.0) } else { 0.0 };
+                (pa - ba * h).length() - radius
+            }
+            SdfNode::Torus { major_radius, minor_radius } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            SdfNode::Translate { offset, node } => node.distance(&(*p - *offset)),
+            SdfNode::Union { left, right, smoothness } => {
+                smooth_min(left.distance(p), right.distance(p), *smoothness)
+            }
+            SdfNode::Subtraction { left, right, smoothness } => {
+                smooth_max(left.distance(p), -right.distance(p), *smoothness)
+            }
+            SdfNode::Intersection { left, right, smoothness } => {
+                smooth_max(left.distance(p), right.distance(p), *smoothness)
+            }
+            SdfNode::Repeat { period, node } => node.distance(&Vector3D::new(
+                repeat_axis(p.x, period.x),
+                repeat_axis(p.y, period.y),
+                repeat_axis(p.z, period.z),
+            )),
+            SdfNode::Twist { amount, node } => {
+                let (sin, cos) = (amount * p.y).sin_cos();
+                node.distance(&Vector3D::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let symmetric = |extent: Vector3D| Aabb::from_points(&[Point3D::default() - extent, Point3D::default() + extent]);
+        match self {
+            SdfNode::Sphere { radius } => symmetric(Vector3D::new(*radius, *radius, *radius)),
+            SdfNode::Box { half_size } | SdfNode::RoundBox { half_size, .. } => symmetric(*half_size),
+            SdfNode::Capsule { a, b, radius } => {
+                let extent = Vector3D::new(*radius, *radius, *radius);
+                Aabb::from_points(&[*a - extent, *a + extent, *b - extent, *b + extent])
+            }
+            SdfNode::Torus { major_radius, minor_radius } => {
+                let outer = major_radius + minor_radius;
+                symmetric(Vector3D::new(outer, *minor_radius, outer))
+            }
+            SdfNode::Translate { offset, node } => {
+                let inner = node.bounds();
+                Aabb::from_points(&[inner.min + *offset, inner.max + *offset])
+            }
+            SdfNode::Union { left, right, smoothness } => {
+                let merged = left.bounds().union(&right.bounds());
+                let extent = Vector3D::new(*smoothness, *smoothness, *smoothness);
+                Aabb::from_points(&[merged.min - extent, merged.max + extent])
+            }
+            SdfNode::Subtraction { left, .. } | SdfNode::Intersection { left, .. } => left.bounds(),
+            SdfNode::Repeat { period, node } => {
+                let inner = node.bounds();
+                let axis = |period: f64, min: f64, max: f64| {
+                    if period > 0.0 { (f64::NEG_INFINITY, f64::INFINITY) } else { (min, max) }
+                };
+                let (min_x, max_x) = axis(period.x, inner.min.x, inner.max.x);
+                let (min_y, max_y) = axis(period.y, inner.min.y, inner.max.y);
+                let (min_z, max_z) = axis(period.z, inner.min.z, inner.max.z);
+                Aabb { min: Point3D::new(min_x, min_y, min_z), max: Point3D::new(max_x, max_y, max_z) }
+            }
+            SdfNode::Twist { node, .. } => {
+                let inner = node.bounds();
+                let radius = Self::radial_extent(&inner);
+                Aabb::from_points(&[
+                    Point3D::new(-radius, inner.min.y, -radius),
+                    Point3D::new(radius, inner.max.y, radius),
+                ])
+            }
+        }
+    }
+
+    // bound on the gradient length, the marching steps are divided by it so they never overshoot
+    pub fn lipschitz(&self) -> f64 {
+        match self {
+            SdfNode::Translate { node, .. } | SdfNode::Repeat { node, .. } => node.lipschitz(),
+            SdfNode::Union { left, right, .. }
+            | SdfNode::Subtraction { left, right, .. }
+            | SdfNode::Intersection { left, right, .. } => left.lipschitz().max(right.lipschitz()),
+            SdfNode::Twist { amount, node } => {
+                let radius = Self::radial_extent(&node.bounds());
+                let stretch = if radius.is_finite() { (1.0 + (amount * radius).powi(2)).sqrt() } else { 4.0 };
+                node.lipschitz() * stretch
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn radial_extent(bounds: &Aabb) -> f64 {
+        let x = bounds.min.x.abs().max(bounds.max.x.abs());
+        let z = bounds.min.z.abs().max(bounds.max.z.abs());
+        (x * x + z * z).sqrt()
+    }
+}
+
+// sphere traces from `t` to `t_max` until `distance` changes sign, steps are divided by `lipschitz`
+// so fields that stretch space never overshoot the surface
+pub fn march<F>(distance: F, ray: &Ray, mut t: f64, t_max: f64, lipschitz: f64) -> Option<f64>
+where
+    F: Fn(&Point3D) -> f64,
+{
+    let speed = ray.direction.length();
+    let outside = distance(&(ray.origin + ray.direction * t)) >= 0.0;
+    for _ in 0..MAX_STEPS {
+        if t > t_max {
+            return None;
+        }
+        let step = distance(&(ray.origin + ray.direction * t));
+        let step = if outside { step } else { -step };
+        if step < SURFACE_EPSILON {
+            return Some(t);
+        }
+        t += step / (lipschitz * speed);
+    }
+    None
+}
+
+// solid spans of the field between `start` and `end`, found by marching from one crossing to the next
+pub fn march_intervals<F>(distance: F, ray: &Ray, start: f64, end: f64, lipschitz: f64) -> Vec<Interval>
+where
+    F: Fn(&Point3D) -> f64,
+{
+    let mut intervals = Vec::new();
+    let step_over = 2.0 * SURFACE_EPSILON / ray.direction.length();
+    let mut t = start;
+    let mut enter = if distance(&(ray.origin + ray.direction * t)) < 0.0 {
+        Some(f64::NEG_INFINITY)
+    } else {
+        None
+    };
+    while let Some(crossing) = march(&distance, ray, t, end, lipschitz) {
+        match enter {
+            Some(entry) => {
+                intervals.push(Interval::new(entry, crossing));
+                enter = None;
+            }
+            None => enter = Some(crossing),
+        }
+        t = crossing + step_over;
+    }
+    if let Some(entry) = enter {
+        intervals.push(Interval::new(entry, end));
+    }
+    intervals
+}
+
+// gradient of the field with the tetrahedron technique
+pub fn gradient_normal<F>(distance: F, point: &Point3D) -> Vector3D
+where
+    F: Fn(&Point3D) -> f64,
+{
+    let offsets = [
+        Vector3D::new(1.0, -1.0, -1.0),
+        Vector3D::new(-1.0, -1.0, 1.0),
+        Vector3D::new(-1.0, 1.0, -1.0),
+        Vector3D::new(1.0, 1.0, 1.0),
+    ];
+    offsets
+        .iter()
+        .fold(Vector3D::default(), |gradient, offset| {
+            gradient + *offset * distance(&(*point + *offset * NORMAL_EPSILON))
+        })
+        .normalize()
+}
+
+// range of the ray worth marching, clipped to `bounds`, only capped when the ray never leaves them
+pub fn march_range(bounds: &Aabb, ray: &Ray) -> Option<(f64, f64)> {
+    let (enter, exit) = bounds.hit_range(ray)?;
+    if exit < 0.0 {
+        return None;
+    }
+    let start = enter.max(0.0);
+    if exit.is_finite() {
+        return Some((start, exit));
+    }
+    Some((start, start + MAX_DISTANCE / ray.direction.length()))
+}
+
+pub struct Sdf {
+    pub root: SdfNode,
+    pub center: Point3D,
+    pub color: Vector3D,
+    lipschitz: f64,
+}
+
+impl Sdf {
+    pub fn new(root: SdfNode, center: Point3D, color: Vector3D) -> Sdf {
+        let lipschitz = root.lipschitz().max(1.0);
+        Sdf { root, center, color, lipschitz }
+    }
+
+    fn distance(&self, point: &Point3D) -> f64 {
+        self.root.distance(&(*point - self.center))
+    }
+}
+
+impl Object for Sdf {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let (start, end) = march_range(&self.bounds(), &ray)?;
+        let t = march(|point| self.distance(point), &ray, start, end, self.lipschitz)?;
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        match march_range(&self.bounds(), &ray) {
+            Some((start, end)) => march_intervals(|point| self.distance(point), &ray, start, end, self.lipschitz),
+            None => Vec::new(),
+        }
+    }
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        gradient_normal(|point| self.distance(point), hit_point)
+    }
+    fn get_center(&self) -> Point3D {
+        self.center
+    }
+    fn bounds(&self) -> Aabb {
+        let local = self.root.bounds();
+        let offset = self.center - Point3D::default();
+        Aabb::from_points(&[local.min + offset, local.max + offset])
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    fn get_albedo(&self, _hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        self.color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    fn sphere(radius: f64) -> Box<SdfNode> {
+        Box::new(SdfNode::Sphere { radius })
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let p = Vector3D::new(3.0, 4.0, 0.0);
+        assert!(close(SdfNode::Sphere { radius: 2.0 }.distance(&p), 3.0));
+        let cube = SdfNode::Box { half_size: Vector3D::new(1.0, 1.0, 1.0) };
+        assert!(close(cube.distance(&Vector3D::new(3.0, 0.0, 0.0)), 2.0));
+        assert!(close(cube.distance(&Vector3D::default()), -1.0));
+        let torus = SdfNode::Torus { major_radius: 2.0, minor_radius: 0.5 };
+        assert!(close(torus.distance(&Vector3D::new(2.0, 0.0, 0.0)), -0.5));
+        let capsule = SdfNode::Capsule { a: Point3D::new(0.0, -1.0, 0.0), b: Point3D::new(0.0, 1.0, 0.0), radius: 0.5 };
+        assert!(close(capsule.distance(&Vector3D::new(2.0, 0.3, 0.0)), 1.5));
+        assert!(close(capsule.distance(&Vector3D::new(0.0, 3.0, 0.0)), 1.5));
+    }
+
+    #[test]
+    fn capsule_with_equal_ends_is_a_sphere() {
+        let point = Point3D::new(1.0, 2.0, 3.0);
+        let capsule = SdfNode::Capsule { a: point, b: point, radius: 0.5 };
+        let distance = capsule.distance(&Vector3D::new(1.0, 2.0, 5.0));
+        assert!(close(distance, 1.5), "{}", distance);
+        assert!(close(capsule.distance(&Vector3D::new(1.0, 2.0, 3.0)), -0.5));
+    }
+
+    #[test]
+    fn combinations() {
+        let moved = |x: f64| Box::new(SdfNode::Translate { offset: Vector3D::new(x, 0.0, 0.0), node: sphere(1.0) });
+        let union = SdfNode::Union { left: moved(-1.0), right: moved(1.0), smoothness: 0.0 };
+        assert!(close(union.distance(&Vector3D::new(3.0, 0.0, 0.0)), 1.0));
+        // blending pulls the surface out between the two spheres
+        let blended = SdfNode::Union { left: moved(-1.0), right: moved(1.0), smoothness: 0.5 };
+        assert!(blended.distance(&Vector3D::new(0.0, 0.5, 0.0)) < union.distance(&Vector3D::new(0.0, 0.5, 0.0)));
+        let bitten = SdfNode::Subtraction { left: sphere(1.0), right: moved(1.0), smoothness: 0.0 };
+        assert!(bitten.distance(&Vector3D::new(0.9, 0.0, 0.0)) > 0.0);
+        assert!(bitten.distance(&Vector3D::new(-0.5, 0.0, 0.0)) < 0.0);
+        let lens = SdfNode::Intersection { left: moved(-0.5), right: moved(0.5), smoothness: 0.0 };
+        assert!(close(lens.distance(&Vector3D::default()), -0.5));
+        let repeated = SdfNode::Repeat { period: Vector3D::new(4.0, 0.0, 0.0), node: sphere(1.0) };
+        assert!(close(repeated.distance(&Vector3D::new(8.0, 2.0, 0.0)), 1.0));
+        assert!(!repeated.bounds().is_finite());
+        assert!(close(repeated.bounds().max.y, 1.0) && repeated.bounds().max.x.is_infinite());
+    }
+
+    #[test]
+    fn repeats_are_marched_to_the_end_of_their_bounds() {
+        let row = SdfNode::Repeat { period: Vector3D::new(4.0, 0.0, 0.0), node: sphere(1.0) };
+        let sdf = Sdf::new(row, Point3D::new(0.0, 0.0, -150.0), WHITE);
+        let hit = sdf.hits(Ray::new(Point3D::new(8.0, 0.0, 0.0), Vector3D::new(0.0, 0.0, -1.0))).unwrap();
+        assert!(close(hit.z, -149.0));
+        // along the row the bounds never end, the march stops at its distance cap instead
+        let along = Ray::new(Point3D::new(-500.0, 0.0, -150.0), Vector3D::new(1.0, 0.0, 0.0));
+        assert!(sdf.hits(along).is_some());
+    }
+
+    #[test]
+    fn marching_finds_the_surface_and_its_spans() {
+        let sdf = Sdf::new(SdfNode::Sphere { radius: 1.0 }, Point3D::new(0.0, 0.0, -5.0), WHITE);
+        let ray = Ray::new(Point3D::default(), Vector3D::new(0.0, 0.0, -1.0));
+        let hit = sdf.hits(ray).unwrap();
+        assert!(close(hit.z, -4.0));
+        let normal = sdf.surface_normal(&hit);
+        assert!(close(normal.z, 1.0));
+        let intervals = sdf.intervals(ray);
+        assert_eq!(intervals.len(), 1);
+        assert!(close(intervals[0].enter, 4.0) && close(intervals[0].exit, 6.0));
+        assert!(sdf.hits(Ray::new(Point3D::default(), Vector3D::new(0.0, 1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn twist_slows_the_march_down() {
+        let twisted = SdfNode::Twist { amount: 2.0, node: Box::new(SdfNode::Box { half_size: Vector3D::new(1.0, 2.0, 0.2) }) };
+        assert!(twisted.lipschitz() > 1.0);
+        let sdf = Sdf::new(twisted, Point3D::default(), WHITE);
+        let hit = sdf.hits(Ray::new(Point3D::new(0.0, 0.0, 5.0), Vector3D::new(0.0, 0.0, -1.0))).unwrap();
+        assert!(sdf.distance(&hit).abs() < 1e-3);
+    }
+}
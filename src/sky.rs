@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+
+use crate::environment::Environment;
+use crate::light::DirectionalLight;
+use crate::math::Vector3D;
+
+// size of the map the sky is baked into
+const SKY_WIDTH: usize = 256;
+const SKY_HEIGHT: usize = 128;
+// kcd/m² of the Preetham model to the radiance of the renderer, where 1 is white
+const LUMINANCE_SCALE: f64 = 0.04;
+// the ground mirrors a dimmed horizon
+const GROUND_ALBEDO: f64 = 0.3;
+
+// Perez distribution for the angle θ from the zenith and γ from the sun
+fn perez(coefficients: [f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = coefficients;
+    (1.0 + a * (b / cos_theta.max(1e-3)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Vector3D {
+    if y <= 0.0 {
+        return Vector3D::new(0.0, 0.0, 0.0);
+    }
+    let (big_x, big_z) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+    Vector3D::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+// direction toward a point of the sky, azimuth 0 is north (-Z) and 90 east (+X), angles in degrees
+pub fn direction(elevation: f64, azimuth: f64) -> Vector3D {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    Vector3D::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos())
+}
+
+// day of the year of a "YYYY-MM-DD" date, leap years are ignored
+pub fn day_of_year(date: &str) -> Option<u32> {
+    const MONTH_LENGTHS: [u32; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let mut parts = date.split('-').skip(1);
+    let month: usize = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) {
+        return None;
+    }
+    // February 29 is taken as March 1
+    let length = if month == 2 { 29 } else { MONTH_LENGTHS[month - 1] };
+    if !(1..=length).contains(&day) {
+        return None;
+    }
+    Some(MONTH_LENGTHS[..month - 1].iter().sum::<u32>() + day)
+}
+
+// elevation and azimuth of the sun in degrees for a day of the year, a local solar time in hours and a latitude
+pub fn solar_position(day: u32, time: f64, latitude: f64) -> (f64, f64) {
+    let declination = (23.44 * (2.0 * PI * (284.0 + day as f64) / 365.0).sin()).to_radians();
+    let hour_angle = (15.0 * (time - 12.0)).to_radians();
+    let latitude = latitude.to_radians();
+    let sin_elevation =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+    // at the poles or with the sun overhead the azimuth is undefined, the sun is then put due south
+    let divisor = elevation.cos() * latitude.cos();
+    if divisor.abs() < 1e-9 {
+        return (elevation.to_degrees(), 180.0);
+    }
+    let cos_azimuth = (declination.sin() - sin_elevation * latitude.sin()) / divisor;
+    let azimuth = cos_azimuth.clamp(-1.0, 1.0).acos().to_degrees();
+    // afternoon suns are west of south
+    let azimuth = if hour_angle > 0.0 { 360.0 - azimuth } else { azimuth };
+    (elevation.to_degrees(), azimuth)
+}
+
+// clear sky of Preetham, Shirley and Smits, `turbidity` from 2 (very clear) to about 10 (hazy)
+pub struct Sky {
+    pub turbidity: f64,
+    pub sun_direction: Vector3D,
+    zenith: (f64, f64, f64),
+    coefficients: [[f64; 5]; 3],
+}
+
+impl Sky {
+    pub fn new(turbidity: f64, elevation: f64, azimuth: f64) -> Sky {
+        let t = turbidity;
+        let sun_direction = direction(elevation, azimuth);
+        let theta_sun = (PI / 2.0 - elevation.to_radians()).clamp(0.0, PI / 2.0);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |matrix: [[f64; 4]; 3]| {
+            let powers = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            let row = |index: usize| matrix[index].iter().zip(powers).map(|(m, p)| m * p).sum::<f64>();
+            t * t * row(0) + t * row(1) + row(2)
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        Sky { turbidity, sun_direction, zenith: (zenith_luminance, zenith_x, zenith_y), coefficients }
+    }
+
+    // linear radiance of the sky in a direction, the sun itself is left to `sun_light`
+    pub fn radiance(&self, direction: &Vector3D) -> Vector3D {
+        let direction = direction.normalize();
+        let below = direction.y < 0.0;
+        // the ground takes the color of the horizon above it
+        let cos_theta = direction.y.abs().max(0.01);
+        let horizontal = Vector3D::new(direction.x, cos_theta, direction.z).normalize();
+        let theta_sun = self.sun_direction.y.clamp(0.0, 1.0).acos();
+        let gamma = horizontal.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |index: usize, zenith: f64| {
+            zenith * perez(self.coefficients[index], cos_theta, gamma)
+                / perez(self.coefficients[index], 1.0, theta_sun)
+        };
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith;
+        let color = xyy_to_rgb(value(1, zenith_x), value(2, zenith_y), value(0, zenith_luminance) * LUMINANCE_SCALE);
+        if below {
+            color * GROUND_ALBEDO
+        } else {
+            color
+        }
+    }
+
+    // fraction of each of red, green and blue sunlight crossing the atmosphere (Rayleigh and aerosols)
+    fn sun_transmittance(&self) -> Vector3D {
+        let elevation = self.sun_direction.y.clamp(-1.0, 1.0).asin().to_degrees();
+        if elevation <= 0.0 {
+            return Vector3D::new(0.0, 0.0, 0.0);
+        }
+        let zenith_angle = 90.0 - elevation;
+        let air_mass = 1.0 / (zenith_angle.to_radians().cos() + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmit = |wavelength: f64| {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            (-(rayleigh + aerosol) * air_mass).exp()
+        };
+        Vector3D::new(transmit(0.68), transmit(0.55), transmit(0.44))
+    }
+
+    // sun matching the sky, reddened and dimmed as it goes down
+    pub fn sun_light(&self, intensity: f64) -> DirectionalLight {
+        let transmittance = self.sun_transmittance();
+        let strongest = transmittance.x.max(transmittance.y).max(transmittance.z);
+        if strongest <= 0.0 {
+            return DirectionalLight::new(self.sun_direction, Vector3D::new(0.0, 0.0, 0.0), 0.0);
+        }
+        DirectionalLight::new(self.sun_direction, transmittance / strongest * 255.0, intensity * strongest)
+    }
+
+    // bakes the sky into a map so it is drawn and sampled like any environment
+    pub fn to_environment(&self, intensity: f64, samples: usize) -> Environment {
+        let mut texels = Vec::with_capacity(SKY_WIDTH * SKY_HEIGHT);
+        for row in 0..SKY_HEIGHT {
+            let theta = (row as f64 + 0.5) / SKY_HEIGHT as f64 * PI;
+            for column in 0..SKY_WIDTH {
+                let phi = ((column as f64 + 0.5) / SKY_WIDTH as f64 - 0.5) * 2.0 * PI;
+                let direction = Vector3D::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+                texels.push(self.radiance(&direction));
+            }
+        }
+        Environment::new(SKY_WIDTH, SKY_HEIGHT, texels, 0.0, intensity, samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_count_days_from_january() {
+        assert_eq!(day_of_year("2024-01-01"), Some(1));
+        assert_eq!(day_of_year("2024-03-22"), Some(81));
+        assert_eq!(day_of_year("2024-12-31"), Some(365));
+        assert_eq!(day_of_year("2024-02-31"), None);
+        assert_eq!(day_of_year("2024-13-01"), None);
+        assert_eq!(day_of_year("today"), None);
+    }
+
+    #[test]
+    fn the_sun_follows_the_day() {
+        // equinox noon at 45° north, halfway up in the south
+        let (elevation, azimuth) = solar_position(81, 12.0, 45.0);
+        assert!((elevation - 45.0).abs() < 0.5 && (azimuth - 180.0).abs() < 1e-6);
+        // rising in the east in the morning and setting in the west
+        assert!(solar_position(81, 8.0, 45.0).1 < 180.0);
+        assert!(solar_position(81, 16.0, 45.0).1 > 180.0);
+        assert!(solar_position(81, 0.0, 45.0).0 < 0.0);
+        // the pole has no north, the angles stay numbers
+        let (elevation, azimuth) = solar_position(172, 12.0, 90.0);
+        assert!((elevation - 23.44).abs() < 0.1 && azimuth.is_finite());
+    }
+
+    #[test]
+    fn the_sky_is_brightest_around_the_sun() {
+        let sky = Sky::new(3.0, 30.0, 90.0);
+        assert!((sky.sun_direction - direction(30.0, 90.0)).length() < 1e-9);
+        let toward_sun = sky.radiance(&direction(35.0, 90.0));
+        let away = sky.radiance(&direction(35.0, 270.0));
+        assert!(toward_sun.y > away.y);
+        // a clear sky is bluer than it is red away from the sun
+        assert!(away.z > away.x);
+        assert!(sky.radiance(&Vector3D::new(0.0, -1.0, 0.0)).y < sky.radiance(&Vector3D::new(0.0, 1.0, 0.0)).y);
+    }
+
+    #[test]
+    fn the_sun_reddens_as_it_sets() {
+        let high = Sky::new(3.0, 60.0, 180.0).sun_light(1.0);
+        let low = Sky::new(3.0, 5.0, 180.0).sun_light(1.0);
+        assert!(low.intensity < high.intensity);
+        assert!(low.color.z / low.color.x < high.color.z / high.color.x);
+        assert_eq!(Sky::new(3.0, -10.0, 180.0).sun_light(1.0).intensity, 0.0);
+    }
+}
@@ -0,0 +1,416 @@
+use std::error::Error;
+use std::fs;
+
+use crate::bvh::Aabb;
+use crate::math::{Point3D, Vector3D};
+use crate::object::{Interval, Object};
+use crate::raytracer::Ray;
+
+const EPSILON: f64 = 1e-6;
+// how close to a voxel face a point has to be to count as lying on it
+const FACE_EPSILON: f64 = 1e-4;
+// cells a grid may hold, a byte each
+const MAX_CELLS: usize = 1 << 28;
+
+fn components(point: &Point3D) -> [f64; 3] {
+    [point.x, point.y, point.z]
+}
+
+// number of cells of a grid, refusing empty axes and grids too large to allocate
+pub fn cell_count(dimensions: [usize; 3]) -> Result<usize, Box<dyn Error>> {
+    if dimensions.contains(&0) {
+        return Err(format!("Voxel grid dimensions {:?} have an empty axis", dimensions).into());
+    }
+    dimensions
+        .iter()
+        .try_fold(1usize, |count, size| count.checked_mul(*size))
+        .filter(|count| *count <= MAX_CELLS)
+        .ok_or_else(|| format!("Voxel grid dimensions {:?} hold more than {} cells", dimensions, MAX_CELLS).into())
+}
+
+// dense grid of cubes starting at `corner`, each cell holds a palette index and 0 means empty
+pub struct VoxelGrid {
+    pub corner: Point3D,
+    pub voxel_size: f64,
+    pub dimensions: [usize; 3],
+    pub cells: Vec<u8>,
+    pub palette: Vec<Vector3D>,
+    pub color: Vector3D,
+}
+
+impl VoxelGrid {
+    pub fn new(
+        corner: Point3D,
+        voxel_size: f64,
+        dimensions: [usize; 3],
+        cells: Vec<u8>,
+        palette: Vec<Vector3D>,
+        color: Vector3D,
+    ) -> VoxelGrid {
+        VoxelGrid { corner, voxel_size, dimensions, cells, palette, color }
+    }
+
+    // MagicaVoxel file, its Z up models are turned so that Y is up
+    // palette entries missing from the file fall back to `color`
+    pub fn load_vox(path: &str, model: usize, corner: Point3D, voxel_size: f64, color: Vector3D) -> Result<VoxelGrid, Box<dyn Error>> {
+        VoxelGrid::parse_vox(&fs::read(path)?, model, corner, voxel_size, color)
+    }
+
+    pub fn parse_vox(bytes: &[u8], model: usize, corner: Point3D, voxel_size: f64, color: Vector3D) -> Result<VoxelGrid, Box<dyn Error>> {
+        if bytes.len() < 8 || &bytes[0..4] != b"VOX " {
+            return Err("Not a MagicaVoxel file".into());
+        }
+        let read_u32 = |offset: usize| -> Result<usize, Box<dyn Error>> {
+            let chunk = bytes.get(offset..offset + 4).ok_or("Truncated VOX file")?;
+            Ok(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize)
+        };
+        let mut sizes: Vec<[usize; 3]> = Vec::new();
+        let mut models: Vec<&[u8]> = Vec::new();
+        let mut palette: Option<&[u8]> = None;
+        // the MAIN chunk header is skipped, its children follow each other up to the end of the file
+        let mut offset = 8 + 12;
+        while offset + 12 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let content_size = read_u32(offset + 4)?;
+            let children_size = read_u32(offset + 8)?;
+            let content = bytes
+                .get(offset + 12..offset + 12 + content_size)
+                .ok_or("Truncated VOX chunk")?;
+            match id {
+                b"SIZE" => sizes.push([read_u32(offset + 12)?, read_u32(offset + 16)?, read_u32(offset + 20)?]),
+                b"XYZI" => models.push(content),
+                b"RGBA" => palette = Some(content),
+                _ => {}
+            }
+            offset += 12 + content_size + children_size;
+        }
+        let size = *sizes.get(model).ok_or_else(|| format!("VOX file has no model {}", model))?;
+        let voxels = *models.get(model).ok_or_else(|| format!("VOX file has no voxels for model {}", model))?;
+        if voxels.len() < 4 {
+            return Err("Truncated VOX voxel chunk".into());
+        }
+        let count = u32::from_le_bytes([voxels[0], voxels[1], voxels[2], voxels[3]]) as usize;
+        let dimensions = [size[0], size[2], size[1]];
+        let mut cells = vec![0; cell_count(dimensions)?];
+        for voxel in voxels[4..].chunks_exact(4).take(count) {
+            let (x, y, z) = (voxel[0] as usize, voxel[1] as usize, voxel[2] as usize);
+            if x >= size[0] || y >= size[1] || z >= size[2] {
+                continue;
+            }
+            cells[(x + z * dimensions[0]) + (size[1] - 1 - y) * dimensions[0] * dimensions[1]] = voxel[3];
+        }
+        // color index i is stored at entry i - 1 of the RGBA chunk
+        let mut colors = vec![color; 256];
+        if let Some(palette) = palette {
+            for (index, rgba) in palette.chunks_exact(4).take(255).enumerate() {
+                colors[index + 1] = Vector3D::new(rgba[0] as f64, rgba[1] as f64, rgba[2] as f64);
+            }
+        }
+        Ok(VoxelGrid::new(corner, voxel_size, dimensions, cells, colors, color))
+    }
+
+    fn index(&self, cell: [usize; 3]) -> usize {
+        cell[0] + cell[1] * self.dimensions[0] + cell[2] * self.dimensions[0] * self.dimensions[1]
+    }
+
+    fn is_filled(&self, cell: [isize; 3]) -> bool {
+        if (0..3).any(|axis| cell[axis] < 0 || cell[axis] as usize >= self.dimensions[axis]) {
+            return false;
+        }
+        self.cells[self.index([cell[0] as usize, cell[1] as usize, cell[2] as usize])] != 0
+    }
+
+    fn grid_coordinates(&self, point: &Point3D) -> [f64; 3] {
+        components(&(Point3D::default() + (*point - self.corner) / self.voxel_size))
+    }
+
+    // walks the cells along the ray from `t_min` with a 3D DDA, `visit` gets each cell and the
+    // distance at which the ray enters it, and stops the walk by returning false
+    fn traverse<F>(&self, ray: &Ray, t_min: f64, mut visit: F)
+    where
+        F: FnMut(bool, f64) -> bool,
+    {
+        if self.dimensions.contains(&0) {
+            return;
+        }
+        let Some((enter, exit)) = self.bounds().hit_range(ray) else {
+            return;
+        };
+        let mut t = enter.max(t_min);
+        if t > exit {
+            return;
+        }
+        let start = self.grid_coordinates(&(ray.origin + ray.direction * t));
+        let origin = self.grid_coordinates(&ray.origin);
+        let direction = components(&(Point3D::default() + ray.direction / self.voxel_size));
+        let mut cell = [0isize; 3];
+        let mut step = [0isize; 3];
+        let mut next = [f64::INFINITY; 3];
+        let mut delta = [f64::INFINITY; 3];
+        for axis in 0..3 {
+            cell[axis] = (start[axis].floor() as isize).clamp(0, self.dimensions[axis] as isize - 1);
+            if direction[axis] != 0.0 {
+                step[axis] = if direction[axis] > 0.0 { 1 } else { -1 };
+                let boundary = (cell[axis] + isize::from(direction[axis] > 0.0)) as f64;
+                next[axis] = (boundary - origin[axis]) / direction[axis];
+                delta[axis] = 1.0 / direction[axis].abs();
+            }
+        }
+        loop {
+            if !visit(self.is_filled(cell), t) {
+                return;
+            }
+            let axis = if next[0] < next[1] && next[0] < next[2] {
+                0
+            } else if next[1] < next[2] {
+                1
+            } else {
+                2
+            };
+            t = next[axis];
+            cell[axis] += step[axis];
+            if t > exit || cell[axis] < 0 || cell[axis] >= self.dimensions[axis] as isize {
+                return;
+            }
+            next[axis] += delta[axis];
+        }
+    }
+
+    // face of a filled voxel the point lies on, as the axis and the side the empty neighbour is on
+    fn face(&self, point: &Point3D) -> Option<(usize, isize)> {
+        let grid = self.grid_coordinates(point);
+        (0..3).find_map(|axis| {
+            let boundary = grid[axis].round();
+            if (grid[axis] - boundary).abs() > FACE_EPSILON {
+                return None;
+            }
+            let mut low = grid.map(|value| value.floor() as isize);
+            low[axis] = boundary as isize - 1;
+            let mut high = low;
+            high[axis] += 1;
+            match (self.is_filled(low), self.is_filled(high)) {
+                (true, false) => Some((axis, 1)),
+                (false, true) => Some((axis, -1)),
+                _ => None,
+            }
+        })
+    }
+
+    // face of a filled voxel among `cells` that is closest to the point and has an empty neighbour,
+    // for points the face test misses
+    fn nearest_face<I>(&self, point: &Point3D, cells: I) -> Option<(usize, isize)>
+    where
+        I: Iterator<Item = [isize; 3]>,
+    {
+        let grid = self.grid_coordinates(point);
+        let mut nearest = None;
+        let mut nearest_distance = f64::INFINITY;
+        for cell in cells.filter(|cell| self.is_filled(*cell)) {
+            for (axis, side) in (0..3).flat_map(|axis| [(axis, -1), (axis, 1)]) {
+                let mut neighbour = cell;
+                neighbour[axis] += side;
+                if self.is_filled(neighbour) {
+                    continue;
+                }
+                // squared distance to the square of the face
+                let distance: f64 = (0..3)
+                    .map(|other| {
+                        let (low, high) = if other == axis {
+                            let plane = (cell[axis] + isize::from(side > 0)) as f64;
+                            (plane, plane)
+                        } else {
+                            (cell[other] as f64, (cell[other] + 1) as f64)
+                        };
+                        let gap = (low - grid[other]).max(grid[other] - high).max(0.0);
+                        gap * gap
+                    })
+                    .sum();
+                if distance < nearest_distance {
+                    nearest_distance = distance;
+                    nearest = Some((axis, side));
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl Object for VoxelGrid {
+    fn hits(&self, ray: Ray) -> Option<Point3D> {
+        let mut hit = None;
+        self.traverse(&ray, EPSILON, |filled, t| {
+            if filled {
+                hit = Some(t);
+            }
+            !filled
+        });
+        let t = hit?;
+        Some(ray.origin + ray.direction * t)
+    }
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let mut intervals = Vec::new();
+        let mut enter: Option<f64> = None;
+        self.traverse(&ray, f64::NEG_INFINITY, |filled, t| {
+            match (enter, filled) {
+                (None, true) => enter = Some(t),
+                (Some(start), false) => {
+                    intervals.push(Interval::new(start, t));
+                    enter = None;
+                }
+                _ => {}
+            }
+            true
+        });
+        if let (Some(start), Some((_, exit))) = (enter, self.bounds().hit_range(&ray)) {
+            intervals.push(Interval::new(start, exit));
+        }
+        intervals
+    }
+    // points off every face take the nearest one, looked for around them first and then in the whole grid
+    fn surface_normal(&self, hit_point: &Point3D) -> Vector3D {
+        let base = self.grid_coordinates(hit_point).map(|value| value.floor() as isize);
+        let around = (0..27).map(|index| [base[0] + index % 3 - 1, base[1] + index / 3 % 3 - 1, base[2] + index / 9 - 1]);
+        let [columns, rows, layers] = self.dimensions.map(|size| size as isize);
+        let everywhere = (0..columns * rows * layers).map(|index| [index % columns, index / columns % rows, index / (columns * rows)]);
+        let face = self
+            .face(hit_point)
+            .or_else(|| self.nearest_face(hit_point, around))
+            .or_else(|| self.nearest_face(hit_point, everywhere));
+        // only a grid without any filled voxel has no face at all
+        let Some((axis, side)) = face else {
+            return Vector3D::new(0.0, 1.0, 0.0);
+        };
+        let mut normal = [0.0; 3];
+        normal[axis] = side as f64;
+        Vector3D::new(normal[0], normal[1], normal[2])
+    }
+    fn get_center(&self) -> Point3D {
+        self.bounds().centroid()
+    }
+    fn bounds(&self) -> Aabb {
+        let extent = Vector3D::new(
+            self.dimensions[0] as f64,
+            self.dimensions[1] as f64,
+            self.dimensions[2] as f64,
+        ) * self.voxel_size;
+        Aabb::from_points(&[self.corner, self.corner + extent])
+    }
+    fn get_color(&self) -> Vector3D {
+        self.color
+    }
+    // palette color of the filled voxel behind the face
+    fn get_albedo(&self, hit_point: &Point3D, _light_dir: &Vector3D) -> Vector3D {
+        let inside = *hit_point - self.surface_normal(hit_point) * (0.5 * self.voxel_size);
+        let cell = self.grid_coordinates(&inside).map(|value| value.floor() as isize);
+        if !self.is_filled(cell) {
+            return self.color;
+        }
+        let index = self.cells[self.index(cell.map(|value| value as usize))];
+        self.palette.get(index as usize).copied().unwrap_or(self.color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vector3D = Vector3D { x: 255.0, y: 255.0, z: 255.0 };
+
+    fn chunk(id: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((content.len() as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(content);
+        bytes
+    }
+
+    // MagicaVoxel file of one model, voxels given as x, y, z (Z up) and color index
+    fn vox(size: [u32; 3], voxels: &[[u8; 4]], palette: Option<[u8; 4]>) -> Vec<u8> {
+        let mut children = chunk(b"SIZE", &size.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>());
+        let mut xyzi = (voxels.len() as u32).to_le_bytes().to_vec();
+        xyzi.extend(voxels.iter().flatten());
+        children.extend(chunk(b"XYZI", &xyzi));
+        if let Some(first) = palette {
+            let mut rgba = first.to_vec();
+            rgba.resize(256 * 4, 0);
+            children.extend(chunk(b"RGBA", &rgba));
+        }
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(150u32.to_le_bytes());
+        bytes.extend(b"MAIN");
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((children.len() as u32).to_le_bytes());
+        bytes.extend(children);
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Result<VoxelGrid, Box<dyn Error>> {
+        VoxelGrid::parse_vox(bytes, 0, Point3D::default(), 1.0, WHITE)
+    }
+
+    // two voxels side by side along x, on the ground
+    fn pair() -> VoxelGrid {
+        parse(&vox([2, 1, 1], &[[0, 0, 0, 1], [1, 0, 0, 1]], None)).unwrap()
+    }
+
+    #[test]
+    fn reads_vox_files_with_y_up() {
+        // a column of two voxels along the file's Z axis, red at the bottom and black on top
+        let grid = parse(&vox([1, 1, 2], &[[0, 0, 0, 1], [0, 0, 1, 2]], Some([255, 0, 0, 255]))).unwrap();
+        assert_eq!(grid.dimensions, [1, 2, 1]);
+        assert!(grid.is_filled([0, 0, 0]) && grid.is_filled([0, 1, 0]));
+        let top = grid.hits(Ray::new(Point3D::new(0.5, 5.0, 0.5), Vector3D::new(0.0, -1.0, 0.0))).unwrap();
+        assert!((top.y - 2.0).abs() < 1e-9);
+        let color = grid.get_albedo(&top, &Vector3D::new(0.0, 1.0, 0.0));
+        assert_eq!((color.x, color.y, color.z), (0.0, 0.0, 0.0));
+        let bottom = Point3D::new(0.5, 0.5, 1.0);
+        let color = grid.get_albedo(&bottom, &Vector3D::new(0.0, 0.0, 1.0));
+        assert_eq!((color.x, color.y, color.z), (255.0, 0.0, 0.0));
+        // without a palette every index takes the grid color
+        let plain = parse(&vox([1, 1, 1], &[[0, 0, 0, 7]], None)).unwrap();
+        assert_eq!(plain.get_albedo(&Point3D::new(0.5, 1.0, 0.5), &Vector3D::new(0.0, 1.0, 0.0)).y, 255.0);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(parse(b"NOPE").is_err());
+        assert!(parse(&vox([0, 4, 4], &[], None)).is_err());
+        assert!(parse(&vox([70000, 70000, 70000], &[], None)).is_err());
+        let mut truncated = vox([1, 1, 1], &[[0, 0, 0, 1]], None);
+        truncated.truncate(truncated.len() - 6);
+        assert!(parse(&truncated).is_err());
+        assert!(VoxelGrid::parse_vox(&vox([1, 1, 1], &[], None), 1, Point3D::default(), 1.0, WHITE).is_err());
+        assert!(cell_count([4, 4, 4]).is_ok());
+    }
+
+    #[test]
+    fn rays_walk_the_cells() {
+        let grid = pair();
+        let ray = Ray::new(Point3D::new(-1.0, 0.5, 0.5), Vector3D::new(1.0, 0.0, 0.0));
+        let hit = grid.hits(ray).unwrap();
+        assert!((hit.x - 0.0).abs() < 1e-9);
+        let normal = grid.surface_normal(&hit);
+        assert_eq!((normal.x, normal.y, normal.z), (-1.0, 0.0, 0.0));
+        let intervals = grid.intervals(ray);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].enter - 1.0).abs() < 1e-9 && (intervals[0].exit - 3.0).abs() < 1e-9);
+        assert!(grid.hits(Ray::new(Point3D::new(-1.0, 1.5, 0.5), Vector3D::new(1.0, 0.0, 0.0))).is_none());
+        // an empty grid never stops a ray
+        let empty = VoxelGrid::new(Point3D::default(), 1.0, [0, 0, 0], Vec::new(), Vec::new(), WHITE);
+        assert!(empty.hits(ray).is_none());
+    }
+
+    #[test]
+    fn stray_points_use_the_nearest_face() {
+        let grid = pair();
+        // just above the top, beyond the face tolerance
+        let normal = grid.surface_normal(&Point3D::new(1.2, 1.01, 0.5));
+        assert_eq!((normal.x, normal.y, normal.z), (0.0, 1.0, 0.0));
+        // off the right end, away from every cell around it
+        let normal = grid.surface_normal(&Point3D::new(5.0, 0.5, 0.5));
+        assert_eq!((normal.x, normal.y, normal.z), (1.0, 0.0, 0.0));
+        // inside the solid, the front face is closest
+        let normal = grid.surface_normal(&Point3D::new(0.5, 0.5, 0.95));
+        assert_eq!((normal.x, normal.y, normal.z), (0.0, 0.0, 1.0));
+    }
+}